@@ -0,0 +1,131 @@
+// Import the software renderer and everything it needs to draw the grid map
+use crate::draw_gl::{ FrameBuffer, VertexArrayWrapper };
+use crate::light::{ LightCache, PointLight };
+use crate::lightmap::{ resolve_path, Lighting, Lightmap };
+use crate::parallel;
+use crate::player::Player;
+use crate::render_gl::{ self, RayBuffers };
+use crate::segment::SegmentIndex;
+use crate::simd;
+use crate::sky::Sky;
+use crate::window_gl::{ self, RENDER_X, RENDER_Y };
+use std::f32::consts::PI;
+use std::path::Path;
+use std::time::{ Duration, Instant };
+
+// Frames rendered per benchmark run (the player turns a full circle over them)
+const BENCH_FRAMES: u32 = 240;
+
+// Time spent in each phase of a benchmark run, and a hash of every frame it drew
+struct BenchResult {
+    cast: Duration, // Ray casting (`cast_rays`)
+    fill: Duration, // Column fill (`draw_walls_to_pixels`)
+    hash: u64, // FNV-1a hash over all frames' pixels
+}
+
+// Code path a benchmark run renders with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CodePath {
+    Scalar, // Packed math switched off
+    Packed, // Packed ray setup and floor points
+}
+
+impl CodePath {
+    // Label printed in the results
+    fn name(self) -> &'static str {
+        match self {
+            CodePath::Scalar => "scalar",
+            CodePath::Packed => "packed",
+        }
+    }
+}
+
+// --- Time the Grid Renderer with and without Its Packed Math ---
+pub fn run(static_lights: &[PointLight]) {
+    let segment_index = SegmentIndex::new(&window_gl::SEGMENTS);
+    let lightmap = Lightmap::load_or_bake(
+        &resolve_path(window_gl::LIGHTMAP_PATH),
+        static_lights,
+        window_gl::MAP_AMBIENT_LIGHT,
+        &segment_index
+    );
+    let light_cache = LightCache::new([0.0; 3]);
+    let lighting = Lighting { baked: &lightmap, dynamic: &light_cache };
+    let sky = Sky::load_or_default(Path::new(window_gl::MAP_SKY_PATH));
+
+    println!(
+        "Rendering {} frames at {}x{} on {} thread(s)",
+        BENCH_FRAMES,
+        RENDER_X,
+        RENDER_Y,
+        parallel::thread_count()
+    );
+    let packed_enabled = simd::enabled();
+    let scalar = run_frames(CodePath::Scalar, &segment_index, &lighting, &sky);
+    let packed = run_frames(CodePath::Packed, &segment_index, &lighting, &sky);
+    simd::set_enabled(packed_enabled);
+
+    print_result(CodePath::Scalar, &scalar);
+    print_result(CodePath::Packed, &packed);
+    println!(
+        "{:>8}  speedup over scalar: cast {:.2}x, fill {:.2}x",
+        "",
+        scalar.cast.as_secs_f64() / packed.cast.as_secs_f64(),
+        scalar.fill.as_secs_f64() / packed.fill.as_secs_f64()
+    );
+    if packed.hash == scalar.hash {
+        println!("Frames are identical");
+    } else {
+        println!("Frames differ between the scalar and packed paths!");
+    }
+}
+
+// Print one run's time per frame
+fn print_result(path: CodePath, result: &BenchResult) {
+    println!(
+        "{:>8}: cast {:>8.3} ms/frame, fill {:>8.3} ms/frame",
+        path.name(),
+        per_frame_ms(result.cast),
+        per_frame_ms(result.fill)
+    );
+}
+
+// Render every benchmark frame with one code path
+fn run_frames(path: CodePath, segments: &SegmentIndex, lighting: &Lighting, sky: &Sky) -> BenchResult {
+    simd::set_enabled(path == CodePath::Packed);
+    let mut player = Player::new(200.0, 200.0);
+    let mut rays = RayBuffers::new();
+    let mut pixels = FrameBuffer::new([0u8; 3]);
+    let mut result = BenchResult { cast: Duration::ZERO, fill: Duration::ZERO, hash: 0xcbf29ce484222325 };
+
+    for frame in 0..BENCH_FRAMES {
+        player.update_dir(((frame as f32) / (BENCH_FRAMES as f32)) * 2.0 * PI);
+
+        let start = Instant::now();
+        let mut vertices = VertexArrayWrapper::new();
+        render_gl::cast_rays(&mut vertices, &player, segments, &mut rays, false);
+        result.cast += start.elapsed();
+
+        let start = Instant::now();
+        render_gl::draw_walls_to_pixels(
+            &mut pixels,
+            &player,
+            &rays,
+            &window_gl::WALL_COLORS,
+            &window_gl::MAP_FOG,
+            lighting,
+            sky
+        );
+        result.fill += start.elapsed();
+
+        for byte in pixels.iter().flatten().flatten() {
+            result.hash = (result.hash ^ (*byte as u64)).wrapping_mul(0x100000001b3);
+        }
+    }
+    result
+}
+
+// Average milliseconds per frame
+fn per_frame_ms(total: Duration) -> f64 {
+    (total.as_secs_f64() * 1000.0) / (BENCH_FRAMES as f64)
+}
@@ -0,0 +1,195 @@
+// Import OpenGL bindings, the window size and the debug view area
+extern crate gl;
+use crate::window_gl::Layout;
+
+// Zoom limits of the debug view (window pixels per world unit)
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 8.0;
+
+// How the camera moves on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    Free, // Stays where it was zoomed and panned to
+    Follow, // Keeps the player in the middle of the debug view
+}
+
+// 2D camera of the debug view. World point `p` lands on window pixel `p * zoom + offset`; the
+// default (zoom 1, no offset) draws one world unit per pixel from the window's top-left corner.
+pub struct Camera2D {
+    pub zoom: f32, // Window pixels per world unit
+    pub offset: [f32; 2], // Window pixel the world origin lands on
+    pub mode: CameraMode,
+}
+
+impl Camera2D {
+    // Constructor to create a camera showing the map at its original size and place
+    pub fn new() -> Camera2D {
+        Camera2D { zoom: 1.0, offset: [0.0, 0.0], mode: CameraMode::Free }
+    }
+
+    // Back to the original size and place
+    pub fn reset(&mut self) {
+        *self = Camera2D::new();
+    }
+
+    // Switch between following the player and staying put
+    pub fn toggle_follow(&mut self) {
+        self.mode = match self.mode {
+            CameraMode::Free => CameraMode::Follow,
+            CameraMode::Follow => CameraMode::Free,
+        };
+    }
+
+    // Whether a window pixel lies inside the debug view
+    pub fn contains(&self, layout: &Layout, window_x: i32, window_y: i32) -> bool {
+        let (x, y, width, height) = layout.debug_view;
+        (x..x + width).contains(&window_x) && (y..y + height).contains(&window_y)
+    }
+
+    // World point under a window pixel
+    pub fn to_world(&self, window_x: f32, window_y: f32) -> [f32; 2] {
+        [(window_x - self.offset[0]) / self.zoom, (window_y - self.offset[1]) / self.zoom]
+    }
+
+    // Zoom by `factor`, keeping the world point under the window pixel (usually the mouse) in place
+    pub fn zoom_at(&mut self, window_x: f32, window_y: f32, factor: f32) {
+        let [world_x, world_y] = self.to_world(window_x, window_y);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.offset = [window_x - world_x * self.zoom, window_y - world_y * self.zoom];
+    }
+
+    // Move the view by a number of window pixels (dragging takes the camera off the player)
+    pub fn pan_by(&mut self, dx: f32, dy: f32) {
+        self.offset[0] += dx;
+        self.offset[1] += dy;
+        self.mode = CameraMode::Free;
+    }
+
+    // In follow mode, center the debug view on a world point (the player's eye)
+    pub fn follow(&mut self, layout: &Layout, world_x: f32, world_y: f32) {
+        if self.mode == CameraMode::Follow {
+            let (x, y, width, height) = layout.debug_view;
+            let center_x = (x as f32) + (width as f32) / 2.0;
+            let center_y = (y as f32) + (height as f32) / 2.0;
+            self.offset = [center_x - world_x * self.zoom, center_y - world_y * self.zoom];
+        }
+    }
+
+    // Projection * view matrix from world units to normalized device coordinates, as mat3 columns
+    // for the `view` uniform of triangle.vert and minimap.vert
+    pub fn matrix(&self, layout: &Layout) -> [[f32; 3]; 3] {
+        // Window pixels to NDC, flipping y so the world's y-axis points down the screen
+        let half_width = (layout.width as f32) / 2.0;
+        let half_height = (layout.height as f32) / 2.0;
+        let scale_x = self.zoom / half_width;
+        let scale_y = -self.zoom / half_height;
+        let translate_x = (self.offset[0] - half_width) / half_width;
+        let translate_y = -(self.offset[1] - half_height) / half_height;
+        [
+            [scale_x, 0.0, 0.0],
+            [0.0, scale_y, 0.0],
+            [translate_x, translate_y, 1.0],
+        ]
+    }
+
+    // Clip drawing to the debug view, so a zoomed map doesn't spill over the rest of the window
+    pub fn begin_clip(&self, layout: &Layout) {
+        let (x, y, width, height) = layout.debug_view;
+        unsafe {
+            gl::Enable(gl::SCISSOR_TEST);
+            // Scissor boxes are measured from the bottom-left corner
+            gl::Scissor(x, layout.height - y - height, width, height);
+        }
+    }
+
+    // Stop clipping to the debug view
+    pub fn end_clip(&self) {
+        unsafe {
+            gl::Disable(gl::SCISSOR_TEST);
+        }
+    }
+}
+
+// Matrix that places window pixels as they are (for overlays drawn with triangle.vert)
+pub fn window_matrix(layout: &Layout) -> [[f32; 3]; 3] {
+    Camera2D::new().matrix(layout)
+}
+
+impl Default for Camera2D {
+    fn default() -> Self {
+        Camera2D::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Window sizes the tests run at: the default and two resized windows
+    fn layouts() -> [Layout; 3] {
+        [Layout::default(), Layout::new(800, 600), Layout::new(1920, 1080)]
+    }
+
+    // Window pixel a world point lands on, going through the matrix the shaders use
+    fn to_window(camera: &Camera2D, layout: &Layout, world: [f32; 2]) -> [f32; 2] {
+        let m = camera.matrix(layout);
+        let ndc_x = m[0][0] * world[0] + m[1][0] * world[1] + m[2][0];
+        let ndc_y = m[0][1] * world[0] + m[1][1] * world[1] + m[2][1];
+        [(ndc_x + 1.0) * (layout.width as f32) / 2.0, (1.0 - ndc_y) * (layout.height as f32) / 2.0]
+    }
+
+    fn assert_close(a: [f32; 2], b: [f32; 2]) {
+        assert!((a[0] - b[0]).abs() < 1e-3 && (a[1] - b[1]).abs() < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn world_to_window_and_back_round_trips() {
+        let points = [[0.0, 0.0], [37.5, 210.0], [448.0, 448.0], [-20.0, 600.0]];
+        for layout in &layouts() {
+            for &zoom in &[MIN_ZOOM, 0.6, 1.0, 2.5, MAX_ZOOM] {
+                for &offset in &[[0.0, 0.0], [120.0, -45.0], [-300.0, 260.5]] {
+                    let camera = Camera2D { zoom, offset, mode: CameraMode::Free };
+                    for &point in &points {
+                        let window = to_window(&camera, layout, point);
+                        assert_close([point[0] * zoom + offset[0], point[1] * zoom + offset[1]], window);
+                        assert_close(camera.to_world(window[0], window[1]), point);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn zooming_keeps_the_point_under_the_mouse() {
+        let layout = Layout::default();
+        let mut camera = Camera2D::new();
+        camera.pan_by(40.0, -15.0);
+        let mouse = [200.0, 150.0];
+        let under_mouse = camera.to_world(mouse[0], mouse[1]);
+        for &factor in &[1.25, 1.25, 0.5, 100.0, 0.001] {
+            camera.zoom_at(mouse[0], mouse[1], factor);
+            assert!((MIN_ZOOM..=MAX_ZOOM).contains(&camera.zoom));
+            assert_close(camera.to_world(mouse[0], mouse[1]), under_mouse);
+            assert_close(to_window(&camera, &layout, under_mouse), mouse);
+        }
+    }
+
+    #[test]
+    fn follow_centers_the_debug_view_on_the_player() {
+        for layout in &layouts() {
+            let (x, y, width, height) = layout.debug_view;
+            let center = [(x as f32) + (width as f32) / 2.0, (y as f32) + (height as f32) / 2.0];
+            let mut camera = Camera2D::new();
+            camera.zoom_at(0.0, 0.0, 3.0);
+            camera.follow(layout, 300.0, 120.0);
+            assert_eq!(camera.offset, [0.0, 0.0], "a free camera stays put");
+            camera.toggle_follow();
+            camera.follow(layout, 300.0, 120.0);
+            assert_close(to_window(&camera, layout, [300.0, 120.0]), center);
+            assert!(camera.contains(layout, center[0] as i32, center[1] as i32));
+            assert!(!camera.contains(layout, x + width, y));
+            camera.pan_by(5.0, 5.0);
+            assert_eq!(camera.mode, CameraMode::Free, "dragging stops following");
+        }
+    }
+}
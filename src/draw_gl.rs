@@ -2,105 +2,239 @@
 extern crate gl;
 
 use crate::window_gl::{ RENDER_X, RENDER_Y }; // internal render resolution
+use crate::palette::PALETTE_SIZE; // colors per palette
+use crate::postfx::ColorLut; // color grading table
+use crate::gl_check; // glGetError checks in debug builds
+use crate::render_gl; // program validation before drawing
+use crate::log::Level; // severity of logged events
+use crate::log_event; // structured logging
+use std::marker::PhantomData;
+use std::ops::{ Deref, DerefMut, Range };
 
-// Struct to manage OpenGL buffer and vertex array objects
-pub struct BufferArrayBinder {
+// One vertex attribute: where the shader reads it, how many floats it has and where it starts
+#[derive(Debug, Clone, Copy)]
+pub struct VertexAttribute {
+    pub location: gl::types::GLuint, // `layout (location = N)` in the vertex shader
+    pub components: gl::types::GLint, // Number of floats
+    pub offset: usize, // Byte offset inside the vertex
+}
+
+// A `#[repr(C)]` vertex struct made of f32 fields, with the attribute layout the shaders expect
+pub trait Vertex: Copy {
+    const ATTRIBUTES: &'static [VertexAttribute];
+    // Whether the attributes advance once per instance (instanced draws) instead of once per vertex
+    const PER_INSTANCE: bool = false;
+}
+
+// Vertex of the colored geometry (map, player, lines, rays): triangle.vert
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ColorVertex {
+    pub position: [f32; 3], // Normalized device coordinates
+    pub color: [f32; 3], // RGB (0.0 - 1.0)
+}
+
+impl ColorVertex {
+    // Constructor to create a new ColorVertex
+    pub fn new(position: [f32; 3], color: [f32; 3]) -> ColorVertex {
+        ColorVertex { position, color }
+    }
+}
+
+impl Vertex for ColorVertex {
+    const ATTRIBUTES: &'static [VertexAttribute] = &[
+        VertexAttribute { location: 0, components: 3, offset: std::mem::offset_of!(ColorVertex, position) },
+        VertexAttribute { location: 1, components: 3, offset: std::mem::offset_of!(ColorVertex, color) },
+    ];
+}
+
+// Vertex of the textured canvas quad: tex.vert
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TexturedVertex {
+    pub position: [f32; 3], // Normalized device coordinates
+    pub color: [f32; 3], // RGB tint (0.0 - 1.0)
+    pub texcoord: [f32; 2], // (0, 0) is the top-left of the frame
+}
+
+impl Vertex for TexturedVertex {
+    const ATTRIBUTES: &'static [VertexAttribute] = &[
+        VertexAttribute { location: 0, components: 3, offset: std::mem::offset_of!(TexturedVertex, position) },
+        VertexAttribute { location: 1, components: 3, offset: std::mem::offset_of!(TexturedVertex, color) },
+        VertexAttribute { location: 2, components: 2, offset: std::mem::offset_of!(TexturedVertex, texcoord) },
+    ];
+}
+
+// One minimap tile, drawn as an instance of the quad minimap.vert builds from `gl_VertexID`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TileInstance {
+    pub cell: [f32; 2], // Map column and row
+    pub color: [f32; 3], // RGB (0.0 - 1.0)
+}
+
+impl Vertex for TileInstance {
+    const ATTRIBUTES: &'static [VertexAttribute] = &[
+        VertexAttribute { location: 0, components: 2, offset: std::mem::offset_of!(TileInstance, cell) },
+        VertexAttribute { location: 1, components: 3, offset: std::mem::offset_of!(TileInstance, color) },
+    ];
+    const PER_INSTANCE: bool = true;
+}
+
+// Struct to manage OpenGL buffer and vertex array objects for one vertex type. Both are created
+// once and reused: the buffer only grows when the vertices no longer fit, otherwise it is orphaned
+// and refilled. The attribute layout comes from the vertex type and is set up once in the VAO.
+pub struct BufferArrayBinder<V: Vertex> {
     vao: gl::types::GLuint, // Vertex Array Object (VAO)
     vbo: gl::types::GLuint, // Vertex Buffer Object (VBO)
+    usage: gl::types::GLenum, // Usage hint: STATIC_DRAW, DYNAMIC_DRAW or STREAM_DRAW
+    capacity: usize, // Size of the buffer's storage in bytes
+    vertex: PhantomData<V>,
 }
 
-impl BufferArrayBinder {
-    // Constructor to create a new BufferArrayBinder
-    pub fn new(vao: gl::types::GLuint, vbo: gl::types::GLuint) -> BufferArrayBinder {
-        BufferArrayBinder { vao, vbo }
+impl<V: Vertex> BufferArrayBinder<V> {
+    // Constructor to create the VAO and an empty VBO with the given usage hint
+    pub fn new(usage: gl::types::GLenum) -> BufferArrayBinder<V> {
+        let mut vao: gl::types::GLuint = 0;
+        let mut vbo: gl::types::GLuint = 0;
+        let stride = std::mem::size_of::<V>() as gl::types::GLint;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenBuffers(1, &mut vbo);
+
+            // Attribute pointers are stored in the VAO and read from the bound buffer
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            for attribute in V::ATTRIBUTES {
+                gl::EnableVertexAttribArray(attribute.location);
+                gl_check!(
+                    gl::VertexAttribPointer(
+                        attribute.location, // Index of the vertex attribute
+                        attribute.components, // Number of components per vertex attribute
+                        gl::FLOAT, // Data type of each component
+                        gl::FALSE, // Normalize the data (false for floats)
+                        stride, // Byte offset between consecutive vertices
+                        attribute.offset as *const gl::types::GLvoid // Offset of the first component
+                    ),
+                    "setting up attribute {} of vertex array {}", attribute.location, vao
+                );
+                if V::PER_INSTANCE {
+                    gl::VertexAttribDivisor(attribute.location, 1);
+                }
+            }
+        }
+        BufferArrayBinder { vao, vbo, usage, capacity: 0, vertex: PhantomData }
     }
 
     // Set up the buffer with vertex data
-    pub fn set_buffers(&mut self, vertices: &Vec<f32>) {
+    pub fn set_buffers(&mut self, vertices: &[V]) {
+        let size = std::mem::size_of_val(vertices);
         unsafe {
-            // Generate a buffer for the VBO
-            gl::GenBuffers(1, &mut self.vbo);
-
             // Bind the buffer to the OpenGL context
             gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
 
-            // Upload the vertex data to the buffer
-            gl::BufferData(
-                gl::ARRAY_BUFFER, // Target buffer type
-                (vertices.len() * std::mem::size_of::<f32>()) as gl::types::GLsizeiptr, // Size of the data in bytes
-                vertices.as_ptr() as *const gl::types::GLvoid, // Pointer to the vertex data
-                gl::STATIC_DRAW // Usage hint (data will not change often)
-            );
-
-            // Generate a Vertex Array Object (VAO)
-            gl::GenVertexArrays(1, &mut self.vao);
+            if size > self.capacity {
+                // Grow the storage (doubling, so a slowly growing frame doesn't reallocate every time)
+                self.capacity = size.max(self.capacity * 2);
+                gl_check!(
+                    gl::BufferData(
+                        gl::ARRAY_BUFFER, // Target buffer type
+                        self.capacity as gl::types::GLsizeiptr, // Size of the storage in bytes
+                        std::ptr::null(), // No data yet, filled below
+                        self.usage // Usage hint
+                    ),
+                    "allocating {} bytes for vertex buffer {}", self.capacity, self.vbo
+                );
+            } else {
+                // Orphan the old storage so the driver doesn't wait for draws still reading it
+                gl_check!(
+                    gl::BufferData(gl::ARRAY_BUFFER, self.capacity as gl::types::GLsizeiptr, std::ptr::null(), self.usage),
+                    "orphaning vertex buffer {}", self.vbo
+                );
+            }
 
-            // Bind the VAO to the buffer
-            gl::BindVertexArray(self.vao);
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            // Upload the vertex data to the start of the buffer
+            gl_check!(
+                gl::BufferSubData(
+                    gl::ARRAY_BUFFER,
+                    0, // Byte offset
+                    size as gl::types::GLsizeiptr, // Size of the data in bytes
+                    vertices.as_ptr() as *const gl::types::GLvoid // Pointer to the vertex data
+                ),
+                "uploading {} bytes to vertex buffer {}", size, self.vbo
+            );
         }
     }
 
-    // Configure vertex attributes for the shader
-    pub fn set_vertex_attribs(&mut self, vertex_size: usize, stride: usize, frag_size: usize) {
+    // Draw a range of vertices (indices into the last `set_buffers` slice)
+    pub fn draw_arrays(&self, mode: gl::types::GLenum, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
         unsafe {
-            // Enable the vertex attribute for position (layout location 0 in the shader)
-            gl::EnableVertexAttribArray(0);
-            gl::VertexAttribPointer(
-                0, // Index of the vertex attribute
-                vertex_size as i32, // Number of components per vertex attribute
-                gl::FLOAT, // Data type of each component
-                gl::FALSE, // Normalize the data (false for floats)
-                (stride * std::mem::size_of::<f32>()) as gl::types::GLint, // Stride (byte offset between consecutive attributes)
-                std::ptr::null() // Offset of the first component
-            );
+            // Bind the VAO
+            gl::BindVertexArray(self.vao);
+            render_gl::validate_current_program();
 
-            // Enable the vertex attribute for color (layout location 1 in the shader)
-            gl::EnableVertexAttribArray(1);
-            gl::VertexAttribPointer(
-                1, // Index of the vertex attribute
-                frag_size as i32, // Number of components per vertex attribute
-                gl::FLOAT, // Data type of each component
-                gl::FALSE, // Normalize the data (false for floats)
-                (stride * std::mem::size_of::<f32>()) as gl::types::GLint, // Stride (byte offset between consecutive attributes)
-                (vertex_size * std::mem::size_of::<f32>()) as *const gl::types::GLvoid // Offset of the first component
+            // Draw the arrays
+            gl_check!(
+                gl::DrawArrays(
+                    mode, // Drawing mode (e.g., GL_TRIANGLES, GL_LINES)
+                    range.start as gl::types::GLint, // First vertex
+                    range.len() as gl::types::GLsizei // Number of vertices to render
+                ),
+                "drawing vertices {:?} from vertex array {}", range, self.vao
             );
         }
     }
 
-    // Draw the vertex data as arrays
-    pub fn draw_arrays(&self, mode: gl::types::GLenum, num_of_indicies: i32, start: i32, end: i32) {
+    // Draw `instances` copies (the first ones in the buffer) of a `vertex_count` vertex primitive
+    // whose vertices the shader builds itself
+    pub fn draw_instanced(&self, mode: gl::types::GLenum, vertex_count: usize, instances: usize) {
+        if vertex_count == 0 || instances == 0 {
+            return;
+        }
         unsafe {
-            // Bind the VAO
             gl::BindVertexArray(self.vao);
-
-            // Draw the arrays
-            gl::DrawArrays(
-                mode, // Drawing mode (e.g., GL_TRIANGLES, GL_LINES)
-                start / num_of_indicies, // Starting index in the enabled arrays
-                ((end - start) / num_of_indicies) as i32 // Number of indices to render
+            render_gl::validate_current_program();
+            gl_check!(
+                gl::DrawArraysInstanced(mode, 0, vertex_count as gl::types::GLsizei, instances as gl::types::GLsizei),
+                "drawing {} instances from vertex array {}", instances, self.vao
             );
         }
-        //print!("Drawing from {} to {}.\n", start, end); // Print the range of indices being drawn
+    }
+}
+
+// Automatically deletes the VAO and VBO when the binder goes out of scope
+impl<V: Vertex> Drop for BufferArrayBinder<V> {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.vbo);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
     }
 }
 
 // --- Vertex Array Wrapper Struct and Methods ---
+// Colored vertices for one frame: triangles first, then lines, then one triangle fan
 pub struct VertexArrayWrapper {
-    points: Vec<f32>,
-    triangle_end: usize,
-    line_end: usize,
+    vertices: Vec<ColorVertex>,
+    triangle_end: usize, // Vertex index where the triangles end and the lines begin
+    line_end: usize, // Vertex index where the lines end and the fan begins
+    fan_end: usize, // Vertex index where the fan ends
 }
 
 impl VertexArrayWrapper {
     pub fn new() -> VertexArrayWrapper {
-        let points = Vec::new();
+        let vertices = Vec::new();
         let triangle_end = 0;
         let line_end = 0;
+        let fan_end = 0;
         VertexArrayWrapper {
-            points,
+            vertices,
             triangle_end,
             line_end,
+            fan_end,
         }
     }
     pub fn set_triangle_end(&mut self, end: usize) {
@@ -109,20 +243,26 @@ impl VertexArrayWrapper {
     pub fn set_line_end(&mut self, end: usize) {
         self.line_end = end;
     }
+    pub fn set_fan_end(&mut self, end: usize) {
+        self.fan_end = end;
+    }
     pub fn len(&self) -> usize {
-        self.points.len()
+        self.vertices.len()
+    }
+    pub fn push(&mut self, vertex: ColorVertex) {
+        self.vertices.push(vertex);
     }
-    pub fn push(&mut self, num: f32) {
-        self.points.push(num);
+    pub fn vertices(&self) -> &[ColorVertex] {
+        &self.vertices
     }
-    pub fn points(&self) -> &Vec<f32> {
-        &self.points
+    pub fn triangles(&self) -> Range<usize> {
+        0..self.triangle_end
     }
-    pub fn triangle_end(&self) -> usize {
-        self.triangle_end
+    pub fn lines(&self) -> Range<usize> {
+        self.triangle_end..self.line_end
     }
-    pub fn line_end(&self) -> usize {
-        self.line_end
+    pub fn fan(&self) -> Range<usize> {
+        self.line_end..self.fan_end
     }
 }
 
@@ -170,18 +310,8 @@ impl TextureManager {
         TextureManager { id }
     }
 
-    // Load a texture from a file
-    pub fn load_texture(
-        &self,
-        pixels: [[[u8; 3]; RENDER_X as usize]; RENDER_Y as usize]
-    ) -> Result<(), String> {
-        let mut flat_pixels = Vec::with_capacity((RENDER_X * RENDER_Y * 3) as usize);
-        for row in pixels {
-            for pixel in row {
-                flat_pixels.extend_from_slice(&pixel);
-            }
-        }
-
+    // Upload a palette as a 256 x 1 RGB texture
+    pub fn load_palette(&self, colors: &[[u8; 3]; PALETTE_SIZE]) -> Result<(), String> {
         unsafe {
             gl::BindTexture(gl::TEXTURE_2D, self.id);
 
@@ -191,19 +321,525 @@ impl TextureManager {
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
 
-            // Upload the pixel data
-            gl::TexImage2D(
-                gl::TEXTURE_2D,
-                0,
-                gl::RGB as i32,
-                RENDER_X,
-                RENDER_Y,
-                0,
-                gl::RGB,
-                gl::UNSIGNED_BYTE,
-                flat_pixels.as_ptr() as *const _
+            // Upload the colors
+            gl_check!(
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::RGB as i32,
+                    PALETTE_SIZE as i32,
+                    1,
+                    0,
+                    gl::RGB,
+                    gl::UNSIGNED_BYTE,
+                    colors.as_ptr() as *const _
+                ),
+                "uploading a palette to texture {}", self.id
             );
         }
         Ok(())
     }
+    // Upload a color grading LUT as a 3D RGB texture (linearly filtered between entries)
+    pub fn load_lut(&self, lut: &ColorLut) -> Result<(), String> {
+        let size = lut.size() as i32;
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_3D, self.id);
+
+            // Set texture parameters
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+
+            // Upload the entries (red along x, green along y, blue along z)
+            gl_check!(
+                gl::TexImage3D(
+                    gl::TEXTURE_3D,
+                    0,
+                    gl::RGB16F as i32,
+                    size,
+                    size,
+                    size,
+                    0,
+                    gl::RGB,
+                    gl::FLOAT,
+                    lut.data().as_ptr() as *const _
+                ),
+                "uploading a {}^3 color LUT to texture {}", size, self.id
+            );
+        }
+        Ok(())
+    }
+
+    // Upload two bytes per texel as unsigned integers, read with `texelFetch` on a `usampler2D`
+    pub fn load_tile_texture(&self, width: i32, height: i32, texels: &[[u8; 2]]) -> Result<(), String> {
+        if texels.len() != (width * height) as usize {
+            return Err(format!("expected {} tile texels, got {}", width * height, texels.len()));
+        }
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            set_nearest_parameters();
+            gl_check!(
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::RG8UI as i32,
+                    width,
+                    height,
+                    0,
+                    gl::RG_INTEGER,
+                    gl::UNSIGNED_BYTE,
+                    texels.as_ptr() as *const _
+                ),
+                "uploading {}x{} tile texels to texture {}", width, height, self.id
+            );
+        }
+        Ok(())
+    }
+
+    // Upload RGB texels as floats (values may go above 1.0)
+    pub fn load_float_texture(&self, width: i32, height: i32, texels: &[[f32; 3]]) -> Result<(), String> {
+        if texels.len() != (width * height) as usize {
+            return Err(format!("expected {} float texels, got {}", width * height, texels.len()));
+        }
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            set_nearest_parameters();
+            gl_check!(
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::RGB32F as i32,
+                    width,
+                    height,
+                    0,
+                    gl::RGB,
+                    gl::FLOAT,
+                    texels.as_ptr() as *const _
+                ),
+                "uploading {}x{} float texels to texture {}", width, height, self.id
+            );
+        }
+        Ok(())
+    }
+
+    // Replace the texels of a float texture already allocated by `load_float_texture` at this size
+    pub fn update_float_texture(&self, width: i32, height: i32, texels: &[[f32; 3]]) -> Result<(), String> {
+        if texels.len() != (width * height) as usize {
+            return Err(format!("expected {} float texels, got {}", width * height, texels.len()));
+        }
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl_check!(
+                gl::TexSubImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    0,
+                    0,
+                    width,
+                    height,
+                    gl::RGB,
+                    gl::FLOAT,
+                    texels.as_ptr() as *const _
+                ),
+                "updating {}x{} float texels in texture {}", width, height, self.id
+            );
+        }
+        Ok(())
+    }
+
+    // Upload four floats per texel (e.g. line segment endpoints), read with `texelFetch`
+    pub fn load_vec4_texture(&self, width: i32, height: i32, texels: &[[f32; 4]]) -> Result<(), String> {
+        if texels.len() != (width * height) as usize {
+            return Err(format!("expected {} vec4 texels, got {}", width * height, texels.len()));
+        }
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            set_nearest_parameters();
+            gl_check!(
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::RGBA32F as i32,
+                    width,
+                    height,
+                    0,
+                    gl::RGBA,
+                    gl::FLOAT,
+                    texels.as_ptr() as *const _
+                ),
+                "uploading {}x{} vec4 texels to texture {}", width, height, self.id
+            );
+        }
+        Ok(())
+    }
+
+    // Upload one byte per texel as an unsigned integer (e.g. colormap tables), read with
+    // `texelFetch` on a `usampler2D`
+    pub fn load_byte_texture(&self, width: i32, height: i32, texels: &[u8]) -> Result<(), String> {
+        if texels.len() != (width * height) as usize {
+            return Err(format!("expected {} byte texels, got {}", width * height, texels.len()));
+        }
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            set_nearest_parameters();
+            gl_check!(
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::R8UI as i32,
+                    width,
+                    height,
+                    0,
+                    gl::RED_INTEGER,
+                    gl::UNSIGNED_BYTE,
+                    texels.as_ptr() as *const _
+                ),
+                "uploading {}x{} byte texels to texture {}", width, height, self.id
+            );
+        }
+        Ok(())
+    }
+
+    // Upload one byte per texel as the red channel (e.g. glyph coverage)
+    pub fn load_coverage_texture(&self, width: i32, height: i32, texels: &[u8]) -> Result<(), String> {
+        if texels.len() != (width * height) as usize {
+            return Err(format!("expected {} coverage texels, got {}", width * height, texels.len()));
+        }
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            set_nearest_parameters();
+            gl_check!(
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::R8 as i32,
+                    width,
+                    height,
+                    0,
+                    gl::RED,
+                    gl::UNSIGNED_BYTE,
+                    texels.as_ptr() as *const _
+                ),
+                "uploading {}x{} coverage texels to texture {}", width, height, self.id
+            );
+        }
+        Ok(())
+    }
+
+    // Upload RGB texels of any size
+    pub fn load_rgb_texture(&self, width: i32, height: i32, texels: &[[u8; 3]]) -> Result<(), String> {
+        if texels.len() != (width * height) as usize {
+            return Err(format!("expected {} RGB texels, got {}", width * height, texels.len()));
+        }
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            set_nearest_parameters();
+            gl_check!(
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::RGB as i32,
+                    width,
+                    height,
+                    0,
+                    gl::RGB,
+                    gl::UNSIGNED_BYTE,
+                    texels.as_ptr() as *const _
+                ),
+                "uploading {}x{} RGB texels to texture {}", width, height, self.id
+            );
+        }
+        Ok(())
+    }
+}
+
+// Nearest filtering and edge clamping for the currently bound 2D texture
+unsafe fn set_nearest_parameters() {
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+}
+
+// Automatically deletes the texture when it goes out of scope
+impl Drop for TextureManager {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+// A pixel type the canvas texture can be streamed from, with the matching OpenGL formats
+pub trait TexelFormat: Copy {
+    const INTERNAL_FORMAT: gl::types::GLenum; // Sized storage format of the texture
+    const FORMAT: gl::types::GLenum; // Layout of the uploaded data
+}
+
+// 24-bit RGB pixels
+impl TexelFormat for [u8; 3] {
+    const INTERNAL_FORMAT: gl::types::GLenum = gl::RGB8;
+    const FORMAT: gl::types::GLenum = gl::RGB;
+}
+
+// 8-bit palette indices (expanded to RGB by tex.frag)
+impl TexelFormat for u8 {
+    const INTERNAL_FORMAT: gl::types::GLenum = gl::R8;
+    const FORMAT: gl::types::GLenum = gl::RED;
+}
+
+// Rows of pixels at the internal render resolution, top row first
+pub type FrameRows<P> = [[P; RENDER_X as usize]; RENDER_Y as usize];
+
+// A frame the software renderers draw into: one contiguous heap block that is uploaded as-is
+pub struct FrameBuffer<P> {
+    rows: Box<FrameRows<P>>,
+}
+
+impl<P: TexelFormat> FrameBuffer<P> {
+    // Constructor to create a frame filled with one pixel value
+    pub fn new(fill: P) -> FrameBuffer<P> {
+        FrameBuffer { rows: Box::new([[fill; RENDER_X as usize]; RENDER_Y as usize]) }
+    }
+
+    // Get the whole frame as bytes, row after row with no padding
+    pub fn as_bytes(&self) -> &[u8] {
+        let len = std::mem::size_of::<FrameRows<P>>();
+        unsafe { std::slice::from_raw_parts(self.rows.as_ptr() as *const u8, len) }
+    }
+
+    // Get the whole frame as writable bytes, e.g. to read a frame back from the GPU
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        let len = std::mem::size_of::<FrameRows<P>>();
+        unsafe { std::slice::from_raw_parts_mut(self.rows.as_mut_ptr() as *mut u8, len) }
+    }
+}
+
+impl<P> Deref for FrameBuffer<P> {
+    type Target = FrameRows<P>;
+    fn deref(&self) -> &FrameRows<P> {
+        &self.rows
+    }
+}
+
+impl<P> DerefMut for FrameBuffer<P> {
+    fn deref_mut(&mut self) -> &mut FrameRows<P> {
+        &mut self.rows
+    }
+}
+
+// Canvas texture with storage allocated once at the render resolution; every frame only replaces
+// its contents, optionally through two pixel buffer objects so the copy to the GPU doesn't stall
+pub struct StreamTexture<P> {
+    texture: TextureManager, // Texture the canvas samples
+    pbos: Option<[gl::types::GLuint; 2]>, // Pixel buffer objects, used in turn
+    next_pbo: usize, // Pixel buffer object the next frame is written to
+    format: PhantomData<P>,
+}
+
+impl<P: TexelFormat> StreamTexture<P> {
+    // Constructor to allocate the texture storage (and the pixel buffer objects if requested)
+    pub fn new(use_pbos: bool) -> StreamTexture<P> {
+        let texture = TextureManager::new();
+        let size = std::mem::size_of::<FrameRows<P>>();
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, texture.id);
+            set_nearest_parameters();
+            if gl::TexStorage2D::is_loaded() {
+                // Immutable storage (core in 4.2, commonly available through ARB_texture_storage)
+                gl_check!(
+                    gl::TexStorage2D(gl::TEXTURE_2D, 1, P::INTERNAL_FORMAT, RENDER_X, RENDER_Y),
+                    "allocating storage for frame texture {}", texture.id
+                );
+            } else {
+                gl_check!(
+                    gl::TexImage2D(
+                        gl::TEXTURE_2D,
+                        0,
+                        P::INTERNAL_FORMAT as i32,
+                        RENDER_X,
+                        RENDER_Y,
+                        0,
+                        P::FORMAT,
+                        gl::UNSIGNED_BYTE,
+                        std::ptr::null()
+                    ),
+                    "allocating storage for frame texture {}", texture.id
+                );
+            }
+        }
+
+        let pbos = if use_pbos {
+            let mut ids: [gl::types::GLuint; 2] = [0; 2];
+            unsafe {
+                gl::GenBuffers(2, ids.as_mut_ptr());
+                for &id in &ids {
+                    gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, id);
+                    gl_check!(
+                        gl::BufferData(gl::PIXEL_UNPACK_BUFFER, size as gl::types::GLsizeiptr, std::ptr::null(), gl::STREAM_DRAW),
+                        "allocating pixel buffer {}", id
+                    );
+                }
+                gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+            }
+            Some(ids)
+        } else {
+            None
+        };
+
+        StreamTexture { texture, pbos, next_pbo: 0, format: PhantomData }
+    }
+
+    // Get the OpenGL ID of the texture
+    pub fn id(&self) -> gl::types::GLuint {
+        self.texture.id
+    }
+
+    // Replace the texture's contents with a frame
+    pub fn upload(&mut self, frame: &FrameBuffer<P>) {
+        let bytes = frame.as_bytes();
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.texture.id);
+
+            let data = match self.pbos {
+                Some(pbos) => {
+                    // Fill one buffer while the driver may still be copying out of the other
+                    let pbo = pbos[self.next_pbo];
+                    self.next_pbo = 1 - self.next_pbo;
+                    gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, pbo);
+                    let mapped = gl_check!(
+                        gl::MapBufferRange(
+                            gl::PIXEL_UNPACK_BUFFER,
+                            0,
+                            bytes.len() as gl::types::GLsizeiptr,
+                            gl::MAP_WRITE_BIT | gl::MAP_INVALIDATE_BUFFER_BIT
+                        ),
+                        "mapping pixel buffer {}", pbo
+                    );
+                    let copied = !mapped.is_null() && {
+                        std::ptr::copy_nonoverlapping(bytes.as_ptr(), mapped as *mut u8, bytes.len());
+                        // False if the buffer's contents were lost while it was mapped
+                        gl::UnmapBuffer(gl::PIXEL_UNPACK_BUFFER) == gl::TRUE
+                    };
+                    if copied {
+                        // With a buffer bound, the data pointer is an offset into it
+                        std::ptr::null()
+                    } else {
+                        // Upload straight from the frame instead of from a buffer with stale contents
+                        log_event!(Level::Warn, "gl", "could not fill pixel buffer, uploading directly"; pbo = pbo);
+                        gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+                        bytes.as_ptr() as *const gl::types::GLvoid
+                    }
+                }
+                None => bytes.as_ptr() as *const gl::types::GLvoid,
+            };
+
+            gl_check!(
+                gl::TexSubImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    0,
+                    0,
+                    RENDER_X,
+                    RENDER_Y,
+                    P::FORMAT,
+                    gl::UNSIGNED_BYTE,
+                    data
+                ),
+                "streaming a frame into texture {}", self.texture.id
+            );
+
+            if self.pbos.is_some() {
+                gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+            }
+        }
+    }
+}
+
+// Automatically deletes the pixel buffer objects (the texture deletes itself)
+impl<P> Drop for StreamTexture<P> {
+    fn drop(&mut self) {
+        if let Some(pbos) = self.pbos {
+            unsafe {
+                gl::DeleteBuffers(2, pbos.as_ptr());
+            }
+        }
+    }
+}
+
+// Framebuffer object drawing into a canvas texture, so a shader can render the frame on the GPU
+// in place of a software upload (and the frame can be read back for CPU post-processing)
+pub struct RenderTarget<P> {
+    id: gl::types::GLuint, // OpenGL ID of the framebuffer
+    format: PhantomData<P>,
+}
+
+impl<P: TexelFormat> RenderTarget<P> {
+    // Constructor to attach a canvas texture as the framebuffer's color buffer
+    pub fn new(texture: &StreamTexture<P>) -> Result<RenderTarget<P>, String> {
+        let mut id: gl::types::GLuint = 0;
+        unsafe {
+            gl::GenFramebuffers(1, &mut id);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, id);
+            gl_check!(
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture.id(), 0),
+                "attaching texture {} to framebuffer {}", texture.id(), id
+            );
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                gl::DeleteFramebuffers(1, &id);
+                return Err(format!("framebuffer for texture {} is incomplete (status 0x{:x})", texture.id(), status));
+            }
+        }
+        Ok(RenderTarget { id, format: PhantomData })
+    }
+
+    // Run `draw` with the texture as the render target and the viewport covering it, then go back
+    // to the window and its viewport
+    pub fn draw_into<F: FnOnce()>(&self, draw: F) {
+        let mut viewport = [0i32; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
+            gl::Viewport(0, 0, RENDER_X, RENDER_Y);
+        }
+        draw();
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        }
+    }
+
+    // Copy the texture's contents back into a frame
+    pub fn read(&self, frame: &mut FrameBuffer<P>) {
+        let bytes = frame.as_bytes_mut();
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.id);
+            gl_check!(
+                gl::ReadPixels(
+                    0,
+                    0,
+                    RENDER_X,
+                    RENDER_Y,
+                    P::FORMAT,
+                    gl::UNSIGNED_BYTE,
+                    bytes.as_mut_ptr() as *mut gl::types::GLvoid
+                ),
+                "reading a frame back from framebuffer {}", self.id
+            );
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+    }
+}
+
+// Automatically deletes the framebuffer (the texture belongs to its `StreamTexture`)
+impl<P> Drop for RenderTarget<P> {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.id);
+        }
+    }
 }
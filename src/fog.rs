@@ -0,0 +1,116 @@
+// Import the light shading helper and the renderers' shading interface
+use crate::light::shade;
+use crate::render_gl::Shading;
+
+// How fog thickens with distance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FogMode {
+    Off, // No fog
+    Linear, // Ramps evenly from `start` to `end`
+    Exponential, // Rises quickly after `start`, ~95% at `end`
+}
+
+// Distance fog settings for the software renderers
+#[derive(Debug, Clone, Copy)]
+pub struct Fog {
+    pub mode: FogMode, // Falloff curve
+    pub color: [u8; 3], // Color surfaces fade towards
+    pub start: f32, // Distance (world units) where fog begins
+    pub end: f32, // Distance (world units) where fog is fully opaque (linear) or ~95% (exponential)
+}
+
+impl Fog {
+    // Fog settings that leave every color untouched
+    pub const OFF: Fog = Fog {
+        mode: FogMode::Off,
+        color: [0, 0, 0],
+        start: 0.0,
+        end: 1.0,
+    };
+
+    // Amount of fog (0.0 = none, 1.0 = only fog color) at a given distance
+    pub fn factor(&self, dist: f32) -> f32 {
+        let range = (self.end - self.start).max(0.0001);
+        let depth = (dist - self.start).max(0.0);
+        match self.mode {
+            FogMode::Off => 0.0,
+            FogMode::Linear => (depth / range).min(1.0),
+            FogMode::Exponential => 1.0 - (-3.0 * (depth / range)).exp(),
+        }
+    }
+
+    // Blend a color towards the fog color according to its distance
+    pub fn apply(&self, color: [u8; 3], dist: f32) -> [u8; 3] {
+        let amount = self.factor(dist);
+        if amount <= 0.0 {
+            return color;
+        }
+        let mut fogged = color;
+        for (channel, &target) in fogged.iter_mut().zip(self.color.iter()) {
+            let from = *channel as f32;
+            *channel = (from + ((target as f32) - from) * amount).round() as u8;
+        }
+        fogged
+    }
+}
+
+// Plain RGB rendering: scale by the light, then blend towards the fog color
+impl Shading for Fog {
+    type Pixel = [u8; 3];
+
+    fn surface(&self, color: [u8; 3], light: [f32; 3], dist: f32) -> [u8; 3] {
+        self.apply(shade(color, light), dist)
+    }
+
+    fn unlit(&self, color: [u8; 3]) -> [u8; 3] {
+        color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fog(mode: FogMode, start: f32, end: f32) -> Fog {
+        Fog { mode, color: [20, 20, 35], start, end }
+    }
+
+    #[test]
+    fn linear_fog_ramps_from_start_to_end() {
+        let fog = fog(FogMode::Linear, 64.0, 640.0);
+        assert_eq!(fog.factor(0.0), 0.0);
+        assert_eq!(fog.factor(64.0), 0.0);
+        assert!((fog.factor(352.0) - 0.5).abs() < 1e-6);
+        assert_eq!(fog.factor(640.0), 1.0);
+        assert_eq!(fog.factor(10_000.0), 1.0);
+    }
+
+    #[test]
+    fn exponential_fog_reaches_95_percent_at_end() {
+        let fog = fog(FogMode::Exponential, 64.0, 640.0);
+        assert_eq!(fog.factor(64.0), 0.0);
+        assert!((fog.factor(640.0) - 0.95).abs() < 0.001);
+        assert!(fog.factor(10_000.0) > 0.999);
+        assert!(fog.factor(352.0) > 0.5); // Rises faster than linear fog
+    }
+
+    #[test]
+    fn end_at_or_before_start_fogs_everything_past_start() {
+        for &end in &[64.0, 10.0] {
+            let linear = fog(FogMode::Linear, 64.0, end);
+            assert_eq!(linear.factor(64.0), 0.0);
+            assert_eq!(linear.factor(65.0), 1.0);
+            let exponential = fog(FogMode::Exponential, 64.0, end);
+            assert_eq!(exponential.factor(64.0), 0.0);
+            assert!(exponential.factor(65.0) > 0.999);
+            assert!(linear.factor(65.0).is_finite() && exponential.factor(65.0).is_finite());
+        }
+    }
+
+    #[test]
+    fn off_and_near_fog_leave_colors_untouched() {
+        assert_eq!(Fog::OFF.apply([200, 100, 50], 10_000.0), [200, 100, 50]);
+        assert_eq!(fog(FogMode::Linear, 64.0, 640.0).apply([200, 100, 50], 32.0), [200, 100, 50]);
+        assert_eq!(fog(FogMode::Linear, 64.0, 640.0).apply([200, 100, 50], 640.0), [20, 20, 35]);
+    }
+}
@@ -0,0 +1,123 @@
+// Import OpenGL bindings and the logging utilities
+extern crate gl;
+use crate::log::Level;
+use crate::log_event;
+use std::ffi::CStr;
+
+// Run a GL call and, in debug builds, check `glGetError` right after it. The context arguments are
+// formatted like `format!` and only evaluated when an error was raised, e.g.
+// `gl_check!(gl::BufferSubData(...), "uploading {} bytes to vertex buffer {}", size, vbo)`.
+// Errors are only logged: the macro evaluates to the call's own return value, so callers that must
+// react to an error call `check_errors` themselves.
+#[macro_export]
+macro_rules! gl_check {
+    ($call:expr, $($context:tt)+) => {{
+        let result = $call;
+        if cfg!(debug_assertions) {
+            // Already logged; see above
+            let _ = $crate::gl_debug::check_errors(file!(), line!(), || format!($($context)+));
+        }
+        result
+    }};
+}
+
+// Drain the GL error queue, logging each error with what was being done and where. Returns the
+// first error as a message, or Ok if there was none.
+pub fn check_errors<F>(file: &str, line: u32, context: F) -> Result<(), String> where F: FnOnce() -> String {
+    let mut first = None;
+    let mut context = Some(context);
+    loop {
+        let code = unsafe { gl::GetError() };
+        if code == gl::NO_ERROR {
+            break;
+        }
+        // Build the context once, for the first error
+        let what = context.take().map_or_else(String::new, |context| context());
+        let message = format!("{} while {} ({}:{})", error_name(code), what, file, line);
+        log_event!(Level::Error, "gl", message);
+        first.get_or_insert(message);
+    }
+    match first {
+        Some(message) => Err(message),
+        None => Ok(()),
+    }
+}
+
+// Readable name of a `glGetError` code
+pub fn error_name(code: gl::types::GLenum) -> &'static str {
+    match code {
+        gl::INVALID_ENUM => "GL_INVALID_ENUM",
+        gl::INVALID_VALUE => "GL_INVALID_VALUE",
+        gl::INVALID_OPERATION => "GL_INVALID_OPERATION",
+        gl::INVALID_FRAMEBUFFER_OPERATION => "GL_INVALID_FRAMEBUFFER_OPERATION",
+        gl::OUT_OF_MEMORY => "GL_OUT_OF_MEMORY",
+        gl::STACK_UNDERFLOW => "GL_STACK_UNDERFLOW",
+        gl::STACK_OVERFLOW => "GL_STACK_OVERFLOW",
+        _ => "unknown GL error",
+    }
+}
+
+// Route driver messages into the log through KHR_debug (core in 4.3, an extension before that).
+// Returns false if the driver doesn't offer debug output.
+pub fn enable_debug_output() -> bool {
+    if !gl::DebugMessageCallback::is_loaded() {
+        log_event!(Level::Info, "gl", "KHR_debug is not available, relying on glGetError checks");
+        return false;
+    }
+    unsafe {
+        gl::Enable(gl::DEBUG_OUTPUT);
+        // Deliver messages during the call that caused them, so they line up with the log
+        gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+        gl::DebugMessageCallback(Some(debug_callback), std::ptr::null());
+        // Skip the chatty notifications (buffer placement hints and the like)
+        gl::DebugMessageControl(
+            gl::DONT_CARE,
+            gl::DONT_CARE,
+            gl::DEBUG_SEVERITY_NOTIFICATION,
+            0,
+            std::ptr::null(),
+            gl::FALSE
+        );
+    }
+    true
+}
+
+// Called by the driver for every debug message
+extern "system" fn debug_callback(
+    source: gl::types::GLenum,
+    kind: gl::types::GLenum,
+    id: gl::types::GLuint,
+    severity: gl::types::GLenum,
+    _length: gl::types::GLsizei,
+    message: *const gl::types::GLchar,
+    _user_param: *mut std::os::raw::c_void
+) {
+    let text = if message.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned()
+    };
+    let severity = match severity {
+        gl::DEBUG_SEVERITY_HIGH => Level::Error,
+        gl::DEBUG_SEVERITY_MEDIUM => Level::Warn,
+        gl::DEBUG_SEVERITY_LOW => Level::Info,
+        _ => Level::Debug,
+    };
+    let source = match source {
+        gl::DEBUG_SOURCE_API => "api",
+        gl::DEBUG_SOURCE_WINDOW_SYSTEM => "window system",
+        gl::DEBUG_SOURCE_SHADER_COMPILER => "shader compiler",
+        gl::DEBUG_SOURCE_THIRD_PARTY => "third party",
+        gl::DEBUG_SOURCE_APPLICATION => "application",
+        _ => "other",
+    };
+    let kind = match kind {
+        gl::DEBUG_TYPE_ERROR => "error",
+        gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated",
+        gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined behavior",
+        gl::DEBUG_TYPE_PORTABILITY => "portability",
+        gl::DEBUG_TYPE_PERFORMANCE => "performance",
+        _ => "other",
+    };
+    log_event!(severity, "gl", text; source = source, kind = kind, id = id);
+}
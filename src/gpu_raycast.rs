@@ -0,0 +1,321 @@
+// Import OpenGL bindings, map constants and the lighting, fog, palette and sky the CPU renderer uses
+extern crate gl;
+use crate::draw_gl::TextureManager;
+use crate::fog::{ Fog, FogMode };
+use crate::lightmap::{ Lighting, LIGHTMAP_RES };
+use crate::palette::{ Colormap, INVERSE_BITS, MAX_LIGHT, PALETTE_SIZE };
+use crate::player;
+use crate::portal::{ Face, PortalLink };
+use crate::render_gl::{ Program, WallColors, MAX_PORTAL_DEPTH };
+use crate::segment::WallSegment;
+use crate::sky::Sky;
+use crate::window_gl::{ FOV, MAP, MAP_S, MAP_X, MAP_Y, PORTALS, RENDER_X, RENDER_Y, SKY_CELLS };
+
+// Portal legs raycast.frag keeps per ray (`MAX_PORTAL_LEGS` there)
+const SHADER_PORTAL_LEGS: i32 = 8;
+const _: () = assert!(MAX_PORTAL_DEPTH <= SHADER_PORTAL_LEGS, "raycast.frag can't follow that many portals");
+
+// Textures read by raycast.frag, which raycasts the grid map on the GPU instead of the CPU
+pub struct GpuRaycaster {
+    map: TextureManager, // Tile value and sky flag per cell (uploaded once)
+    sky: TextureManager, // Panoramic sky (uploaded once)
+    segments: TextureManager, // Wall segment endpoints (uploaded once)
+    portals: TextureManager, // Portal links, both directions of each portal (uploaded once)
+    inverse: TextureManager, // Palette index of each RGB value (uploaded once)
+    light_table: TextureManager, // Colormap light tables (uploaded once)
+    fog_table: TextureManager, // Colormap fog tables (uploaded once)
+    floor_light: TextureManager, // Light per floor texel (refreshed when the light changes)
+    wall_light: TextureManager, // Light per wall face texel (refreshed when the light changes)
+    segment_count: usize,
+    portal_count: usize,
+    lighting_allocated: bool, // Whether the light textures have their storage yet
+}
+
+impl GpuRaycaster {
+    // Constructor to upload the map, sky, wall segments, portals and the colormap for palette mode
+    pub fn new(sky: &Sky, segments: &[WallSegment], colormap: &Colormap) -> Result<GpuRaycaster, String> {
+        let mut cells = Vec::with_capacity((MAP_X * MAP_Y) as usize);
+        for (row, sky_row) in MAP.iter().zip(SKY_CELLS.iter()) {
+            for (&tile, &open) in row.iter().zip(sky_row.iter()) {
+                cells.push([tile, open]);
+            }
+        }
+        let map = TextureManager::new();
+        map.load_tile_texture(MAP_X, MAP_Y, &cells)?;
+
+        let (sky_width, sky_height) = sky.size();
+        let sky_texture = TextureManager::new();
+        sky_texture.load_rgb_texture(sky_width as i32, sky_height as i32, sky.pixels())?;
+
+        // Textures can't be empty, so a map without segments or portals gets one unused texel
+        let mut endpoints: Vec<[f32; 4]> = segments.iter()
+            .map(|segment| [segment.x1, segment.y1, segment.x2, segment.y2])
+            .collect();
+        let segment_count = endpoints.len();
+        endpoints.resize(segment_count.max(1), [0.0; 4]);
+        let segment_texture = TextureManager::new();
+        segment_texture.load_vec4_texture(endpoints.len() as i32, 1, &endpoints)?;
+
+        let mut links = Vec::new();
+        for portal in PORTALS.iter() {
+            for link in [PortalLink::new(portal.a, portal.b), PortalLink::new(portal.b, portal.a)] {
+                links.extend_from_slice(&portal_texels(&link));
+            }
+        }
+        let portal_count = links.len() / 3;
+        links.resize(links.len().max(3), [0.0; 4]);
+        let portal_texture = TextureManager::new();
+        portal_texture.load_vec4_texture(links.len() as i32, 1, &links)?;
+
+        let steps = 1 << INVERSE_BITS;
+        let inverse = TextureManager::new();
+        inverse.load_byte_texture(steps * steps, steps, colormap.inverse())?;
+        let light_table = TextureManager::new();
+        let light_tables = colormap.light_tables();
+        light_table.load_byte_texture(PALETTE_SIZE as i32, light_tables.len() as i32, light_tables.as_flattened())?;
+        let fog_table = TextureManager::new();
+        let fog_tables = colormap.fog_tables();
+        fog_table.load_byte_texture(PALETTE_SIZE as i32, fog_tables.len() as i32, fog_tables.as_flattened())?;
+
+        Ok(GpuRaycaster {
+            map,
+            sky: sky_texture,
+            segments: segment_texture,
+            portals: portal_texture,
+            inverse,
+            light_table,
+            fog_table,
+            floor_light: TextureManager::new(),
+            wall_light: TextureManager::new(),
+            segment_count,
+            portal_count,
+            lighting_allocated: false,
+        })
+    }
+
+    // Upload the current baked and dynamic light, sampled at the same texels the CPU renderer reads.
+    // Only needed when the light has changed; the storage is allocated on the first call and
+    // refilled in place after that.
+    pub fn update_lighting(&mut self, lighting: &Lighting) -> Result<(), String> {
+        let map_s = MAP_S as f32;
+        let texel = map_s / (LIGHTMAP_RES as f32);
+        let width = MAP_X * LIGHTMAP_RES;
+
+        let floor_height = MAP_Y * LIGHTMAP_RES;
+        let mut floor = Vec::with_capacity((width * floor_height) as usize);
+        for ty in 0..floor_height {
+            for tx in 0..width {
+                let x = ((tx as f32) + 0.5) * texel;
+                let y = ((ty as f32) + 0.5) * texel;
+                floor.push(lighting.floor_light(x, y));
+            }
+        }
+        upload_light(&self.floor_light, self.lighting_allocated, width, floor_height, &floor)?;
+
+        // One row per face of each cell row; texels run along the world x (North, South) or y axis
+        let faces = [Face::North, Face::South, Face::East, Face::West];
+        let wall_height = MAP_Y * (faces.len() as i32);
+        let mut walls = Vec::with_capacity((width * wall_height) as usize);
+        for cell_y in 0..MAP_Y {
+            for face in faces {
+                for tx in 0..width {
+                    let cell_x = tx / LIGHTMAP_RES;
+                    let along = (((tx % LIGHTMAP_RES) as f32) + 0.5) * texel;
+                    let (nx, ny) = face.normal();
+                    let (x, y) = match face {
+                        Face::North | Face::South => (
+                            (cell_x as f32) * map_s + along,
+                            ((cell_y as f32) + 0.5 + ny * 0.5) * map_s,
+                        ),
+                        Face::East | Face::West => (
+                            ((cell_x as f32) + 0.5 + nx * 0.5) * map_s,
+                            (cell_y as f32) * map_s + along,
+                        ),
+                    };
+                    walls.push(lighting.wall_light(cell_x, cell_y, Some(face), x, y));
+                }
+            }
+        }
+        upload_light(&self.wall_light, self.lighting_allocated, width, wall_height, &walls)?;
+        self.lighting_allocated = true;
+        Ok(())
+    }
+
+    // Bind the textures and set every uniform raycast.frag needs; `indexed` makes it write palette
+    // indices instead of RGB
+    pub fn set_uniforms(&self, program: &Program, player: &player::Player, colors: &WallColors, fog: &Fog, indexed: bool) {
+        let rgb = |color: [u8; 3]| color.map(|channel| channel as f32);
+        let fog_mode = match fog.mode {
+            FogMode::Off => 0,
+            FogMode::Linear => 1,
+            FogMode::Exponential => 2,
+        };
+        let textures = [
+            (&self.map, "map"),
+            (&self.floor_light, "floor_light"),
+            (&self.wall_light, "wall_light"),
+            (&self.sky, "sky"),
+            (&self.segments, "segments"),
+            (&self.portals, "portals"),
+            (&self.inverse, "inverse"),
+            (&self.light_table, "light_table"),
+            (&self.fog_table, "fog_table"),
+        ];
+        for (unit, (texture, name)) in textures.iter().enumerate() {
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE0 + (unit as u32));
+                gl::BindTexture(gl::TEXTURE_2D, texture.id);
+            }
+            program.set_sampler(name, unit as u32);
+        }
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+
+        program.set_vec2("eye", [player.x_pos + 4.0, player.y_pos + 4.0]);
+        program.set_float("view_angle", player.get_dir());
+        program.set_float("fov", FOV);
+        program.set_vec2("resolution", [RENDER_X as f32, RENDER_Y as f32]);
+        program.set_float("cell_size", MAP_S as f32);
+        program.set_int("light_res", LIGHTMAP_RES);
+        program.set_vec3("horizontal_color", rgb(colors.horizontal));
+        program.set_vec3("vertical_color", rgb(colors.vertical));
+        program.set_vec3("segment_color", rgb(colors.segment));
+        program.set_vec3("ceiling_color", rgb(colors.background));
+        program.set_vec3("floor_color", rgb(colors.floor));
+        program.set_vec3("fog_color", rgb(fog.color));
+        program.set_int("fog_mode", fog_mode);
+        program.set_float("fog_start", fog.start);
+        program.set_float("fog_end", fog.end);
+        program.set_int("segment_count", self.segment_count as i32);
+        program.set_int("portal_count", self.portal_count as i32);
+        program.set_int("portal_depth", MAX_PORTAL_DEPTH);
+        program.set_bool("indexed", indexed);
+        program.set_float("max_light", MAX_LIGHT);
+    }
+}
+
+// Allocate a light texture, or refill the one already allocated
+fn upload_light(texture: &TextureManager, allocated: bool, width: i32, height: i32, texels: &[[f32; 3]]) -> Result<(), String> {
+    if allocated {
+        texture.update_float_texture(width, height, texels)
+    } else {
+        texture.load_float_texture(width, height, texels)
+    }
+}
+
+// Texels raycast.frag reads for one portal link: entry cell, face and rotation; entry and exit
+// face centers; cosine and sine of the rotation and the exit face's normal
+fn portal_texels(link: &PortalLink) -> [[f32; 4]; 3] {
+    let entry = link.entry();
+    let exit = link.exit();
+    let (entry_x, entry_y) = entry.center();
+    let (exit_x, exit_y) = exit.center();
+    let (sin, cos) = link.rotation().sin_cos();
+    let (nx, ny) = exit.face.normal();
+    [
+        [entry.cell_x as f32, entry.cell_y as f32, entry.face.index() as f32, link.rotation()],
+        [entry_x, entry_y, exit_x, exit_y],
+        [cos, sin, nx, ny],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw_gl::{
+        BufferArrayBinder,
+        FrameBuffer,
+        RenderTarget,
+        StreamTexture,
+        TexelFormat,
+        TexturedVertex,
+        VertexArrayWrapper,
+    };
+    use crate::light::LightCache;
+    use crate::lightmap::Lightmap;
+    use crate::palette::Palette;
+    use crate::render_gl::{ self, RayBuffers, Shader };
+    use crate::segment::SegmentIndex;
+    use crate::window_gl::{ LIGHTS, MAP_AMBIENT_LIGHT, MAP_FOG, SEGMENTS, WALL_COLORS };
+    use std::f32::consts::PI;
+    use std::ffi::CString;
+
+    // Draw one frame with raycast.frag into a canvas texture and read it back
+    fn render_gpu<P: TexelFormat>(
+        raycaster: &GpuRaycaster,
+        program: &Program,
+        player: &player::Player,
+        indexed: bool,
+        fill: P
+    ) -> FrameBuffer<P> {
+        let texture = StreamTexture::<P>::new(false);
+        let target = RenderTarget::new(&texture).unwrap();
+        let mut quad: BufferArrayBinder<TexturedVertex> = BufferArrayBinder::new(gl::STATIC_DRAW);
+        quad.set_buffers(&render_gl::target_vertices());
+        program.set_used();
+        raycaster.set_uniforms(program, player, &WALL_COLORS, &MAP_FOG, indexed);
+        target.draw_into(|| quad.draw_arrays(gl::TRIANGLES, 0..6));
+        let mut frame = FrameBuffer::new(fill);
+        target.read(&mut frame);
+        frame
+    }
+
+    // Share of pixels whose bytes differ by more than `tolerance` between two frames
+    fn mismatch<P: TexelFormat>(a: &FrameBuffer<P>, b: &FrameBuffer<P>, tolerance: u8) -> f32 {
+        let size = std::mem::size_of::<P>();
+        let pixels = a.as_bytes().chunks(size).zip(b.as_bytes().chunks(size));
+        let differing = pixels
+            .filter(|(a, b)| a.iter().zip(b.iter()).any(|(a, b)| a.abs_diff(*b) > tolerance))
+            .count();
+        (differing as f32) / ((RENDER_X * RENDER_Y) as f32)
+    }
+
+    // Needs an OpenGL 4.1 context; run with `cargo test -- --ignored` on a machine with a display
+    #[test]
+    #[ignore]
+    fn gpu_frames_match_the_cpu_frames() {
+        let _context = render_gl::test_context().expect("no OpenGL 4.1 context available");
+        unsafe {
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        }
+        let source = |text: &str| CString::new(text).unwrap();
+        let program = Program::from_shaders(&[
+            Shader::from_vert_source("tex.vert", &source(include_str!("./shaders/tex.vert"))).unwrap(),
+            Shader::from_frag_source("raycast.frag", &source(include_str!("./shaders/raycast.frag"))).unwrap(),
+        ]).unwrap();
+
+        let segments = SegmentIndex::new(&SEGMENTS);
+        let lightmap = Lightmap::bake(&LIGHTS, MAP_AMBIENT_LIGHT, &segments);
+        let dynamic = LightCache::new([0.0; 3]);
+        let lighting = Lighting { baked: &lightmap, dynamic: &dynamic };
+        let sky = Sky::gradient([20, 24, 60], [140, 110, 130]);
+        let colormap = Colormap::new(&Palette::ramps(), MAP_FOG);
+        let mut raycaster = GpuRaycaster::new(&sky, &SEGMENTS, &colormap).unwrap();
+        raycaster.update_lighting(&lighting).unwrap();
+        raycaster.update_lighting(&lighting).unwrap(); // Refills the storage allocated by the first upload
+
+        // An open corridor, both sides of the portal, a wall segment close up, and the sky with the
+        // other segment
+        let poses = [(200.0, 200.0, 0.0), (150.0, 405.0, PI), (345.0, 90.0, 4.71), (80.0, 100.0, 3.9), (340.0, 340.0, 0.785)];
+        for &(x, y, dir) in &poses {
+            let mut player = player::Player::new(x, y);
+            player.update_dir(dir);
+            let mut rays = RayBuffers::new();
+            render_gl::cast_rays(&mut VertexArrayWrapper::new(), &player, &segments, &mut rays, false);
+
+            let mut cpu = FrameBuffer::new([0u8; 3]);
+            render_gl::draw_walls_to_pixels(&mut cpu, &player, &rays, &WALL_COLORS, &MAP_FOG, &lighting, &sky);
+            let gpu = render_gpu(&raycaster, &program, &player, false, [0u8; 3]);
+            let share = mismatch(&cpu, &gpu, 8);
+            assert!(share < 0.02, "{:.1}% of RGB pixels differ at ({}, {}, {})", share * 100.0, x, y, dir);
+
+            let mut cpu = FrameBuffer::new(0u8);
+            render_gl::draw_walls_to_pixels(&mut cpu, &player, &rays, &WALL_COLORS, &colormap, &lighting, &sky);
+            let gpu = render_gpu(&raycaster, &program, &player, true, 0u8);
+            let share = mismatch(&cpu, &gpu, 0);
+            assert!(share < 0.02, "{:.1}% of palette indices differ at ({}, {}, {})", share * 100.0, x, y, dir);
+        }
+    }
+}
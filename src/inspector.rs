@@ -0,0 +1,288 @@
+// Import OpenGL bindings, the raycaster's step-by-step results and the overlay utilities
+extern crate gl;
+use crate::camera;
+use crate::draw_gl::{ BufferArrayBinder, ColorVertex, VertexArrayWrapper };
+use crate::log::Level;
+use crate::log_event;
+use crate::player::Player;
+use crate::render_gl::{ self, Program, RayReport, StepCell };
+use crate::text::{ Align, TextRenderer, TextStyle };
+use crate::window_gl::{ Layout, FOV, RAYS_COUNT, RENDER_X };
+use std::f32::consts::PI;
+
+// Half the size of the cross marking a grid step on the minimap (world units)
+const STEP_MARK: f32 = 2.0;
+
+// Picks one canvas column, highlights its ray on the minimap and explains how the ray was traced.
+// While a column is picked the view is frozen: the player only moves by explicit pose steps.
+pub struct RayInspector {
+    column: Option<i32>, // Canvas column being inspected
+    highlight: BufferArrayBinder<ColorVertex>, // The ray's legs and grid steps, in world units
+    panel: BufferArrayBinder<ColorVertex>, // Panel background and the column marker, in window pixels
+}
+
+impl RayInspector {
+    // Constructor to create an inspector with nothing picked
+    pub fn new() -> RayInspector {
+        RayInspector {
+            column: None,
+            highlight: BufferArrayBinder::new(gl::STREAM_DRAW),
+            panel: BufferArrayBinder::new(gl::STREAM_DRAW),
+        }
+    }
+
+    // Whether a column is picked (and the view frozen)
+    pub fn is_active(&self) -> bool {
+        self.column.is_some()
+    }
+
+    // The picked canvas column
+    pub fn column(&self) -> Option<i32> {
+        self.column
+    }
+
+    // Let go of the column and unfreeze the view
+    pub fn close(&mut self) {
+        self.column = None;
+    }
+
+    // Pick a canvas column
+    pub fn select_column(&mut self, column: i32) {
+        let column = column.clamp(0, RENDER_X - 1);
+        log_event!(Level::Debug, "inspector", "column picked"; column = column, ray = render_gl::column_ray(column));
+        self.column = Some(column);
+    }
+
+    // Pick the ray passing nearest to a world point (a click on the 2D view). Returns false when
+    // the point lies outside the field of view.
+    pub fn select_point(&mut self, player: &Player, world_x: f32, world_y: f32) -> bool {
+        match nearest_ray(player, world_x, world_y) {
+            Some(ray) => {
+                self.select_column(render_gl::ray_column(ray));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Move the pick by a number of columns
+    pub fn step_column(&mut self, delta: i32) {
+        if let Some(column) = self.column {
+            self.select_column(column + delta);
+        }
+    }
+
+    // Draw the ray's legs and the grid crossings it examined. Expects triangle.vert to be in use
+    // with the debug view's camera matrix, like the rest of the minimap.
+    pub fn draw_highlight(&mut self, report: &RayReport) {
+        let mut vertices = VertexArrayWrapper::new();
+        let leg_color = [0.0, 1.0, 1.0];
+        for leg in &report.legs {
+            vertices.push(ColorVertex::new([leg.origin.0, leg.origin.1, 0.0], leg_color));
+            vertices.push(ColorVertex::new([leg.end.0, leg.end.1, 0.0], leg_color));
+        }
+        // Crossings: green on horizontal grid lines, red on vertical ones, white where a wall
+        // stopped the leg and grey where it left the map
+        for step in &report.steps {
+            let color = match (step.cell, step.vertical) {
+                (StepCell::Wall(..), _) => [1.0, 1.0, 1.0],
+                (StepCell::Outside, _) => [0.5, 0.5, 0.5],
+                (StepCell::Empty(..), false) => [0.0, 1.0, 0.0],
+                (StepCell::Empty(..), true) => [1.0, 0.0, 0.0],
+            };
+            for (from, to) in [
+                ((-STEP_MARK, -STEP_MARK), (STEP_MARK, STEP_MARK)),
+                ((-STEP_MARK, STEP_MARK), (STEP_MARK, -STEP_MARK)),
+            ] {
+                vertices.push(ColorVertex::new([step.x + from.0, step.y + from.1, 0.0], color));
+                vertices.push(ColorVertex::new([step.x + to.0, step.y + to.1, 0.0], color));
+            }
+        }
+        vertices.set_line_end(vertices.len());
+        self.highlight.set_buffers(vertices.vertices());
+        self.highlight.draw_arrays(gl::LINES, vertices.lines());
+    }
+
+    // Mark the column on the canvas, and draw the info panel in the bottom-left corner of the
+    // debug view with triangle.vert/triangle.frag and the overlay text
+    pub fn draw_panel(&mut self, program: &Program, text: &mut TextRenderer, report: &RayReport, layout: &Layout) {
+        let info = describe(report);
+        let (text_width, text_height) = text.font().measure(&info, 1);
+        let (view_x, view_y, _, view_height) = layout.debug_view;
+        let left = view_x + 8;
+        let top = (view_y + view_height - 8 - (text_height as i32)).max(view_y + 8);
+
+        let mut vertices = VertexArrayWrapper::new();
+        push_rect(
+            &mut vertices,
+            (left - 4) as f32,
+            (top - 4) as f32,
+            (text_width + 8) as f32,
+            (text_height + 8) as f32,
+            [0.05, 0.05, 0.1]
+        );
+        vertices.set_triangle_end(vertices.len());
+        // Outline of the column on the canvas
+        let (canvas_x, canvas_y, canvas_width, canvas_height) = layout.canvas_view;
+        let column_width = (canvas_width as f32) / (RENDER_X as f32);
+        let x = (canvas_x as f32) + (report.column as f32) * column_width;
+        let (top_y, bottom_y) = (canvas_y as f32, (canvas_y + canvas_height) as f32);
+        let marker = [0.0, 1.0, 1.0];
+        for (start, end) in [
+            ([x, top_y], [x, bottom_y]),
+            ([x + column_width, top_y], [x + column_width, bottom_y]),
+        ] {
+            vertices.push(ColorVertex::new([start[0], start[1], 0.0], marker));
+            vertices.push(ColorVertex::new([end[0], end[1], 0.0], marker));
+        }
+        vertices.set_line_end(vertices.len());
+
+        self.panel.set_buffers(vertices.vertices());
+        program.set_used();
+        program.set_mat3("view", &camera::window_matrix(layout));
+        self.panel.draw_arrays(gl::TRIANGLES, vertices.triangles());
+        self.panel.draw_arrays(gl::LINES, vertices.lines());
+        text.queue(&info, left, top, &TextStyle::new([230, 230, 230], 1, Align::Left));
+    }
+}
+
+impl Default for RayInspector {
+    fn default() -> Self {
+        RayInspector::new()
+    }
+}
+
+// --- Canvas Column Under a Window Pixel, if It Lies on the Canvas ---
+pub fn canvas_column(layout: &Layout, window_x: i32, window_y: i32) -> Option<i32> {
+    let (x, y, width, height) = layout.canvas_view;
+    if !(x..x + width).contains(&window_x) || !(y..y + height).contains(&window_y) {
+        return None;
+    }
+    Some(((window_x - x) * RENDER_X) / width)
+}
+
+// --- Ray Passing Nearest to a World Point, or None Outside the Field of View ---
+fn nearest_ray(player: &Player, world_x: f32, world_y: f32) -> Option<usize> {
+    let dx = world_x - (player.x_pos + 4.0);
+    let dy = world_y - (player.y_pos + 4.0);
+    let angle = dy.atan2(dx).rem_euclid(2.0 * PI);
+    let angles = render_gl::ray_angles(player);
+    let (ray, difference) = angles
+        .iter()
+        .map(|&ray_angle| {
+            let difference = (angle - ray_angle).rem_euclid(2.0 * PI);
+            difference.min(2.0 * PI - difference)
+        })
+        .enumerate()
+        .fold((0, f32::INFINITY), |nearest, (ray, difference)| {
+            if difference < nearest.1 { (ray, difference) } else { nearest }
+        });
+    if difference > FOV / (RAYS_COUNT as f32) {
+        return None;
+    }
+    Some(ray)
+}
+
+// --- Panel Text: the Ray's Angle, Each Leg's Steps and Hits, and the Chosen Distance ---
+fn describe(report: &RayReport) -> String {
+    let mut lines = vec![
+        format!(
+            "Ray {} (column {})  angle {:.4} ({:.2} deg)",
+            report.ray,
+            report.column,
+            report.angle,
+            report.angle.to_degrees()
+        )
+    ];
+    for (index, leg) in report.legs.iter().enumerate() {
+        lines.push(
+            format!("Leg {} from ({:.1}, {:.1}) at {:.4}", index, leg.origin.0, leg.origin.1, leg.angle)
+        );
+        for step in report.steps.iter().filter(|step| step.leg == index) {
+            let cell = match step.cell {
+                StepCell::Empty(x, y) => format!("cell {},{} empty", x, y),
+                StepCell::Wall(x, y) => format!("cell {},{} wall", x, y),
+                StepCell::Outside => String::from("off the map"),
+            };
+            let lines_kind = if step.vertical { "v" } else { "h" };
+            lines.push(format!("  {} ({:.1}, {:.1}) {}", lines_kind, step.x, step.y, cell));
+        }
+        lines.push(format!("  h hit ({:.1}, {:.1}) dist {:.2}", leg.h_hit.0, leg.h_hit.1, leg.h_dist));
+        lines.push(format!("  v hit ({:.1}, {:.1}) dist {:.2}", leg.v_hit.0, leg.v_hit.1, leg.v_dist));
+    }
+    if let Some((x, y)) = report.segment_hit {
+        lines.push(format!("Segment hit ({:.1}, {:.1})", x, y));
+    }
+    lines.push(format!("hrays {:.2}  vrays {:.2}  srays {:.2}", report.h_dist, report.v_dist, report.s_dist));
+    lines.push(
+        format!(
+            "Chosen {}  dist {:.2}  corrected {:.2}",
+            report.side.name(),
+            report.raw_dist,
+            report.corrected_dist
+        )
+    );
+    lines.push(String::from(",/. column  arrows step pose  I close"));
+    lines.join("\n")
+}
+
+// --- Push Two Triangles Covering a Rectangle ---
+fn push_rect(vertices: &mut VertexArrayWrapper, x: f32, y: f32, width: f32, height: f32, color: [f32; 3]) {
+    let corners = [[x, y, 0.0], [x + width, y, 0.0], [x, y + height, 0.0], [x + width, y + height, 0.0]];
+    for &i in &[0, 1, 2, 1, 3, 2] {
+        vertices.push(ColorVertex::new(corners[i], color));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // World point `distance` units from the player's eye along an angle
+    fn point_along(player: &Player, angle: f32, distance: f32) -> (f32, f32) {
+        (player.x_pos + 4.0 + angle.cos() * distance, player.y_pos + 4.0 + angle.sin() * distance)
+    }
+
+    #[test]
+    fn canvas_edges_pick_the_first_and_last_columns() {
+        for layout in &[Layout::default(), Layout::new(800, 600), Layout::new(1920, 1080)] {
+            let (x, y, width, height) = layout.canvas_view;
+            assert_eq!(canvas_column(layout, x, y), Some(0));
+            assert_eq!(canvas_column(layout, x + width - 1, y + height - 1), Some(RENDER_X - 1));
+            assert_eq!(canvas_column(layout, x - 1, y), None);
+            assert_eq!(canvas_column(layout, x + width, y), None);
+            assert_eq!(canvas_column(layout, x, y - 1), None);
+            assert_eq!(canvas_column(layout, x, y + height), None);
+        }
+
+        // The edge columns show the first and last rays, and every ray's first column maps back to it
+        assert_eq!(render_gl::column_ray(0), 0);
+        assert_eq!(render_gl::column_ray(RENDER_X - 1), (RAYS_COUNT as usize) - 1);
+        for ray in 0..(RAYS_COUNT as usize) {
+            assert_eq!(render_gl::column_ray(render_gl::ray_column(ray)), ray);
+        }
+    }
+
+    #[test]
+    fn points_at_the_view_edges_pick_the_edge_rays() {
+        let step = FOV / (RAYS_COUNT as f32);
+        for &dir in &[0.0, 1.0, 3.0, 6.0] {
+            let mut player = Player::new(200.0, 200.0);
+            player.update_dir(dir);
+            let angles = render_gl::ray_angles(&player);
+            let last = (RAYS_COUNT as usize) - 1;
+            let (x, y) = point_along(&player, angles[0], 60.0);
+            assert_eq!(nearest_ray(&player, x, y), Some(0), "left edge at {}", dir);
+            let (x, y) = point_along(&player, angles[last], 60.0);
+            assert_eq!(nearest_ray(&player, x, y), Some(last), "right edge at {}", dir);
+
+            // Just past either edge and straight behind the player there is no ray
+            let (x, y) = point_along(&player, angles[0] - 2.0 * step, 60.0);
+            assert_eq!(nearest_ray(&player, x, y), None);
+            let (x, y) = point_along(&player, angles[last] + 2.0 * step, 60.0);
+            assert_eq!(nearest_ray(&player, x, y), None);
+            let (x, y) = point_along(&player, dir + PI, 60.0);
+            assert_eq!(nearest_ray(&player, x, y), None);
+        }
+    }
+}
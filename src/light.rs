@@ -0,0 +1,360 @@
+// Import map constants, cell faces and the wall segment index (segments cast shadows too)
+use crate::window_gl::{ MAP, MAP_S, MAP_X, MAP_Y, TILE_EMPTY };
+use crate::portal::Face;
+use crate::segment::SegmentIndex;
+
+// A point light lighting walls and floors around it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointLight {
+    pub x: f32, // World x-coordinate
+    pub y: f32, // World y-coordinate
+    pub color: [f32; 3], // Light color (RGB, 0.0 - 1.0)
+    pub radius: f32, // Distance at which the light fades out completely
+    pub intensity: f32, // Brightness multiplier
+    pub flicker: f32, // How far the intensity may randomly drop (0.0 = steady, 1.0 = down to dark)
+}
+
+impl PointLight {
+    // Static lights don't flicker and can be baked into the lightmap
+    pub fn is_static(&self) -> bool {
+        self.flicker <= 0.0
+    }
+
+    // Brightness reaching a point at the given distance, ignoring occlusion
+    pub fn falloff(&self, dist: f32) -> f32 {
+        if dist >= self.radius {
+            return 0.0;
+        }
+        let fade = 1.0 - dist / self.radius;
+        self.intensity * fade * fade
+    }
+
+    // Copy of this light with its flicker applied for the given frame
+    pub fn flickered(&self, seed: u32, frame: u32) -> PointLight {
+        if self.flicker <= 0.0 {
+            return *self;
+        }
+        // Only change every few frames, so the light cache isn't rebuilt on every single one
+        let step = frame / 4;
+        let hash = (step.wrapping_mul(747796405) ^ seed.wrapping_mul(2891336453)).wrapping_mul(
+            277803737
+        );
+        let noise = ((hash >> 8) as f32) / ((1u32 << 24) as f32); // 0.0 - 1.0
+        PointLight {
+            intensity: self.intensity * (1.0 - self.flicker * noise),
+            ..*self
+        }
+    }
+}
+
+// Light values per map tile: one per floor cell and one per wall face.
+// Only tiles within reach of a light that moved, flickered or changed are recomputed.
+pub struct LightCache {
+    lights: Vec<PointLight>, // Lights the cache was last built for
+    ambient: [f32; 3], // Light every surface gets, even in full shadow
+    floor: Vec<[f32; 3]>, // Per cell (row-major), light at the cell's center
+    faces: Vec<[[f32; 3]; 4]>, // Per cell (row-major), light on each face (indexed by `Face::index`)
+    built: bool, // Whether the cache has been built at least once
+}
+
+impl LightCache {
+    // Constructor to create an empty LightCache
+    pub fn new(ambient: [f32; 3]) -> LightCache {
+        LightCache {
+            lights: Vec::new(),
+            ambient,
+            floor: vec![ambient; (MAP_X * MAP_Y) as usize],
+            faces: vec![[ambient; 4]; (MAP_X * MAP_Y) as usize],
+            built: false,
+        }
+    }
+
+    // Bring the cache up to date with the lights; returns whether anything was recomputed
+    pub fn update(&mut self, lights: &[PointLight], segments: &SegmentIndex) -> bool {
+        if self.built && self.lights == lights {
+            return false;
+        }
+        if !self.built || self.lights.len() != lights.len() {
+            // Lights were added or removed: start over
+            self.lights = lights.to_vec();
+            self.rebuild(segments);
+            self.built = true;
+            return true;
+        }
+
+        // A light only reaches cells within its radius, so only those around the old and new
+        // state of each changed light can differ
+        let changed: Vec<(PointLight, PointLight)> = self.lights
+            .iter()
+            .zip(lights.iter())
+            .filter(|(old, new)| old != new)
+            .map(|(&old, &new)| (old, new))
+            .collect();
+        self.lights = lights.to_vec();
+        for (old, new) in changed {
+            self.update_around(&old, segments);
+            self.update_around(&new, segments);
+        }
+        true
+    }
+
+    // Recompute every floor cell and wall face from scratch
+    fn rebuild(&mut self, segments: &SegmentIndex) {
+        for cell_y in 0..MAP_Y {
+            for cell_x in 0..MAP_X {
+                self.update_cell(cell_x, cell_y, segments);
+            }
+        }
+    }
+
+    // Recompute the cells a light can reach
+    fn update_around(&mut self, light: &PointLight, segments: &SegmentIndex) {
+        let map_s = MAP_S as f32;
+        // Face samples sit just over half a cell from the cell center
+        let reach = light.radius + map_s;
+        let first_x = (((light.x - reach) / map_s).floor() as i32).max(0);
+        let last_x = (((light.x + reach) / map_s).floor() as i32).min(MAP_X - 1);
+        let first_y = (((light.y - reach) / map_s).floor() as i32).max(0);
+        let last_y = (((light.y + reach) / map_s).floor() as i32).min(MAP_Y - 1);
+        for cell_y in first_y..=last_y {
+            for cell_x in first_x..=last_x {
+                let dx = ((cell_x as f32) + 0.5) * map_s - light.x;
+                let dy = ((cell_y as f32) + 0.5) * map_s - light.y;
+                if dx * dx + dy * dy < reach * reach {
+                    self.update_cell(cell_x, cell_y, segments);
+                }
+            }
+        }
+    }
+
+    // Recompute the floor light of an open cell, or the face lights of a wall cell
+    fn update_cell(&mut self, cell_x: i32, cell_y: i32, segments: &SegmentIndex) {
+        let map_s = MAP_S as f32;
+        let index = (cell_y * MAP_X + cell_x) as usize;
+        let center_x = ((cell_x as f32) + 0.5) * map_s;
+        let center_y = ((cell_y as f32) + 0.5) * map_s;
+
+        if MAP[cell_y as usize][cell_x as usize] == TILE_EMPTY {
+            self.floor[index] = self.light_at(center_x, center_y, None, segments);
+            return;
+        }
+
+        // Sample each face just in front of it, if that spot is open floor
+        for face in [Face::North, Face::South, Face::East, Face::West] {
+            let (nx, ny) = face.normal();
+            let sample_x = center_x + nx * (map_s / 2.0 + 1.0);
+            let sample_y = center_y + ny * (map_s / 2.0 + 1.0);
+            self.faces[index][face.index()] = if is_open(sample_x, sample_y) {
+                self.light_at(sample_x, sample_y, Some((nx, ny)), segments)
+            } else {
+                self.ambient
+            };
+        }
+    }
+
+    // Sum of ambient and all unoccluded lights at a point (optionally on a surface facing `normal`)
+    fn light_at(
+        &self,
+        x: f32,
+        y: f32,
+        normal: Option<(f32, f32)>,
+        segments: &SegmentIndex
+    ) -> [f32; 3] {
+        let mut total = self.ambient;
+        for light in &self.lights {
+            let dx = light.x - x;
+            let dy = light.y - y;
+            let brightness = light.falloff((dx * dx + dy * dy).sqrt());
+            if brightness <= 0.0 {
+                continue;
+            }
+            if let Some((nx, ny)) = normal {
+                if dx * nx + dy * ny <= 0.0 {
+                    continue; // Light is behind the surface
+                }
+            }
+            if !line_of_sight(light.x, light.y, x, y, segments) {
+                continue; // In shadow
+            }
+            for (channel, color) in total.iter_mut().zip(light.color.iter()) {
+                *channel += color * brightness;
+            }
+        }
+        total
+    }
+
+    // Cached light on the floor (or ceiling) at a world point
+    pub fn floor_light(&self, x: f32, y: f32) -> [f32; 3] {
+        match cell_index(x, y) {
+            Some(index) => self.floor[index],
+            None => self.ambient,
+        }
+    }
+
+    // Cached light on a wall face, or the floor light in front of it for faceless hits (segments)
+    pub fn wall_light(&self, cell_x: i32, cell_y: i32, face: Option<Face>, x: f32, y: f32) -> [f32; 3] {
+        let in_map = (0..MAP_X).contains(&cell_x) && (0..MAP_Y).contains(&cell_y);
+        match face {
+            Some(face) if in_map => self.faces[(cell_y * MAP_X + cell_x) as usize][face.index()],
+            Some(_) => self.ambient,
+            None => self.floor_light(x, y),
+        }
+    }
+}
+
+// Scale a color by a light value, saturating at full brightness
+pub fn shade(color: [u8; 3], light: [f32; 3]) -> [u8; 3] {
+    let mut shaded = color;
+    for (channel, amount) in shaded.iter_mut().zip(light.iter()) {
+        *channel = ((*channel as f32) * amount).round().min(255.0) as u8;
+    }
+    shaded
+}
+
+// Add two light values together
+pub fn add_light(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+// Index of the map cell containing a world point, if it is inside the map
+fn cell_index(x: f32, y: f32) -> Option<usize> {
+    let cell_x = (x / (MAP_S as f32)).floor() as i32;
+    let cell_y = (y / (MAP_S as f32)).floor() as i32;
+    if (0..MAP_X).contains(&cell_x) && (0..MAP_Y).contains(&cell_y) {
+        Some((cell_y * MAP_X + cell_x) as usize)
+    } else {
+        None
+    }
+}
+
+// Check whether a world point is on open floor
+fn is_open(x: f32, y: f32) -> bool {
+    match cell_index(x, y) {
+        Some(index) => MAP[index / (MAP_X as usize)][index % (MAP_X as usize)] == TILE_EMPTY,
+        None => false,
+    }
+}
+
+// Check whether the straight line between two points passes no wall cells or wall segments
+pub fn line_of_sight(x1: f32, y1: f32, x2: f32, y2: f32, segments: &SegmentIndex) -> bool {
+    let map_s = MAP_S as f32;
+    let dx = x2 - x1;
+    let dy = y2 - y1;
+    let length = (dx * dx + dy * dy).sqrt();
+    if length < 0.0001 {
+        return true;
+    }
+    let (dir_x, dir_y) = (dx / length, dy / length);
+
+    // Walk the cells between the two points (Amanatides & Woo grid traversal)
+    let mut cell_x = (x1 / map_s).floor() as i32;
+    let mut cell_y = (y1 / map_s).floor() as i32;
+    let end_x = (x2 / map_s).floor() as i32;
+    let end_y = (y2 / map_s).floor() as i32;
+    let step_x = if dir_x > 0.0 { 1 } else { -1 };
+    let step_y = if dir_y > 0.0 { 1 } else { -1 };
+    let delta_x = if dir_x != 0.0 { (map_s / dir_x).abs() } else { f32::INFINITY };
+    let delta_y = if dir_y != 0.0 { (map_s / dir_y).abs() } else { f32::INFINITY };
+    let mut next_x = if dir_x > 0.0 {
+        (((cell_x + 1) as f32) * map_s - x1) / dir_x
+    } else if dir_x < 0.0 {
+        ((cell_x as f32) * map_s - x1) / dir_x
+    } else {
+        f32::INFINITY
+    };
+    let mut next_y = if dir_y > 0.0 {
+        (((cell_y + 1) as f32) * map_s - y1) / dir_y
+    } else if dir_y < 0.0 {
+        ((cell_y as f32) * map_s - y1) / dir_y
+    } else {
+        f32::INFINITY
+    };
+
+    while (cell_x, cell_y) != (end_x, end_y) {
+        if next_x.min(next_y) > length {
+            break;
+        }
+        if next_x < next_y {
+            cell_x += step_x;
+            next_x += delta_x;
+        } else {
+            cell_y += step_y;
+            next_y += delta_y;
+        }
+        if !(0..MAP_X).contains(&cell_x) || !(0..MAP_Y).contains(&cell_y) {
+            return false;
+        }
+        if MAP[cell_y as usize][cell_x as usize] != TILE_EMPTY && (cell_x, cell_y) != (end_x, end_y) {
+            return false;
+        }
+    }
+
+    segments.cast(x1, y1, dir_y.atan2(dir_x), length).is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A lamp in the open room at the right of the map
+    fn lamp(x: f32, y: f32) -> PointLight {
+        PointLight { x, y, color: [1.0, 0.8, 0.6], radius: 100.0, intensity: 1.0, flicker: 0.0 }
+    }
+
+    #[test]
+    fn falloff_fades_to_zero_at_the_radius() {
+        let light = PointLight { intensity: 2.0, ..lamp(0.0, 0.0) };
+        assert_eq!(light.falloff(0.0), 2.0);
+        assert_eq!(light.falloff(50.0), 0.5);
+        assert_eq!(light.falloff(100.0), 0.0);
+        assert_eq!(light.falloff(500.0), 0.0);
+        assert!(light.falloff(25.0) > light.falloff(75.0));
+    }
+
+    #[test]
+    fn flicker_holds_for_a_few_frames_and_stays_in_range() {
+        let steady = lamp(0.0, 0.0);
+        assert!(steady.is_static());
+        assert_eq!(steady.flickered(1, 7), steady);
+
+        let flickering = PointLight { flicker: 0.5, ..steady };
+        assert!(!flickering.is_static());
+        assert_eq!(flickering.flickered(1, 8), flickering.flickered(1, 11));
+        let intensities: Vec<f32> = (0..64).map(|frame| flickering.flickered(1, frame * 4).intensity).collect();
+        assert!(intensities.iter().all(|&intensity| (0.5..=1.0).contains(&intensity)));
+        assert!(intensities.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+
+    #[test]
+    fn still_lights_keep_the_cache_and_moved_lights_refresh_it() {
+        let segments = SegmentIndex::new(&[]);
+        let mut cache = LightCache::new([0.1; 3]);
+        assert!(cache.update(&[lamp(352.0, 352.0)], &segments));
+        assert!(!cache.update(&[lamp(352.0, 352.0)], &segments));
+        let before = cache.floor_light(352.0, 352.0);
+
+        assert!(cache.update(&[lamp(352.0, 300.0)], &segments));
+        assert_ne!(cache.floor_light(352.0, 352.0), before);
+        assert!(!cache.update(&[lamp(352.0, 300.0)], &segments));
+
+        let flickered = PointLight { flicker: 0.9, ..lamp(352.0, 300.0) }.flickered(3, 0);
+        assert!(cache.update(&[flickered], &segments));
+    }
+
+    #[test]
+    fn partial_update_matches_a_full_rebuild() {
+        let segments = SegmentIndex::new(&[]);
+        let steady = lamp(96.0, 96.0);
+        let mut cache = LightCache::new([0.1; 3]);
+        cache.update(&[steady, lamp(352.0, 352.0)], &segments);
+        let far_away = cache.floor_light(96.0, 352.0);
+
+        let moved = [steady, lamp(352.0, 224.0)];
+        cache.update(&moved, &segments);
+        let mut fresh = LightCache::new([0.1; 3]);
+        fresh.update(&moved, &segments);
+        assert_eq!(cache.floor, fresh.floor);
+        assert_eq!(cache.faces, fresh.faces);
+        assert_eq!(cache.floor_light(96.0, 352.0), far_away);
+    }
+}
@@ -0,0 +1,474 @@
+// Import map constants, lights and the shadow test shared with the dynamic light cache
+use crate::window_gl::{ MAP, MAP_S, MAP_X, MAP_Y, TILE_EMPTY };
+use crate::light::{ add_light, line_of_sight, LightCache, PointLight };
+use crate::log::Level;
+use crate::log_event;
+use crate::portal::Face;
+use crate::segment::SegmentIndex;
+use std::f32::consts::PI;
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+// Texels per tile edge (floor cells get RES x RES texels, wall faces RES texels along the face)
+pub const LIGHTMAP_RES: i32 = 8;
+// Radius of the disk static lights are sampled over, for soft shadow edges
+const LIGHT_SIZE: f32 = 10.0;
+// How far ambient occlusion looks for nearby walls
+const AO_RADIUS: f32 = 24.0;
+// How dark fully enclosed spots get from ambient occlusion (0.0 = no AO)
+const AO_STRENGTH: f32 = 0.6;
+// Number of rays cast per texel for ambient occlusion
+const AO_RAYS: i32 = 12;
+// File header identifying a lightmap and its format version
+const MAGIC: &[u8; 4] = b"YGLM";
+const VERSION: u32 = 1;
+
+// Pre-computed static lighting for the grid map: ambient, static lights, soft shadows and AO
+pub struct Lightmap {
+    key: u64, // Hash of everything the bake depends on (used to detect stale files)
+    floor: Vec<[f32; 3]>, // Floor texels, (MAP_X * RES) x (MAP_Y * RES), row-major
+    faces: Vec<[f32; 3]>, // Wall face texels, RES per face, 4 faces per cell, cells row-major
+}
+
+impl Lightmap {
+    // Bake a lightmap for the given static lights, ambient light and wall segments
+    pub fn bake(lights: &[PointLight], ambient: [f32; 3], segments: &SegmentIndex) -> Lightmap {
+        let map_s = MAP_S as f32;
+        let texel = map_s / (LIGHTMAP_RES as f32);
+        let floor_w = MAP_X * LIGHTMAP_RES;
+        let floor_h = MAP_Y * LIGHTMAP_RES;
+
+        // Floor texels: lit from every direction, occluded by any nearby wall
+        let mut floor = vec![[0.0; 3]; (floor_w * floor_h) as usize];
+        for ty in 0..floor_h {
+            for tx in 0..floor_w {
+                let x = ((tx as f32) + 0.5) * texel;
+                let y = ((ty as f32) + 0.5) * texel;
+                if !is_open(x, y) {
+                    continue;
+                }
+                floor[(ty * floor_w + tx) as usize] = bake_texel(
+                    x,
+                    y,
+                    None,
+                    lights,
+                    ambient,
+                    segments
+                );
+            }
+        }
+
+        // Wall face texels: sampled just in front of the face, lit only from the front
+        let mut faces = vec![[0.0; 3]; (MAP_X * MAP_Y * 4 * LIGHTMAP_RES) as usize];
+        for cell_y in 0..MAP_Y {
+            for cell_x in 0..MAP_X {
+                if MAP[cell_y as usize][cell_x as usize] == TILE_EMPTY {
+                    continue;
+                }
+                for face in [Face::North, Face::South, Face::East, Face::West] {
+                    let (nx, ny) = face.normal();
+                    for t in 0..LIGHTMAP_RES {
+                        // Position along the face (left to right when looking at it from outside)
+                        let along = ((t as f32) + 0.5) * texel;
+                        let (x, y) = face_point(cell_x, cell_y, face, along);
+                        let (x, y) = (x + nx, y + ny);
+                        if !is_open(x, y) {
+                            continue;
+                        }
+                        let index = face_texel_index(cell_x, cell_y, face, t);
+                        faces[index] = bake_texel(x, y, Some((nx, ny)), lights, ambient, segments);
+                    }
+                }
+            }
+        }
+
+        Lightmap {
+            key: bake_key(lights, ambient, segments),
+            floor,
+            faces,
+        }
+    }
+
+    // Load a lightmap from disk if it matches the current map and lights, otherwise bake and save it
+    pub fn load_or_bake(
+        path: &Path,
+        lights: &[PointLight],
+        ambient: [f32; 3],
+        segments: &SegmentIndex
+    ) -> Lightmap {
+        let key = bake_key(lights, ambient, segments);
+        if let Ok(lightmap) = Lightmap::load(path) {
+            if lightmap.key == key {
+                return lightmap;
+            }
+        }
+        log_event!(Level::Info, "lightmap", "baking"; path = path.display());
+        let lightmap = Lightmap::bake(lights, ambient, segments);
+        if let Err(error) = lightmap.save(path) {
+            log_event!(Level::Error, "lightmap", "could not save"; path = path.display(), error = error);
+        }
+        lightmap
+    }
+
+    // Write the lightmap to a file
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.key.to_le_bytes());
+        bytes.extend_from_slice(&(LIGHTMAP_RES as u32).to_le_bytes());
+        for texel in self.floor.iter().chain(self.faces.iter()) {
+            for channel in texel {
+                bytes.extend_from_slice(&channel.to_le_bytes());
+            }
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        fs::write(path, bytes).map_err(|e| e.to_string())
+    }
+
+    // Read a lightmap from a file written by `save`
+    pub fn load(path: &Path) -> Result<Lightmap, String> {
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        let floor_len = (MAP_X * MAP_Y * LIGHTMAP_RES * LIGHTMAP_RES) as usize;
+        let faces_len = (MAP_X * MAP_Y * 4 * LIGHTMAP_RES) as usize;
+        let header_len = 4 + 4 + 8 + 4;
+        if bytes.len() != header_len + (floor_len + faces_len) * 12 || &bytes[0..4] != MAGIC {
+            return Err(String::from("not a lightmap for this map"));
+        }
+        let read_u32 = |at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+        if read_u32(4) != VERSION || read_u32(16) != (LIGHTMAP_RES as u32) {
+            return Err(String::from("lightmap version or resolution mismatch"));
+        }
+        let mut key_bytes = [0u8; 8];
+        key_bytes.copy_from_slice(&bytes[8..16]);
+
+        let texels: Vec<[f32; 3]> = bytes[header_len..]
+            .chunks_exact(12)
+            .map(|chunk| {
+                let mut texel = [0.0; 3];
+                for (channel, raw) in texel.iter_mut().zip(chunk.chunks_exact(4)) {
+                    *channel = f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+                }
+                texel
+            })
+            .collect();
+
+        Ok(Lightmap {
+            key: u64::from_le_bytes(key_bytes),
+            floor: texels[..floor_len].to_vec(),
+            faces: texels[floor_len..].to_vec(),
+        })
+    }
+
+    // Baked light on the floor (or ceiling) at a world point
+    pub fn floor_light(&self, x: f32, y: f32) -> [f32; 3] {
+        let texel = (MAP_S as f32) / (LIGHTMAP_RES as f32);
+        let tx = (x / texel).floor() as i32;
+        let ty = (y / texel).floor() as i32;
+        if !(0..MAP_X * LIGHTMAP_RES).contains(&tx) || !(0..MAP_Y * LIGHTMAP_RES).contains(&ty) {
+            return [0.0; 3];
+        }
+        self.floor[(ty * MAP_X * LIGHTMAP_RES + tx) as usize]
+    }
+
+    // Baked light on a wall face at a world hit point, or the floor light there for faceless hits
+    pub fn wall_light(&self, cell_x: i32, cell_y: i32, face: Option<Face>, x: f32, y: f32) -> [f32; 3] {
+        let face = match face {
+            Some(face) => face,
+            None => {
+                return self.floor_light(x, y);
+            }
+        };
+        if !(0..MAP_X).contains(&cell_x) || !(0..MAP_Y).contains(&cell_y) {
+            return [0.0; 3];
+        }
+        let map_s = MAP_S as f32;
+        let along = match face {
+            Face::North => x - (cell_x as f32) * map_s,
+            Face::South => ((cell_x as f32) + 1.0) * map_s - x,
+            Face::East => y - (cell_y as f32) * map_s,
+            Face::West => ((cell_y as f32) + 1.0) * map_s - y,
+        };
+        let t = ((along / map_s) * (LIGHTMAP_RES as f32)) as i32;
+        self.faces[face_texel_index(cell_x, cell_y, face, t.clamp(0, LIGHTMAP_RES - 1))]
+    }
+}
+
+// Baked and dynamic light together, as sampled by the renderer
+pub struct Lighting<'a> {
+    pub baked: &'a Lightmap, // Ambient and static lights
+    pub dynamic: &'a LightCache, // Moving and flickering lights
+}
+
+impl Lighting<'_> {
+    // Total light on the floor (or ceiling) at a world point
+    pub fn floor_light(&self, x: f32, y: f32) -> [f32; 3] {
+        add_light(self.baked.floor_light(x, y), self.dynamic.floor_light(x, y))
+    }
+
+    // Total light on a wall face at a world hit point
+    pub fn wall_light(&self, cell_x: i32, cell_y: i32, face: Option<Face>, x: f32, y: f32) -> [f32; 3] {
+        add_light(
+            self.baked.wall_light(cell_x, cell_y, face, x, y),
+            self.dynamic.wall_light(cell_x, cell_y, face, x, y)
+        )
+    }
+}
+
+// Light at one texel: ambient scaled by occlusion plus soft-shadowed static lights
+fn bake_texel(
+    x: f32,
+    y: f32,
+    normal: Option<(f32, f32)>,
+    lights: &[PointLight],
+    ambient: [f32; 3],
+    segments: &SegmentIndex
+) -> [f32; 3] {
+    // Ambient occlusion: fraction of short rays that run into a wall
+    let (start_angle, spread) = match normal {
+        Some((nx, ny)) => (ny.atan2(nx) - PI / 2.0, PI), // Half circle in front of a wall
+        None => (0.0, 2.0 * PI), // Full circle on the floor
+    };
+    let mut blocked = 0;
+    for i in 0..AO_RAYS {
+        let angle = start_angle + spread * (((i as f32) + 0.5) / (AO_RAYS as f32));
+        let (sin, cos) = angle.sin_cos();
+        if !line_of_sight(x, y, x + cos * AO_RADIUS, y + sin * AO_RADIUS, segments) {
+            blocked += 1;
+        }
+    }
+    let ao = 1.0 - AO_STRENGTH * ((blocked as f32) / (AO_RAYS as f32));
+
+    let mut total = [ambient[0] * ao, ambient[1] * ao, ambient[2] * ao];
+    for light in lights {
+        let dx = light.x - x;
+        let dy = light.y - y;
+        let brightness = light.falloff((dx * dx + dy * dy).sqrt());
+        if brightness <= 0.0 {
+            continue;
+        }
+        if let Some((nx, ny)) = normal {
+            if dx * nx + dy * ny <= 0.0 {
+                continue; // Light is behind the surface
+            }
+        }
+
+        // Soft shadows: how much of the light's disk is visible from here
+        let samples = soft_shadow_samples(light);
+        let visible = samples
+            .iter()
+            .filter(|(sx, sy)| line_of_sight(*sx, *sy, x, y, segments))
+            .count();
+        let coverage = (visible as f32) / (samples.len() as f32);
+        for (channel, color) in total.iter_mut().zip(light.color.iter()) {
+            *channel += color * brightness * coverage;
+        }
+    }
+    total
+}
+
+// Points spread over a light's disk: the center plus two rings
+fn soft_shadow_samples(light: &PointLight) -> Vec<(f32, f32)> {
+    let mut samples = vec![(light.x, light.y)];
+    for (ring, count) in [(0.5, 6), (1.0, 10)] {
+        for i in 0..count {
+            let angle = (2.0 * PI * (i as f32)) / (count as f32) + ring;
+            samples.push((
+                light.x + angle.cos() * LIGHT_SIZE * ring,
+                light.y + angle.sin() * LIGHT_SIZE * ring,
+            ));
+        }
+    }
+    samples
+}
+
+// World point on a cell's face, `along` units from its left end (seen from outside)
+fn face_point(cell_x: i32, cell_y: i32, face: Face, along: f32) -> (f32, f32) {
+    let map_s = MAP_S as f32;
+    let left = (cell_x as f32) * map_s;
+    let top = (cell_y as f32) * map_s;
+    match face {
+        Face::North => (left + along, top),
+        Face::South => (left + map_s - along, top + map_s),
+        Face::East => (left + map_s, top + along),
+        Face::West => (left, top + map_s - along),
+    }
+}
+
+// Index of a wall face texel in `Lightmap::faces`
+fn face_texel_index(cell_x: i32, cell_y: i32, face: Face, t: i32) -> usize {
+    ((((cell_y * MAP_X + cell_x) * 4) as usize + face.index()) * (LIGHTMAP_RES as usize)) + (t as usize)
+}
+
+// Check whether a world point is on open floor
+fn is_open(x: f32, y: f32) -> bool {
+    let cell_x = (x / (MAP_S as f32)).floor() as i32;
+    let cell_y = (y / (MAP_S as f32)).floor() as i32;
+    (0..MAP_X).contains(&cell_x) &&
+        (0..MAP_Y).contains(&cell_y) &&
+        MAP[cell_y as usize][cell_x as usize] == TILE_EMPTY
+}
+
+// Hash of everything a bake depends on: the map, the wall segments, the lights and the ambient light.
+// The inputs are hashed as explicit little-endian bytes with FNV-1a, so the key stays the same
+// across Rust releases and platforms (unlike `DefaultHasher`) and saved lightmaps stay valid.
+pub fn bake_key(lights: &[PointLight], ambient: [f32; 3], segments: &SegmentIndex) -> u64 {
+    let mut hasher = Fnv1a::new();
+    for row in MAP.iter() {
+        hasher.write(row);
+    }
+    hasher.write(&(segments.segments().len() as u64).to_le_bytes());
+    for segment in segments.segments() {
+        for value in [segment.x1, segment.y1, segment.x2, segment.y2] {
+            hasher.write_f32(value);
+        }
+    }
+    hasher.write(&(lights.len() as u64).to_le_bytes());
+    for light in lights {
+        for value in [light.x, light.y, light.radius, light.intensity] {
+            hasher.write_f32(value);
+        }
+        for value in light.color {
+            hasher.write_f32(value);
+        }
+    }
+    for value in ambient {
+        hasher.write_f32(value);
+    }
+    // Bake settings, so tuning them invalidates old files too
+    hasher.write(&(LIGHTMAP_RES as u32).to_le_bytes());
+    hasher.write(&(AO_RAYS as u32).to_le_bytes());
+    for value in [LIGHT_SIZE, AO_RADIUS, AO_STRENGTH] {
+        hasher.write_f32(value);
+    }
+    hasher.finish()
+}
+
+// Where a configured lightmap path points: absolute paths as given, relative ones next to the
+// executable, so the file neither depends on nor litters the working directory
+pub fn resolve_path(configured: &str) -> PathBuf {
+    let path = Path::new(configured);
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+    match std::env::current_exe() {
+        Ok(exe) => exe.parent().map_or_else(|| path.to_path_buf(), |dir| dir.join(path)),
+        Err(error) => {
+            log_event!(Level::Warn, "lightmap", "could not find the executable"; error = error);
+            path.to_path_buf()
+        }
+    }
+}
+
+// 64-bit FNV-1a hash
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ (byte as u64)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn write_f32(&mut self, value: f32) {
+        self.write(&value.to_bits().to_le_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::LightCache;
+
+    fn lamp(x: f32, y: f32) -> PointLight {
+        PointLight { x, y, color: [1.0, 0.8, 0.6], radius: 150.0, intensity: 1.0, flicker: 0.0 }
+    }
+
+    #[test]
+    fn fnv1a_matches_reference_values() {
+        assert_eq!(Fnv1a::new().finish(), 0xcbf2_9ce4_8422_2325);
+        let mut hasher = Fnv1a::new();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn bake_key_follows_the_inputs() {
+        let segments = SegmentIndex::new(&[]);
+        let lights = [lamp(100.0, 100.0)];
+        let key = bake_key(&lights, [0.1; 3], &segments);
+        assert_eq!(key, bake_key(&lights, [0.1; 3], &segments));
+        assert_ne!(key, bake_key(&lights, [0.2; 3], &segments));
+        assert_ne!(key, bake_key(&[lamp(101.0, 100.0)], [0.1; 3], &segments));
+        assert_ne!(key, bake_key(&[], [0.1; 3], &segments));
+    }
+
+    #[test]
+    fn relative_paths_resolve_next_to_the_executable() {
+        let exe_dir = std::env::current_exe().unwrap().parent().unwrap().to_path_buf();
+        assert_eq!(resolve_path("lightmaps/map.lightmap"), exe_dir.join("lightmaps/map.lightmap"));
+        let absolute = std::env::temp_dir().join("map.lightmap");
+        assert_eq!(resolve_path(absolute.to_str().unwrap()), absolute);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let segments = SegmentIndex::new(&[]);
+        let lightmap = Lightmap::bake(&[lamp(100.0, 100.0)], [0.1; 3], &segments);
+        let path = std::env::temp_dir().join(format!("yggdrasil-lightmap-{}.lightmap", std::process::id()));
+        lightmap.save(&path).unwrap();
+        let loaded = Lightmap::load(&path);
+        let _ = fs::remove_file(&path);
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.key, lightmap.key);
+        assert_eq!(loaded.floor, lightmap.floor);
+        assert_eq!(loaded.faces, lightmap.faces);
+    }
+
+    #[test]
+    fn load_rejects_other_files() {
+        let path = std::env::temp_dir().join(format!("yggdrasil-not-a-lightmap-{}", std::process::id()));
+        fs::write(&path, b"YGLM but far too short").unwrap();
+        let result = Lightmap::load(&path);
+        let _ = fs::remove_file(&path);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn lighting_adds_baked_and_dynamic_light() {
+        let segments = SegmentIndex::new(&[]);
+        let lightmap = Lightmap::bake(&[], [0.25; 3], &segments);
+        let dynamic = LightCache::new([0.5; 3]);
+        let lighting = Lighting { baked: &lightmap, dynamic: &dynamic };
+        let (x, y) = open_point();
+        let baked = lightmap.floor_light(x, y);
+        let total = lighting.floor_light(x, y);
+        for channel in 0..3 {
+            assert!(baked[channel] > 0.0 && baked[channel] <= 0.25);
+            assert!((total[channel] - (baked[channel] + 0.5)).abs() < 1e-6);
+        }
+    }
+
+    // Center of the first open cell on the map
+    fn open_point() -> (f32, f32) {
+        let map_s = MAP_S as f32;
+        for cell_y in 0..MAP_Y {
+            for cell_x in 0..MAP_X {
+                if MAP[cell_y as usize][cell_x as usize] == TILE_EMPTY {
+                    return (((cell_x as f32) + 0.5) * map_s, ((cell_y as f32) + 0.5) * map_s);
+                }
+            }
+        }
+        panic!("the map has no open cell");
+    }
+}
@@ -1,90 +1,356 @@
-// Import utility functions for converting coordinates and constants for window dimensions
-use crate::draw_gl;
-use draw_gl::get_x;
-use draw_gl::get_y;
-use crate::window_gl::HEIGHT;
-use crate::window_gl::WIDTH;
-use crate::player::Player;
-
-// Logs details about a horizontal ray during raycasting
-pub fn log_h_ray(
-    mx: i32, // Map x-coordinate of the ray
-    my: i32, // Map y-coordinate of the ray
-    mp: i32, // Map position index
-    dof: i32, // Depth of field (number of steps taken)
-    a_tan: f32, // Inverse tangent of the ray angle
-    ra: f32, // Ray angle
-    rx: f32, // Ray x-coordinate
-    ry: f32, // Ray y-coordinate
-    xo: f32, // Step size in the x-direction
-    yo: f32, // Step size in the y-direction
-) {
-    println!("================");
-    println!("===horizontal===");
-    println!("================");
-    println!("mx => {}", mx); // Log map x-coordinate
-    println!("my => {}", my); // Log map y-coordinate
-    println!("mp => {}", mp); // Log map position index
-    println!("dof => {}", dof); // Log depth of field
-    println!("================");
-    println!("aTan => {}", a_tan); // Log inverse tangent of the ray angle
-    println!("ra => {}", ra); // Log ray angle
-    println!("rx => {}", rx); // Log ray x-coordinate
-    println!("ry => {}", ry); // Log ray y-coordinate
-    println!("xo => {}", xo); // Log step size in the x-direction
-    println!("yo => {}", yo); // Log step size in the y-direction
-    println!("================");
-    println!("================");
-}
-
-// Logs details about a vertical ray during raycasting
-pub fn log_v_ray(
-    mx: i32, // Map x-coordinate of the ray
-    my: i32, // Map y-coordinate of the ray
-    mp: i32, // Map position index
-    dof: i32, // Depth of field (number of steps taken)
-    n_tan: f32, // Negative tangent of the ray angle
-    ra: f32, // Ray angle
-    rx: f32, // Ray x-coordinate
-    ry: f32, // Ray y-coordinate
-    xo: f32, // Step size in the x-direction
-    yo: f32, // Step size in the y-direction
-) {
-    println!("================");
-    println!("====vertical====");
-    println!("================");
-    println!("mx => {}", mx); // Log map x-coordinate
-    println!("my => {}", my); // Log map y-coordinate
-    println!("mp => {}", mp); // Log map position index
-    println!("dof => {}", dof); // Log depth of field
-    println!("================");
-    println!("nTan => {}", n_tan); // Log negative tangent of the ray angle
-    println!("ra => {}", ra); // Log ray angle
-    println!("rx => {}", rx); // Log ray x-coordinate
-    println!("ry => {}", ry); // Log ray y-coordinate
-    println!("xo => {}", xo); // Log step size in the x-direction
-    println!("yo => {}", yo); // Log step size in the y-direction
-    println!("================");
-    println!("================");
-}
-
-// Logs the vertices being pushed for a ray
-pub fn log_ray_vertices(player: &Player, rx: f32, ry: f32) {
-    println!("================");
-    println!("pushing vertices");
-    println!("player x => {}", player.get_player_x(4.0)); // Log player's x-coordinate
-    println!("player y => {}", player.get_player_y(4.0)); // Log player's y-coordinate
-    println!("ray x => {}", get_x(rx, WIDTH)); // Log ray's x-coordinate in normalized OpenGL space
-    println!("ray y => {}", get_y(ry, HEIGHT)); // Log ray's y-coordinate in normalized OpenGL space
-    println!("end vertices");
-    println!("================");
-}
-
-// Logs the player's current position
-pub fn log_player_pos(player: &Player) {
-    println!("-----------------------");
-    println!("player position");
-    println!("player x => {}", player.x_pos); // Log player's x-coordinate
-    println!("player y => {}", player.y_pos); // Log player's y-coordinate
-    println!("-----------------------");
+// Import synchronization and file utilities
+use crate::window_gl::LOG_MAX_LEVEL;
+use std::fmt::{ Debug, Write as _ };
+use std::fs::{ self, File, OpenOptions };
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{ AtomicBool, AtomicU32, AtomicU8, Ordering };
+use std::sync::{ Mutex, OnceLock, RwLock };
+use std::time::Instant;
+
+// Log levels, from quietest to noisiest (`Off` only appears in filters)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    // Name as written in filters and in the output
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Level> {
+        [Level::Off, Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace]
+            .iter()
+            .copied()
+            .find(|level| level.name().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+// Where log records go and which ones are kept
+#[derive(Debug, Clone, Copy)]
+pub struct LogConfig {
+    pub enabled: bool, // Whether logging starts on (L toggles it at runtime)
+    pub filter: &'static str, // Default level, then per-target levels: "info,raycast=trace,gl=debug"
+    pub stderr: bool, // Write records to stderr
+    pub file: Option<&'static str>, // Also append them to this file
+    pub max_file_bytes: u64, // Size at which the file is rotated
+    pub max_files: usize, // Rotated files kept next to it (`.1` is the newest)
+}
+
+// Environment variable that replaces the configured filter
+const FILTER_VAR: &str = "YGGDRASIL_LOG";
+
+// Whether logging is on at all (runtime toggle)
+static ENABLED: AtomicBool = AtomicBool::new(true);
+// Noisiest level any target lets through, so most disabled records stop at one atomic load
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+// Bumped whenever the filter changes, so callsites know their cached level is stale
+static GENERATION: AtomicU32 = AtomicU32::new(1);
+
+// Parsed filter: the default level and the per-target overrides
+struct Filter {
+    default: Level,
+    targets: Vec<(String, Level)>,
+}
+
+impl Filter {
+    // Parse a default level followed by `target=level` pairs, separated by commas. Unknown levels
+    // are reported and skipped.
+    fn parse(spec: &str) -> Filter {
+        let mut parsed = Filter { default: Level::Info, targets: Vec::new() };
+        for part in spec.split(',').filter(|part| !part.trim().is_empty()) {
+            match part.split_once('=') {
+                Some((target, level)) => {
+                    match Level::parse(level) {
+                        Some(level) => parsed.targets.push((target.trim().to_string(), level)),
+                        None => eprintln!("Unknown log level in `{}`", part),
+                    }
+                }
+                None => {
+                    match Level::parse(part) {
+                        Some(level) => {
+                            parsed.default = level;
+                        }
+                        None => eprintln!("Unknown log level `{}`", part),
+                    }
+                }
+            }
+        }
+        parsed
+    }
+
+    // Noisiest level a target's records may have (the last override for it wins)
+    fn level(&self, target: &str) -> Level {
+        self.targets
+            .iter()
+            .rev()
+            .find(|(name, _)| name == target)
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    // Noisiest level any target lets through
+    fn max_level(&self) -> Level {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Level::max)
+    }
+}
+
+// Open outputs
+struct Sinks {
+    stderr: bool,
+    file: Option<RotatingFile>,
+}
+
+fn filter() -> &'static RwLock<Filter> {
+    static FILTER: OnceLock<RwLock<Filter>> = OnceLock::new();
+    FILTER.get_or_init(|| RwLock::new(Filter { default: Level::Info, targets: Vec::new() }))
+}
+
+fn sinks() -> &'static Mutex<Sinks> {
+    static SINKS: OnceLock<Mutex<Sinks>> = OnceLock::new();
+    SINKS.get_or_init(|| Mutex::new(Sinks { stderr: true, file: None }))
+}
+
+fn start() -> Instant {
+    static START: OnceLock<Instant> = OnceLock::new();
+    *START.get_or_init(Instant::now)
+}
+
+// Set up logging from a config (records logged before this go to stderr at the info level)
+pub fn init(config: &LogConfig) {
+    start();
+    let spec = std::env::var(FILTER_VAR).unwrap_or_else(|_| config.filter.to_string());
+    set_filter(&spec);
+    let file = config.file.and_then(|path| {
+        match RotatingFile::open(PathBuf::from(path), config.max_file_bytes, config.max_files) {
+            Ok(file) => Some(file),
+            Err(error) => {
+                eprintln!("Could not open log file {}: {}", path, error);
+                None
+            }
+        }
+    });
+    *sinks().lock().unwrap() = Sinks { stderr: config.stderr, file };
+    set_enabled(config.enabled);
+}
+
+// Replace the filter: a default level followed by `target=level` pairs, separated by commas
+pub fn set_filter(spec: &str) {
+    let parsed = Filter::parse(spec);
+    let max = parsed.max_level();
+    *filter().write().unwrap() = parsed;
+    MAX_LEVEL.store(max as u8, Ordering::Relaxed);
+    GENERATION.fetch_add(1, Ordering::Release);
+}
+
+// Turn logging on or off at runtime
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// Whether logging is on
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Whether a record of `level` for `target` would be written. Levels above `LOG_MAX_LEVEL` are
+// ruled out at compile time; otherwise this is an atomic load or two before the filter is read.
+// `log_event!` goes through a `Callsite` instead, which doesn't read the filter on every call.
+#[inline]
+pub fn enabled(level: Level, target: &str) -> bool {
+    passes_global_checks(level) && level <= filter().read().unwrap().level(target)
+}
+
+// The checks that don't depend on the target
+#[inline]
+fn passes_global_checks(level: Level) -> bool {
+    level <= LOG_MAX_LEVEL && ENABLED.load(Ordering::Relaxed) && (level as u8) <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+// The filter level of one `log_event!` call's target, looked up the first time the call passes
+// the global checks and again only after the filter changes. A callsite always logs to the same
+// target, so a hot one (like the per-ray trace in `cast_rays`) costs a few atomic loads.
+#[derive(Default)]
+pub struct Callsite {
+    state: AtomicU32, // Filter generation << 8 | level; generation 0 means not looked up yet
+}
+
+impl Callsite {
+    pub const fn new() -> Callsite {
+        Callsite { state: AtomicU32::new(0) }
+    }
+
+    // Like `enabled`, with the target's level cached
+    #[inline]
+    pub fn enabled(&self, level: Level, target: &str) -> bool {
+        if !passes_global_checks(level) {
+            return false;
+        }
+        let generation = GENERATION.load(Ordering::Acquire);
+        let state = self.state.load(Ordering::Relaxed);
+        let allowed = if state >> 8 == generation {
+            (state & 0xff) as u8
+        } else {
+            let allowed = filter().read().unwrap().level(target) as u8;
+            self.state.store((generation << 8) | (allowed as u32), Ordering::Relaxed);
+            allowed
+        };
+        (level as u8) <= allowed
+    }
+}
+
+// Write a record: `seconds LEVEL target: message key=value ...`. Use `log_event!`, which skips
+// formatting the message and fields when the record is filtered out.
+pub fn write(level: Level, target: &str, message: &str, fields: &[(&str, &dyn Debug)]) {
+    let mut line = format!(
+        "{:>10.3} {:<5} {}: {}",
+        start().elapsed().as_secs_f64(),
+        level.name().to_uppercase(),
+        target,
+        message
+    );
+    for (key, value) in fields {
+        let _ = write!(line, " {}={:?}", key, value);
+    }
+    let mut sinks = sinks().lock().unwrap();
+    if sinks.stderr {
+        eprintln!("{}", line);
+    }
+    if let Some(file) = &mut sinks.file {
+        if let Err(error) = file.write_line(&line) {
+            eprintln!("Could not write to log file: {}", error);
+            sinks.file = None;
+        }
+    }
+}
+
+// Log a record with optional key-value fields, e.g.
+// `log_event!(Level::Debug, "raycast", "ray"; column = r, distance = dist)`. Nothing after the
+// target is evaluated unless the record passes the filter. The target is a literal so each call
+// can cache its filter level.
+#[macro_export]
+macro_rules! log_event {
+    ($level:expr, $target:literal, $message:expr $(; $($key:ident = $value:expr),+ $(,)?)?) => {{
+        static CALLSITE: $crate::log::Callsite = $crate::log::Callsite::new();
+        let level = $level;
+        let target = $target;
+        if CALLSITE.enabled(level, target) {
+            $crate::log::write(level, target, &$message, &[$($((stringify!($key), &$value as &dyn ::std::fmt::Debug)),+)?]);
+        }
+    }};
+}
+
+// Log file that is moved aside (`name.1`, `name.2`, ...) once it grows past a size
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64, // Bytes in the current file
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> Result<RotatingFile, String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path).map_err(|e| e.to_string())?;
+        let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        Ok(RotatingFile { path, file, size, max_bytes, max_files })
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        if self.size > 0 && self.size + (line.len() as u64) + 1 > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line).map_err(|e| e.to_string())?;
+        self.size += (line.len() as u64) + 1;
+        Ok(())
+    }
+
+    // Shift `name.N-1` to `name.N` (dropping the oldest) and start an empty file
+    fn rotate(&mut self) -> Result<(), String> {
+        let numbered = |index: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", index));
+            PathBuf::from(name)
+        };
+        if self.max_files == 0 {
+            let _ = fs::remove_file(&self.path);
+        } else {
+            let _ = fs::remove_file(numbered(self.max_files));
+            for index in (1..self.max_files).rev() {
+                let _ = fs::rename(numbered(index), numbered(index + 1));
+            }
+            let _ = fs::rename(&self.path, numbered(1));
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)
+            .map_err(|e| e.to_string())?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_parses_the_default_and_targets() {
+        let filter = Filter::parse(" warn , raycast=TRACE,gl = debug");
+        assert_eq!(filter.default, Level::Warn);
+        assert_eq!(filter.level("raycast"), Level::Trace);
+        assert_eq!(filter.level("gl"), Level::Debug);
+        assert_eq!(filter.level("player"), Level::Warn);
+        assert_eq!(filter.max_level(), Level::Trace);
+    }
+
+    #[test]
+    fn filter_skips_unknown_levels() {
+        let filter = Filter::parse("loud,gl=chatty,raycast=off,,");
+        assert_eq!(filter.default, Level::Info);
+        assert_eq!(filter.level("gl"), Level::Info);
+        assert_eq!(filter.level("raycast"), Level::Off);
+        assert_eq!(filter.max_level(), Level::Info);
+    }
+
+    #[test]
+    fn later_overrides_win() {
+        let filter = Filter::parse("error,gl=debug,gl=warn");
+        assert_eq!(filter.level("gl"), Level::Warn);
+        assert_eq!(Filter::parse("").default, Level::Info);
+    }
+
+    #[test]
+    fn callsites_see_filter_changes() {
+        // Error and warn records are compiled in for every build, so this holds in release too
+        let callsite = Callsite::new();
+        set_filter("error,yggdrasil-test=warn");
+        assert!(callsite.enabled(Level::Warn, "yggdrasil-test"));
+        assert!(callsite.enabled(Level::Warn, "yggdrasil-test"));
+        set_filter("warn,yggdrasil-test=error");
+        assert!(!callsite.enabled(Level::Warn, "yggdrasil-test"));
+        assert!(callsite.enabled(Level::Error, "yggdrasil-test"));
+        set_filter("info");
+    }
 }
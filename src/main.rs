@@ -3,33 +3,96 @@ extern crate gl; // OpenGL bindings
 extern crate sdl2; // SDL2 bindings
 
 // --- Imports from Other Modules ---
-use crate::window_gl::{ HEIGHT, RAYS_COUNT, RENDER_X, RENDER_Y, WIDTH }; // Window dimensions
+use crate::window_gl::{ HEIGHT, WIDTH, RenderMode }; // Window dimensions
 use crate::draw_gl::VertexArrayWrapper; // Wrapper for vertex array management
+use crate::log::Level; // Log levels
+use crate::profiler::Phase; // Timed parts of a frame
 use sdl2::keyboard::Scancode; // Keyboard input handling
 use std::f32::consts::PI; // Mathematical constant for pi
-use std::ffi::CString; // String conversion for OpenGL
 
 // --- Submodules ---
+pub mod bench; // Software renderer benchmark
+pub mod camera; // Camera of the 2D debug view
 pub mod draw_gl; // OpenGL utilities
-pub mod log; // Logging utilities
+pub mod fog; // Distance fog for the software renderers
+pub mod gl_debug; // OpenGL error checks and driver debug output
+pub mod gpu_raycast; // Grid raycasting in a fragment shader
+pub mod inspector; // Step-by-step view of one column's ray
+pub mod light; // Point lights and the per-tile light cache
+pub mod lightmap; // Baked static lighting
+pub mod log; // Leveled, per-target logging to stderr and a rotating file
+pub mod palette; // 8-bit palette and colormap tables
+pub mod parallel; // Worker threads for the software renderer
 pub mod player; // Player logic
+pub mod portal; // Portal links between wall faces
+pub mod postfx; // Post-processing of the canvas (CRT, scanlines, grading, ...)
+pub mod profiler; // Frame timings, GPU timer queries and Chrome traces
+pub mod raydump; // Per-frame ray dumps (JSON/CSV) and their diff
 pub mod render_gl; // Shader and OpenGL program management
-pub mod square; // Map square representation
+pub mod sector; // Sector-based renderer with variable floor and ceiling heights
+pub mod segment; // Free-standing wall segments and their spatial index
+pub mod shader_reload; // Shader programs rebuilt from disk in dev builds
+pub mod simd; // Packed math for the software renderer
+pub mod sky; // Panoramic sky for open-ceiling areas
+pub mod text; // Bitmap font text for the overlay and the software frame
 pub mod window_gl; // SDL2 window and OpenGL context setup
 
 // --- Main Function ---
 fn main() {
+    // --- Logging ---
+    log::init(&window_gl::LOG);
+
+    // --- Offline Lightmap Bake (`--bake-lightmap`) ---
+    if std::env::args().any(|arg| arg == "--bake-lightmap") {
+        std::process::exit(bake_lightmap());
+    }
+
+    // --- Render Thread Count (`--threads <count>`, 0 = one per CPU core) ---
+    if let Some(args) = flag_args("--threads") {
+        match args.first().and_then(|arg| arg.parse().ok()) {
+            Some(count) => parallel::set_thread_count(count),
+            None => {
+                eprintln!("Usage: --threads <count> (0 = one per CPU core)");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // --- Ray Dump of a Pose (`--dump-rays <path> [x y dir]`) ---
+    if let Some(args) = flag_args("--dump-rays") {
+        std::process::exit(dump_rays(&args));
+    }
+
+    // --- Ray Dump Comparison (`--ray-diff <before> <after> [tolerance]`) ---
+    if let Some(args) = flag_args("--ray-diff") {
+        std::process::exit(ray_diff(&args));
+    }
+
+    // --- Software Renderer Benchmark (`--bench`) ---
+    if std::env::args().any(|arg| arg == "--bench") {
+        bench::run(&static_lights());
+        return;
+    }
+
     // --- State Variables ---
-    let mut _is_log = 0; // Toggle for logging/debugging
+    let mut render_mode: RenderMode = window_gl::CURRENT_RENDER_MODE; // Grid, sector or GPU renderer
+    let mut lantern = false; // Whether the player carries a light
+    let mut frame: u32 = 0; // Frame counter (drives light flicker)
+    let mut palette_mode = window_gl::CURRENT_PALETTE_MODE; // Render palette indices instead of RGB
+    let mut flash: f32 = 0.0; // Strength of the damage flash palette tint (fades out)
+    let mut post_fx = window_gl::POST_FX; // Post-processing chain settings
+    let mut debug_rays = window_gl::CURRENT_DEBUG_RAYS; // Draw every ray on the minimap, not just the FOV cone
+    let mut camera = camera::Camera2D::new(); // Zoom and pan of the 2D debug view
+    let mut panning = false; // Whether the debug view is being dragged
+    let mut message: Option<(String, u32)> = None; // In-game message and the frames it has left
+    let mut perf_overlay_shown = window_gl::CURRENT_PERF_OVERLAY; // Show FPS, phase times and the frame graph
 
     // Pixel buffer for the raycasted scene (used as a texture)
-    let mut _pixels: [[[u8; 3]; RENDER_X as usize]; RENDER_Y as usize] = [
-        [[0u8; 3]; RENDER_X as usize];
-        RENDER_Y as usize
-    ];
-    // Arrays to store horizontal and vertical ray distances for each column
-    let mut hrays: [f32; RAYS_COUNT as usize] = [0.0; RAYS_COUNT as usize];
-    let mut vrays: [f32; RAYS_COUNT as usize] = [0.0; RAYS_COUNT as usize];
+    let mut _pixels: draw_gl::FrameBuffer<[u8; 3]> = draw_gl::FrameBuffer::new([0u8; 3]);
+    // Palette index buffer used instead of `_pixels` in palette mode (one byte per pixel)
+    let mut _indices: draw_gl::FrameBuffer<u8> = draw_gl::FrameBuffer::new(0u8);
+    // Ray distances (horizontal, vertical, segment) and wall hits for each column
+    let mut rays = render_gl::RayBuffers::new();
 
     // --- SDL2 and OpenGL Initialization ---
     let sdl = sdl2::init().unwrap();
@@ -39,146 +102,729 @@ fn main() {
     let gl_attr = video_subsystem.gl_attr();
     gl_attr.set_context_profile(sdl2::video::GLProfile::Core);
     gl_attr.set_context_version(4, 1);
+    // Debug builds ask for a debug context so the driver reports problems through KHR_debug
+    if cfg!(debug_assertions) {
+        gl_attr.set_context_flags().debug().set();
+    }
 
     // Create the window and OpenGL context
-    let window = video_subsystem
+    let mut window = video_subsystem
         .window("Game", WIDTH, HEIGHT)
         .opengl()
         .resizable()
         .build()
         .unwrap();
     let _gl_context = window.gl_create_context().unwrap();
-    let _gl = gl::load_with(
+    gl::load_with(
         |s| video_subsystem.gl_get_proc_address(s) as *const std::os::raw::c_void
     );
 
-    // --- Shader Compilation ---
-    let vert_shader = render_gl::Shader
-        ::from_vert_source(&CString::new(include_str!("./shaders/triangle.vert")).unwrap())
-        .unwrap();
-    let frag_shader = render_gl::Shader
-        ::from_frag_source(&CString::new(include_str!("./shaders/triangle.frag")).unwrap())
-        .unwrap();
-    let tex_frag_shader = render_gl::Shader
-        ::from_frag_source(&CString::new(include_str!("./shaders/tex.frag")).unwrap())
-        .unwrap();
-    let tex_vert_shader = render_gl::Shader
-        ::from_vert_source(&CString::new(include_str!("./shaders/tex.vert")).unwrap())
-        .unwrap();
+    // Where the views sit in the window (laid out again whenever it is resized)
+    let (window_width, window_height) = window.size();
+    let mut layout = window_gl::Layout::new(window_width as i32, window_height as i32);
 
-    // --- Shader Program Linking ---
-    let shader_program = render_gl::Program::from_shaders(&[vert_shader, frag_shader]).unwrap();
-    let tex_shader_program = render_gl::Program
-        ::from_shaders(&[tex_vert_shader, tex_frag_shader])
-        .unwrap();
+    if cfg!(debug_assertions) {
+        gl_debug::enable_debug_output();
+    }
+
+    // --- Shader Compilation and Linking ---
+    // Dev builds read the shaders from `src/shaders` and rebuild them when the files change
+    let mut shader_program = built(
+        shader_reload::ShaderProgram::new(
+            vec![
+                shader_reload::ShaderSource::vertex("triangle.vert", include_str!("./shaders/triangle.vert")),
+                shader_reload::ShaderSource::fragment("triangle.frag", include_str!("./shaders/triangle.frag"))
+            ]
+        )
+    );
+    let mut minimap_shader_program = built(
+        shader_reload::ShaderProgram::new(
+            vec![
+                shader_reload::ShaderSource::vertex("minimap.vert", include_str!("./shaders/minimap.vert")),
+                shader_reload::ShaderSource::fragment("triangle.frag", include_str!("./shaders/triangle.frag"))
+            ]
+        )
+    );
+    let mut tex_shader_program = built(
+        shader_reload::ShaderProgram::new(
+            vec![
+                shader_reload::ShaderSource::vertex("tex.vert", include_str!("./shaders/tex.vert")),
+                shader_reload::ShaderSource::fragment("tex.frag", include_str!("./shaders/tex.frag"))
+            ]
+        )
+    );
+    let mut raycast_shader_program = built(
+        shader_reload::ShaderProgram::new(
+            vec![
+                shader_reload::ShaderSource::vertex("tex.vert", include_str!("./shaders/tex.vert")),
+                shader_reload::ShaderSource::fragment("raycast.frag", include_str!("./shaders/raycast.frag"))
+            ]
+        )
+    );
+    let mut text_shader_program = built(
+        shader_reload::ShaderProgram::new(
+            vec![
+                shader_reload::ShaderSource::vertex("text.vert", include_str!("./shaders/text.vert")),
+                shader_reload::ShaderSource::fragment("text.frag", include_str!("./shaders/text.frag"))
+            ]
+        )
+    );
+    let mut shader_error: Option<String> = None; // Error shown in the window title while a reload fails
 
     // --- OpenGL State Setup ---
     unsafe {
-        gl::Viewport(0, 0, WIDTH as i32, HEIGHT as i32); // Set viewport size
+        gl::Viewport(0, 0, layout.width, layout.height); // Set viewport size
         gl::ClearColor(0.3, 0.3, 0.5, 1.0); // Set background color
+        // Texture rows are tightly packed (1- and 3-byte pixels, odd widths), so every upload
+        // and read-back works on them byte-aligned instead of GL's default 4-byte row alignment
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
     }
 
     // --- Game State Initialization ---
     let mut player: player::Player = player::Player::new(200.0, 200.0); // Player starting position
 
-    // Texture manager for uploading the raycasted scene as a texture
-    let mut _texture_manager = draw_gl::TextureManager::new();
+    // Spatial index of the free-standing wall segments (built once, the segments are static)
+    let segment_index = segment::SegmentIndex::new(&window_gl::SEGMENTS);
+
+    // Static lighting, loaded from disk or baked at startup
+    let lightmap = lightmap::Lightmap::load_or_bake(
+        &lightmap::resolve_path(window_gl::LIGHTMAP_PATH),
+        &static_lights(),
+        window_gl::MAP_AMBIENT_LIGHT,
+        &segment_index
+    );
+
+    // Panoramic skies chosen by each level
+    let map_sky = sky::Sky::load_or_default(std::path::Path::new(window_gl::MAP_SKY_PATH));
+    let sector_sky = sky::Sky::load_or_default(std::path::Path::new(window_gl::SECTOR_SKY_PATH));
+
+    // Per-tile light values for the dynamic lights, updated around the lights that change
+    // (ambient light is already part of the lightmap)
+    let mut light_cache = light::LightCache::new([0.0; 3]);
+
+    // 8-bit palette with light and fog colormaps for each level
+    let palette = palette::Palette::ramps();
+    let map_colormap = palette::Colormap::new(&palette, window_gl::MAP_FOG);
+    let sector_colormap = palette::Colormap::new(&palette, window_gl::SECTOR_FOG);
+
+    // Map, sky, segment, portal, colormap and light textures for the GPU renderer
+    let mut gpu_raycaster = built(gpu_raycast::GpuRaycaster::new(&map_sky, &window_gl::SEGMENTS, &map_colormap));
+    let mut gpu_lighting_stale = true; // Light changed since the GPU renderer last uploaded it
+
+    // Canvas textures the raycasted scene is streamed into (RGB, or palette indices in palette mode)
+    let mut frame_texture = draw_gl::StreamTexture::<[u8; 3]>::new(window_gl::FRAME_UPLOAD_PBOS);
+    let mut indexed_frame_texture = draw_gl::StreamTexture::<u8>::new(window_gl::FRAME_UPLOAD_PBOS);
+    // The GPU renderer draws into the same textures instead of uploading them
+    let frame_target = built(draw_gl::RenderTarget::new(&frame_texture));
+    let indexed_frame_target = built(draw_gl::RenderTarget::new(&indexed_frame_texture));
+    // Texture holding the (possibly tinted) palette in palette mode
+    let palette_texture = draw_gl::TextureManager::new();
+
+    // Color grading table for post-processing, uploaded once as a 3D texture
+    let lut = postfx::ColorLut::load_or_default(std::path::Path::new(window_gl::POST_LUT_PATH));
+    let lut_texture = draw_gl::TextureManager::new();
+    let lut_uploaded = match lut_texture.load_lut(&lut) {
+        Ok(()) => true,
+        Err(error) => {
+            log_event!(Level::Error, "gl", "could not upload color LUT, running without grading"; error = error);
+            post_fx.grading = false;
+            false
+        }
+    };
 
-    // BufferArrayBinder manages VAO/VBO for rendering
-    let vbo_squares: gl::types::GLuint = 0;
-    let vao_squares: gl::types::GLuint = 0;
-    let mut bab: draw_gl::BufferArrayBinder = draw_gl::BufferArrayBinder::new(
-        vao_squares,
-        vbo_squares
+    // BufferArrayBinder manages VAO/VBO for rendering; the per-frame geometry is streamed into one
+    // buffer, the minimap tiles live in a static one that is only rebuilt when the map changes and
+    // the canvas quad is only uploaded again when the window is resized
+    let mut bab: draw_gl::BufferArrayBinder<draw_gl::ColorVertex> = draw_gl::BufferArrayBinder::new(gl::STREAM_DRAW);
+    let mut minimap = render_gl::MinimapMesh::new(&window_gl::MAP);
+    let mut canvas: draw_gl::BufferArrayBinder<draw_gl::TexturedVertex> = draw_gl::BufferArrayBinder::new(
+        gl::STATIC_DRAW
+    );
+    canvas.set_buffers(&render_gl::canvas_vertices(&layout));
+    // Quad covering a whole render target, for raycast.frag
+    let mut target_quad: draw_gl::BufferArrayBinder<draw_gl::TexturedVertex> = draw_gl::BufferArrayBinder::new(
+        gl::STATIC_DRAW
     );
+    target_quad.set_buffers(&render_gl::target_vertices());
+
+    // Font and the overlay text drawn over everything at the end of each frame
+    let font = match window_gl::FONT_PATH {
+        Some(path) => text::Font::load_or_default(std::path::Path::new(path)),
+        None => text::Font::builtin(),
+    };
+    let mut overlay = text::TextRenderer::new(font);
+
+    // Ray inspector (picking a column freezes the view until it is closed)
+    let mut inspector = inspector::RayInspector::new();
+
+    // Performance overlay and the GPU frame timer
+    let mut perf_overlay = profiler::PerfOverlay::new();
+    let mut gpu_timer = profiler::GpuTimer::new();
 
     // --- Main Game Loop ---
     let mut event_pump = sdl.event_pump().unwrap();
     'main: loop {
+        profiler::begin_frame();
+
         // --- Event Handling ---
-        for event in event_pump.poll_iter() {
-            match event {
-                sdl2::event::Event::Quit { .. } => {
-                    break 'main; // Exit the game loop on quit
+        {
+            let _scope = profiler::scope(Phase::Input);
+            for event in event_pump.poll_iter() {
+                match event {
+                    sdl2::event::Event::Quit { .. } => {
+                        break 'main; // Exit the game loop on quit
+                    }
+                    // --- Window Resized: lay the views out for the new size ---
+                    sdl2::event::Event::Window { win_event: sdl2::event::WindowEvent::SizeChanged(width, height), .. } => {
+                        layout = window_gl::Layout::new(width, height);
+                        unsafe {
+                            gl::Viewport(0, 0, layout.width, layout.height);
+                        }
+                        canvas.set_buffers(&render_gl::canvas_vertices(&layout));
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(Scancode::Tab), repeat: false, .. } => {
+                        render_mode = render_mode.next(); // Cycle through the grid, sector and GPU renderers
+                        message = Some((render_mode.name().to_string(), window_gl::MESSAGE_FRAMES));
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(Scancode::F), repeat: false, .. } => {
+                        lantern = !lantern; // Toggle the player's lantern
+                        message = Some((format!("Lantern {}", on_off(lantern)), window_gl::MESSAGE_FRAMES));
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(Scancode::P), repeat: false, .. } => {
+                        palette_mode = !palette_mode; // Switch between RGB and palette rendering
+                        message = Some((format!("Palette mode {}", on_off(palette_mode)), window_gl::MESSAGE_FRAMES));
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(Scancode::T), repeat: false, .. } => {
+                        // Double the render threads, wrapping back to one past the core count
+                        let threads = parallel::thread_count() * 2;
+                        let threads = if threads > parallel::core_count() { 1 } else { threads };
+                        parallel::set_thread_count(threads);
+                        message = Some((format!("Render threads: {}", threads), window_gl::MESSAGE_FRAMES));
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(Scancode::H), repeat: false, .. } => {
+                        flash = 1.0; // Start a damage flash (shown in palette mode)
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(Scancode::R), repeat: false, .. } => {
+                        debug_rays = !debug_rays; // Toggle the individual ray lines on the minimap
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(Scancode::L), repeat: false, .. } => {
+                        log::set_enabled(!log::is_enabled()); // Toggle logging
+                        message = Some((format!("Logging {}", on_off(log::is_enabled())), window_gl::MESSAGE_FRAMES));
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(Scancode::F3), repeat: false, .. } => {
+                        perf_overlay_shown = !perf_overlay_shown; // Toggle the performance overlay
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(Scancode::F4), repeat: false, .. } => {
+                        // Start recording a Chrome trace, or stop and write it
+                        if profiler::is_tracing() {
+                            let path = std::path::Path::new(window_gl::PROFILE_TRACE_PATH);
+                            let text = match profiler::stop_trace(path) {
+                                Ok(spans) => {
+                                    log_event!(Level::Info, "profiler", "trace written"; path = path.display(), spans = spans);
+                                    format!("Trace of {} spans written to {}", spans, path.display())
+                                }
+                                Err(error) => {
+                                    log_event!(Level::Error, "profiler", "could not write trace"; error = error);
+                                    format!("Could not write trace: {}", error)
+                                }
+                            };
+                            message = Some((text, window_gl::MESSAGE_FRAMES));
+                        } else {
+                            profiler::start_trace();
+                            message = Some((String::from("Recording trace"), window_gl::MESSAGE_FRAMES));
+                        }
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(Scancode::F5), repeat: false, .. } => {
+                        // Write every ray of the frame on screen (the player hasn't moved since it was cast)
+                        let path = std::path::Path::new(window_gl::RAY_DUMP_PATH);
+                        let dump = raydump::RayDump::capture(&player, &rays);
+                        let text = match dump.save(path) {
+                            Ok(()) => {
+                                log_event!(Level::Info, "raydump", "rays written"; path = path.display(), rays = dump.rays.len());
+                                format!("{} rays written to {}", dump.rays.len(), path.display())
+                            }
+                            Err(error) => {
+                                log_event!(Level::Error, "raydump", "could not write rays"; path = path.display(), error = error);
+                                format!("Could not write rays: {}", error)
+                            }
+                        };
+                        message = Some((text, window_gl::MESSAGE_FRAMES));
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(Scancode::C), repeat: false, .. } => {
+                        camera.toggle_follow(); // Keep the player centered in the debug view, or not
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(Scancode::Backspace), repeat: false, .. } => {
+                        camera.reset(); // Show the map at its original size and place again
+                    }
+                    // --- Debug View Camera: wheel zooms around the mouse, left-drag pans ---
+                    sdl2::event::Event::MouseWheel { precise_y, mouse_x, mouse_y, .. } if camera.contains(&layout, mouse_x, mouse_y) => {
+                        camera.zoom_at(mouse_x as f32, mouse_y as f32, (1.1f32).powf(precise_y));
+                    }
+                    // --- Ray Inspector: left-click a canvas column or right-click a ray in the debug view ---
+                    sdl2::event::Event::MouseButtonDown { mouse_btn: sdl2::mouse::MouseButton::Left, x, y, .. } => {
+                        match inspector::canvas_column(&layout, x, y) {
+                            Some(column) => inspector.select_column(column),
+                            None => {
+                                panning = camera.contains(&layout, x, y);
+                            }
+                        }
+                    }
+                    sdl2::event::Event::MouseButtonDown { mouse_btn: sdl2::mouse::MouseButton::Right, x, y, .. } if camera.contains(&layout, x, y) => {
+                        let [world_x, world_y] = camera.to_world(x as f32, y as f32);
+                        if !inspector.select_point(&player, world_x, world_y) {
+                            message = Some((String::from("No ray there"), window_gl::MESSAGE_FRAMES));
+                        }
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(Scancode::I), repeat: false, .. } => {
+                        inspector.close(); // Close the ray inspector and unfreeze the view
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(key @ (Scancode::Comma | Scancode::Period)), .. } if inspector.is_active() => {
+                        inspector.step_column(if key == Scancode::Comma { -1 } else { 1 });
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(key @ (Scancode::Left | Scancode::Right | Scancode::Up | Scancode::Down)), .. } if inspector.is_active() => {
+                        step_pose(&mut player, key, &segment_index); // Nudge the frozen player
+                    }
+                    sdl2::event::Event::MouseButtonUp { mouse_btn: sdl2::mouse::MouseButton::Left, .. } => {
+                        panning = false;
+                    }
+                    sdl2::event::Event::MouseMotion { xrel, yrel, .. } if panning => {
+                        camera.pan_by(xrel as f32, yrel as f32);
+                    }
+                    sdl2::event::Event::KeyDown { scancode: Some(key), repeat: false, .. } => {
+                        // Post-processing toggles
+                        match key {
+                            Scancode::Num1 => post_fx.crt = !post_fx.crt,
+                            Scancode::Num2 => post_fx.scanlines = !post_fx.scanlines,
+                            Scancode::Num3 => post_fx.vignette = !post_fx.vignette,
+                            Scancode::Num4 => post_fx.aberration = !post_fx.aberration,
+                            Scancode::Num5 => post_fx.grading = !post_fx.grading && lut_uploaded,
+                            Scancode::Num0 => post_fx.software = !post_fx.software,
+                            Scancode::Minus => post_fx.gamma = (post_fx.gamma - 0.1).max(0.5),
+                            Scancode::Equals => post_fx.gamma = (post_fx.gamma + 0.1).min(2.5),
+                            _ => {}
+                        }
+                    }
+                    _ => {}
                 }
-                _ => {}
             }
+
+            // --- Shader Hot-Reload (dev builds) ---
+            // A shader that fails to build leaves the last good program running; its error is shown in
+            // the window title (the full log goes to the console) until the file is fixed
+            shader_program.reload_if_changed();
+            minimap_shader_program.reload_if_changed();
+            tex_shader_program.reload_if_changed();
+            raycast_shader_program.reload_if_changed();
+            text_shader_program.reload_if_changed();
+            let error = [
+                &shader_program,
+                &minimap_shader_program,
+                &tex_shader_program,
+                &raycast_shader_program,
+                &text_shader_program,
+            ]
+                .iter()
+                .find_map(|program| program.error())
+                .map(|error| error.to_string());
+            if error != shader_error {
+                let title = match &error {
+                    Some(error) => format!("Game - shader error: {}", error.replace('\n', " | ")),
+                    None => String::from("Game"),
+                };
+                window.set_title(&title).unwrap();
+                shader_error = error;
+            }
+
+            // --- Player Input (the view stays frozen while the ray inspector is open) ---
+            if !inspector.is_active() {
+                player = get_input(&event_pump, player, &segment_index);
+            }
+            log_event!(Level::Debug, "player", "position"; x = player.x_pos, y = player.y_pos, dir = player.get_dir());
         }
 
-        // --- Vertex Construction ---
+        // --- Debug View Camera ---
+        camera.follow(&layout, player.x_pos + 4.0, player.y_pos + 4.0);
+
+        // --- Build the Per-Frame Vertices (player, lines, FOV cone) ---
         let mut vertices: VertexArrayWrapper = VertexArrayWrapper::new();
+        {
+            let _scope = profiler::scope(Phase::ConstructVertices);
+            render_gl::construct_vertices(
+                &player,
+                &segment_index,
+                &mut vertices,
+                &mut rays,
+                debug_rays
+            );
+        }
 
-        // --- Player Input ---
-        player = get_input(&event_pump, player);
+        // --- Ray Inspector: Trace the Picked Column's Ray Again, Step by Step ---
+        let inspected = inspector.column().map(|column| render_gl::inspect_ray(&player, &segment_index, column));
 
-        // --- Build All Vertices (map, player, lines, rays, canvas) ---
-        render_gl::construct_vertices(&player, &mut vertices, &mut hrays, &mut vrays, _is_log);
+        // --- Lighting: Animate Lights and Refresh the Light Cache ---
+        let mut lights: Vec<light::PointLight> = window_gl::LIGHTS.iter()
+            .enumerate()
+            .filter(|(_, light)| !light.is_static())
+            .map(|(i, light)| light.flickered(i as u32, frame))
+            .collect();
+        if lantern {
+            let lantern_light = light::PointLight {
+                x: player.x_pos + 4.0,
+                y: player.y_pos + 4.0,
+                ..window_gl::PLAYER_LANTERN
+            };
+            lights.push(lantern_light.flickered(lights.len() as u32, frame));
+        }
+        gpu_lighting_stale |= light_cache.update(&lights, &segment_index);
+        if !inspector.is_active() {
+            frame = frame.wrapping_add(1); // Lights hold still in a frozen view
+        }
+
+        // --- Software Rendering: Draw Walls to Pixel Buffer ---
+        let colors = window_gl::WALL_COLORS;
+        let lighting = lightmap::Lighting { baked: &lightmap, dynamic: &light_cache };
+        {
+            let _scope = profiler::scope(Phase::DrawWalls);
+            match (render_mode, palette_mode) {
+                (RenderMode::Grid, false) => {
+                    render_gl::draw_walls_to_pixels(
+                        &mut _pixels,
+                        &player,
+                        &rays,
+                        &colors,
+                        &window_gl::MAP_FOG,
+                        &lighting,
+                        &map_sky
+                    );
+                }
+                (RenderMode::Grid, true) => {
+                    render_gl::draw_walls_to_pixels(
+                        &mut _indices,
+                        &player,
+                        &rays,
+                        &colors,
+                        &map_colormap,
+                        &lighting,
+                        &map_sky
+                    );
+                }
+                (RenderMode::Sector, false) => {
+                    sector::draw_sectors_to_pixels(
+                        &mut _pixels,
+                        &player,
+                        &window_gl::SECTORS,
+                        &colors,
+                        &window_gl::SECTOR_FOG,
+                        &lighting,
+                        &sector_sky
+                    );
+                }
+                (RenderMode::Sector, true) => {
+                    sector::draw_sectors_to_pixels(
+                        &mut _indices,
+                        &player,
+                        &window_gl::SECTORS,
+                        &colors,
+                        &sector_colormap,
+                        &lighting,
+                        &sector_sky
+                    );
+                }
+                // raycast.frag draws straight into the canvas texture. Software post-processing works on
+                // the CPU, so then the frame is read back and carries on like a software frame.
+                (RenderMode::Gpu, _) => {
+                    if gpu_lighting_stale {
+                        // A failed upload leaves the previous light in place until the light changes again
+                        if let Err(error) = gpu_raycaster.update_lighting(&lighting) {
+                            log_event!(Level::Error, "gl", "could not upload light"; error = error);
+                        }
+                        gpu_lighting_stale = false;
+                    }
+                    raycast_shader_program.set_used();
+                    gpu_raycaster.set_uniforms(&raycast_shader_program, &player, &colors, &window_gl::MAP_FOG, palette_mode);
+                    if palette_mode {
+                        indexed_frame_target.draw_into(|| target_quad.draw_arrays(gl::TRIANGLES, 0..6));
+                        if post_fx.software {
+                            indexed_frame_target.read(&mut _indices);
+                        }
+                    } else {
+                        frame_target.draw_into(|| target_quad.draw_arrays(gl::TRIANGLES, 0..6));
+                        if post_fx.software {
+                            frame_target.read(&mut _pixels);
+                        }
+                    }
+                }
+            }
+        }
 
-        // --- Raycasting: Draw Walls to Pixel Buffer ---
-        render_gl::draw_walls_to_pixels(
-            &mut _pixels,
-            &hrays,
-            &vrays,
-            [120, 120, 120], // horizontal wall color (light gray)
-            [80, 80, 80], // vertical wall color (dark gray)
-            [30, 30, 60] // background color (dark blue)
-        );
+        // --- In-Game Message ---
+        // RGB software frames get it drawn into the scene (so post-processing applies to it too);
+        // palette and GPU frames show it through the overlay instead
+        let message_style = text::TextStyle::new([255, 255, 255], 1, text::Align::Center);
+        if let Some((text, frames)) = &mut message {
+            if render_mode != RenderMode::Gpu && !palette_mode {
+                text::draw_text_to_pixels(
+                    &mut _pixels,
+                    overlay.font(),
+                    text,
+                    window_gl::RENDER_X / 2,
+                    window_gl::RENDER_Y - 24,
+                    &message_style
+                );
+            } else {
+                // Centered on the canvas quad, near its bottom edge
+                let (canvas_x, canvas_y, canvas_width, canvas_height) = layout.canvas_view;
+                overlay.queue(
+                    text,
+                    canvas_x + canvas_width / 2,
+                    canvas_y + canvas_height - 42,
+                    &text::TextStyle { scale: 2, ..message_style }
+                );
+            }
+            *frames = frames.saturating_sub(1);
+        }
+        if let Some((_, 0)) = message {
+            message = None;
+        }
 
         // --- Upload Pixel Buffer as Texture ---
-        _texture_manager.load_texture(_pixels).unwrap();
+        {
+            let _scope = profiler::scope(Phase::TextureUpload);
+            // In palette mode only the indices and the 256-color palette are uploaded; a damage flash
+            // just swaps in a tinted palette
+            // GPU frames are already in their texture unless software post-processing read them back
+            let frame_on_gpu = render_mode == RenderMode::Gpu && !post_fx.software;
+            if palette_mode && !post_fx.software {
+                if !frame_on_gpu {
+                    indexed_frame_texture.upload(&_indices);
+                }
+                palette_texture
+                    .load_palette(&palette.tinted(palette::DAMAGE_FLASH_COLOR, flash * 0.6))
+                    .unwrap();
+            } else if !frame_on_gpu {
+                // Software post-processing works on RGB, so palette frames are expanded on the CPU first
+                if palette_mode {
+                    let colors = palette.tinted(palette::DAMAGE_FLASH_COLOR, flash * 0.6);
+                    palette::expand(&_indices, &colors, &mut _pixels);
+                }
+                if post_fx.software {
+                    post_fx.apply(&mut _pixels, &lut);
+                }
+                frame_texture.upload(&_pixels);
+            }
+            flash = (flash - 0.05).max(0.0);
+        }
 
         // --- Bind Vertex Data to Buffers ---
-        bab.set_buffers(&vertices.points());
-        bab.set_vertex_attribs(3, 6, 3);
+        {
+            let _scope = profiler::scope(Phase::GlDraw);
+            gpu_timer.begin();
+            bab.set_buffers(vertices.vertices());
+            minimap.update(&window_gl::MAP);
 
-        // --- Clear the Screen ---
-        unsafe {
-            gl::Clear(gl::COLOR_BUFFER_BIT);
-        }
+            // --- Clear the Screen ---
+            unsafe {
+                gl::Clear(gl::COLOR_BUFFER_BIT);
+            }
 
-        // --- Draw Map, Player, and Lines (non-textured geometry) ---
-        shader_program.set_used();
-        bab.set_vertex_attribs(3, 6, 3); // 3 pos, 3 color, stride 6
-        bab.draw_arrays(gl::TRIANGLES, 6, 0, vertices.triangle_end() as i32);
-        bab.draw_arrays(gl::LINES, 6, vertices.triangle_end() as i32, vertices.line_end() as i32);
-
-        // --- Draw Canvas (textured quad) ---
-        tex_shader_program.set_used();
-        // For canvas: 3 pos, 3 color, 2 texcoord = 8 floats per vertex
-        bab.set_vertex_attribs(3, 8, 3);
-        // Set up texcoord attribute (location 2)
-        unsafe {
-            gl::EnableVertexAttribArray(2);
-            gl::VertexAttribPointer(
-                2, // location 2 in shader
-                2, // 2 floats for texcoord
-                gl::FLOAT,
-                gl::FALSE,
-                (8 * std::mem::size_of::<f32>()) as gl::types::GLint,
-                (6 * std::mem::size_of::<f32>()) as *const gl::types::GLvoid
-            );
-            gl::ActiveTexture(gl::TEXTURE0);
-            gl::BindTexture(gl::TEXTURE_2D, _texture_manager.id);
-            let tex_loc = gl::GetUniformLocation(
-                tex_shader_program.id(),
-                b"tex\0".as_ptr() as *const _
-            );
-            gl::Uniform1i(tex_loc, 0);
+            // --- Draw Map, Player, and Lines (non-textured geometry) ---
+            // Everything here is in world units, placed by the camera and clipped to the debug view
+            let view = camera.matrix(&layout);
+            camera.begin_clip(&layout);
+            minimap_shader_program.set_used();
+            minimap_shader_program.set_mat3("view", &view);
+            minimap.draw(&minimap_shader_program);
+            shader_program.set_used();
+            shader_program.set_mat3("view", &view);
+            // The cone goes under the player and the lines
+            bab.draw_arrays(gl::TRIANGLE_FAN, vertices.fan());
+            bab.draw_arrays(gl::TRIANGLES, vertices.triangles());
+            bab.draw_arrays(gl::LINES, vertices.lines());
+            if let Some(report) = &inspected {
+                inspector.draw_highlight(report);
+            }
+            camera.end_clip();
+
+            // --- Draw Canvas (textured quad) ---
+            // Palette frames are expanded on the GPU unless software post-processing already did it
+            let indexed = palette_mode && !post_fx.software;
+            tex_shader_program.set_used();
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE0);
+                let frame_id = if indexed { indexed_frame_texture.id() } else { frame_texture.id() };
+                gl::BindTexture(gl::TEXTURE_2D, frame_id);
+
+                // Palette lookup texture on unit 1 (only sampled for indexed frames)
+                gl::ActiveTexture(gl::TEXTURE1);
+                gl::BindTexture(gl::TEXTURE_2D, palette_texture.id);
+
+                // Color grading LUT on unit 2
+                gl::ActiveTexture(gl::TEXTURE2);
+                gl::BindTexture(gl::TEXTURE_3D, lut_texture.id);
+                gl::ActiveTexture(gl::TEXTURE0);
+            }
+            tex_shader_program.set_sampler("tex", 0);
+            tex_shader_program.set_sampler("palette", 1);
+            tex_shader_program.set_bool("indexed", indexed);
+            tex_shader_program.set_sampler("lut", 2);
+            post_fx.set_uniforms(&tex_shader_program, &lut);
+            canvas.draw_arrays(gl::TRIANGLES, 0..6);
+
+            // --- Overlay Text ---
+            // A shader that failed to reload shows its error under the views until the file is fixed
+            if let Some(error) = &shader_error {
+                let style = text::TextStyle::new([255, 90, 90], 1, text::Align::Left);
+                let shown: Vec<&str> = error.lines().take(16).collect();
+                // Left-aligned with the debug view, just below the canvas
+                let (debug_x, _, _, _) = layout.debug_view;
+                let (_, canvas_y, _, canvas_height) = layout.canvas_view;
+                overlay.queue(&format!("Shader error:\n{}", shown.join("\n")), debug_x + 8, canvas_y + canvas_height + 12, &style);
+            }
+            if let Some(report) = &inspected {
+                inspector.draw_panel(&shader_program, &mut overlay, report, &layout);
+            }
+            if perf_overlay_shown {
+                perf_overlay.draw(&shader_program, &mut overlay, &layout);
+            }
+            overlay.draw(&text_shader_program, &layout);
+            gpu_timer.end();
         }
-        bab.draw_arrays(
-            gl::TRIANGLES,
-            8, // vertex size for canvas
-            vertices.line_end() as i32,
-            vertices.len() as i32
-        );
 
         // --- Swap the Window Buffer (display the frame) ---
-        window.gl_swap_window();
+        {
+            let _scope = profiler::scope(Phase::Swap);
+            window.gl_swap_window();
+        }
+        profiler::end_frame();
+    }
+}
+
+// --- "on" or "off" for a toggle message ---
+fn on_off(enabled: bool) -> &'static str {
+    if enabled { "on" } else { "off" }
+}
+
+// --- Stop with a Readable Message if a Shader or Program Failed to Build ---
+fn built<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|error| {
+        log_event!(Level::Error, "gl", error);
+        std::process::exit(1);
+    })
+}
+
+// --- Static Lights (the ones baked into the lightmap) ---
+fn static_lights() -> Vec<light::PointLight> {
+    window_gl::LIGHTS.iter()
+        .filter(|light| light.is_static())
+        .copied()
+        .collect()
+}
+
+// --- Bake the Lightmap and Write It to Disk; Returns the Exit Code ---
+fn bake_lightmap() -> i32 {
+    let segment_index = segment::SegmentIndex::new(&window_gl::SEGMENTS);
+    let lightmap = lightmap::Lightmap::bake(
+        &static_lights(),
+        window_gl::MAP_AMBIENT_LIGHT,
+        &segment_index
+    );
+    let path = lightmap::resolve_path(window_gl::LIGHTMAP_PATH);
+    match lightmap.save(&path) {
+        Ok(()) => {
+            println!("Lightmap written to {}", path.display());
+            0
+        }
+        Err(error) => {
+            log_event!(Level::Error, "lightmap", "could not save"; path = path.display(), error = error);
+            1
+        }
+    }
+}
+
+// --- Arguments Following a Command-Line Flag (None if the flag isn't given) ---
+fn flag_args(flag: &str) -> Option<Vec<String>> {
+    let args: Vec<String> = std::env::args().collect();
+    let at = args.iter().position(|arg| arg == flag)?;
+    Some(args[at + 1..].iter().take_while(|arg| !arg.starts_with("--")).cloned().collect())
+}
+
+// --- Write Every Ray Seen from a Pose (the starting pose by default); Returns the Exit Code ---
+fn dump_rays(args: &[String]) -> i32 {
+    let path = match args.first() {
+        Some(path) => std::path::Path::new(path),
+        None => {
+            eprintln!("Usage: --dump-rays <path.json|path.csv> [x y dir]");
+            return 2;
+        }
+    };
+    let numbers: Result<Vec<f32>, _> = args[1..].iter().map(|arg| arg.parse::<f32>()).collect();
+    let width = (window_gl::MAP_X * window_gl::MAP_S) as f32;
+    let height = (window_gl::MAP_Y * window_gl::MAP_S) as f32;
+    let (x, y, dir) = match numbers.as_deref() {
+        Ok([]) => (200.0, 200.0, 0.0),
+        Ok(&[x, y, dir]) if (0.0..width).contains(&x) && (0.0..height).contains(&y) && dir.is_finite() => (x, y, dir),
+        _ => {
+            eprintln!("Bad pose `{}`: expected x and y inside the map and a direction", args[1..].join(" "));
+            return 2;
+        }
+    };
+    let mut player = player::Player::new(x, y);
+    player.update_dir(dir.rem_euclid(2.0 * PI));
+    let segment_index = segment::SegmentIndex::new(&window_gl::SEGMENTS);
+    let mut rays = render_gl::RayBuffers::new();
+    render_gl::cast_rays(&mut VertexArrayWrapper::new(), &player, &segment_index, &mut rays, false);
+    let dump = raydump::RayDump::capture(&player, &rays);
+    match dump.save(path) {
+        Ok(()) => {
+            println!("{} rays written to {}", dump.rays.len(), path.display());
+            0
+        }
+        Err(error) => {
+            eprintln!("Could not write rays: {}", error);
+            1
+        }
+    }
+}
+
+// --- Compare Two Ray Dumps; the Exit Code Is 1 if They Differ ---
+fn ray_diff(args: &[String]) -> i32 {
+    let (before, after) = match args {
+        [before, after, ..] => (std::path::Path::new(before), std::path::Path::new(after)),
+        _ => {
+            eprintln!("Usage: --ray-diff <before> <after> [tolerance]");
+            return 2;
+        }
+    };
+    let tolerance = args.get(2).and_then(|arg| arg.parse().ok()).unwrap_or(0.0);
+    let dumps = raydump::RayDump::load(before).and_then(|before| Ok((before, raydump::RayDump::load(after)?)));
+    let (before, after) = match dumps {
+        Ok(dumps) => dumps,
+        Err(error) => {
+            eprintln!("Could not read ray dump {}", error);
+            return 2;
+        }
+    };
+    if before.pose != after.pose {
+        println!("Note: the dumps were cast from different poses ({:?} and {:?})", before.pose, after.pose);
+    }
+    let changes = raydump::diff(&before, &after, tolerance);
+    for change in &changes {
+        println!("ray {:>3} {:<14} {} -> {}", change.ray, change.field, change.before, change.after);
     }
+    let mut rays: Vec<usize> = changes.iter().map(|change| change.ray).collect();
+    rays.sort_unstable();
+    rays.dedup();
+    println!("{} of {} rays differ", rays.len(), before.rays.len().max(after.rays.len()));
+    if changes.is_empty() { 0 } else { 1 }
 }
 
 // --- Handle Player Input (WASD movement and rotation) ---
-fn get_input(event_pump: &sdl2::EventPump, mut player: player::Player) -> player::Player {
+fn get_input(
+    event_pump: &sdl2::EventPump,
+    mut player: player::Player,
+    segments: &segment::SegmentIndex
+) -> player::Player {
     if event_pump.keyboard_state().is_scancode_pressed(Scancode::A) {
         player.update_dir(player.get_dir() - 0.03);
         if player.get_dir() < 0.0 {
@@ -199,18 +845,66 @@ fn get_input(event_pump: &sdl2::EventPump, mut player: player::Player) -> player
     if event_pump.keyboard_state().is_scancode_pressed(Scancode::W) {
         let dx = player.get_x_dir() * 1.1;
         let dy = player.get_y_dir() * 1.1;
-        try_move_player(&mut player, dx, dy);
+        try_move_player(&mut player, dx, dy, segments);
     }
     if event_pump.keyboard_state().is_scancode_pressed(Scancode::S) {
         let dx = -player.get_x_dir() * 1.1;
         let dy = -player.get_y_dir() * 1.1;
-        try_move_player(&mut player, dx, dy);
+        try_move_player(&mut player, dx, dy, segments);
     }
     player
 }
 
+// --- Step the Player's Pose by Hand (ray inspector): arrows turn by one ray or move one unit ---
+fn step_pose(player: &mut player::Player, key: Scancode, segments: &segment::SegmentIndex) {
+    let turn = window_gl::FOV / (window_gl::RAYS_COUNT as f32);
+    match key {
+        Scancode::Left | Scancode::Right => {
+            let mut dir = player.get_dir() + if key == Scancode::Left { -turn } else { turn };
+            if dir < 0.0 {
+                dir += 2.0 * PI;
+            } else if dir > 2.0 * PI {
+                dir -= 2.0 * PI;
+            }
+            player.update_dir(dir);
+            player.update_x_dir(player.get_dir().cos());
+            player.update_y_dir(player.get_dir().sin());
+        }
+        Scancode::Up => try_move_player(player, player.get_dir().cos(), player.get_dir().sin(), segments),
+        Scancode::Down => try_move_player(player, -player.get_dir().cos(), -player.get_dir().sin(), segments),
+        _ => {}
+    }
+}
+
+// --- Whether the Player's Box at (x, y) Overlaps a Wall Cell, the Map Edge or a Wall Segment ---
+fn position_blocked(x: f32, y: f32, segments: &segment::SegmentIndex) -> bool {
+    let map_s = window_gl::MAP_S as f32;
+    for &(corner_x, corner_y) in &[(x, y), (x + 8.0, y), (x, y + 8.0), (x + 8.0, y + 8.0)] {
+        if corner_x < 0.0 || corner_y < 0.0 {
+            return true;
+        }
+        let cell = window_gl::MAP
+            .get((corner_y / map_s) as usize)
+            .and_then(|row| row.get((corner_x / map_s) as usize));
+        if cell != Some(&window_gl::TILE_EMPTY) {
+            return true;
+        }
+    }
+    segments.intersects_rect(x, y, x + 8.0, y + 8.0)
+}
+
 // --- Attempt to Move Player (collision detection and response) ---
-fn try_move_player(player: &mut player::Player, dx: f32, dy: f32) {
+fn try_move_player(
+    player: &mut player::Player,
+    dx: f32,
+    dy: f32,
+    segments: &segment::SegmentIndex
+) {
+    // Walking into a portal face moves the player to the linked face instead of colliding
+    if try_portal_teleport(player, dx, dy, segments) {
+        return;
+    }
+
     // Map constants (adjust if your map is not 8x8 or MAP_S is not 64)
     let map = window_gl::MAP;
     let map_s = window_gl::MAP_S as f32;
@@ -236,9 +930,9 @@ fn try_move_player(player: &mut player::Player, dx: f32, dy: f32) {
     }
 
     // If the intended cell is a wall, snap to the edge
-    if map[cell_y][cell_x] == 1 {
+    if map[cell_y][cell_x] != window_gl::TILE_EMPTY {
         // Snap X
-        if map[(player.y_pos / map_s) as usize][cell_x] == 1 {
+        if map[(player.y_pos / map_s) as usize][cell_x] != window_gl::TILE_EMPTY {
             // Blocked in X direction, snap to edge
             if dx > 0.0 {
                 new_x = (cell_x as f32) * map_s - 8.01;
@@ -249,7 +943,7 @@ fn try_move_player(player: &mut player::Player, dx: f32, dy: f32) {
             }
         }
         // Snap Y
-        if map[cell_y][(player.x_pos / map_s) as usize] == 1 {
+        if map[cell_y][(player.x_pos / map_s) as usize] != window_gl::TILE_EMPTY {
             // Blocked in Y direction, snap to edge
             if dy > 0.0 {
                 new_y = (cell_y as f32) * map_s - 8.01;
@@ -261,6 +955,78 @@ fn try_move_player(player: &mut player::Player, dx: f32, dy: f32) {
         }
     }
 
+    // Wall segments block the player's box; slide along them one axis at a time
+    if segments.intersects_rect(new_x, new_y, new_x + 8.0, new_y + 8.0) {
+        if !segments.intersects_rect(new_x, player.y_pos, new_x + 8.0, player.y_pos + 8.0) {
+            new_y = player.y_pos;
+        } else if !segments.intersects_rect(player.x_pos, new_y, player.x_pos + 8.0, new_y + 8.0) {
+            new_x = player.x_pos;
+        } else {
+            return; // Blocked on both axes, don't move
+        }
+    }
+
     // If not colliding, or after snapping, update position
     player.update_pos(new_x, new_y);
 }
+
+// --- Attempt to Teleport Player (when the move would enter a portal face) ---
+fn try_portal_teleport(player: &mut player::Player, dx: f32, dy: f32, segments: &segment::SegmentIndex) -> bool {
+    let map_s = window_gl::MAP_S as f32;
+
+    // Player center and the cell it is in
+    let center_x = player.x_pos + 4.0;
+    let center_y = player.y_pos + 4.0;
+    let cell_x = (center_x / map_s).floor() as i32;
+    let cell_y = (center_y / map_s).floor() as i32;
+
+    // For each axis of motion: the cell the leading edge would enter, the face it would cross,
+    // and the point on that face in line with the player's center
+    let mut crossings = Vec::new();
+    if dx != 0.0 {
+        let edge_x = center_x + dx + 4.0 * dx.signum();
+        let target_x = (edge_x / map_s).floor() as i32;
+        let (face, face_x) = if dx > 0.0 {
+            (portal::Face::West, (target_x as f32) * map_s)
+        } else {
+            (portal::Face::East, ((target_x as f32) + 1.0) * map_s)
+        };
+        crossings.push((target_x, cell_y, face, face_x, center_y));
+    }
+    if dy != 0.0 {
+        let edge_y = center_y + dy + 4.0 * dy.signum();
+        let target_y = (edge_y / map_s).floor() as i32;
+        let (face, face_y) = if dy > 0.0 {
+            (portal::Face::North, (target_y as f32) * map_s)
+        } else {
+            (portal::Face::South, ((target_y as f32) + 1.0) * map_s)
+        };
+        crossings.push((cell_x, target_y, face, center_x, face_y));
+    }
+
+    for (target_x, target_y, face, point_x, point_y) in crossings {
+        if target_x == cell_x && target_y == cell_y {
+            continue; // Still inside the current cell, nothing crossed
+        }
+        if let Some(link) = portal::find_link(target_x, target_y, face) {
+            // Come out at the matching point on the exit face, just clear of the wall
+            let (exit_x, exit_y) = link.transform_point(point_x, point_y);
+            let (nx, ny) = link.exit().face.normal();
+            let new_center_x = exit_x + nx * 4.01;
+            let new_center_y = exit_y + ny * 4.01;
+            if position_blocked(new_center_x - 4.0, new_center_y - 4.0, segments) {
+                // Something stands right in front of the exit; the portal acts as a wall
+                log_event!(Level::Debug, "player", "portal exit blocked"; x = new_center_x, y = new_center_y);
+                return false;
+            }
+            player.update_pos(new_center_x - 4.0, new_center_y - 4.0);
+
+            // Keep the heading relative to the portal
+            player.update_dir(link.transform_angle(player.get_dir()));
+            player.update_x_dir(player.get_dir().cos());
+            player.update_y_dir(player.get_dir().sin());
+            return true;
+        }
+    }
+    false
+}
@@ -0,0 +1,329 @@
+// Import the fog settings, the frame layout and the renderers' shading interface
+use crate::draw_gl::FrameRows;
+use crate::fog::Fog;
+use crate::render_gl::Shading;
+
+// Number of colors in a palette (one byte per pixel)
+pub const PALETTE_SIZE: usize = 256;
+// Brightness steps in the light colormap, from black up to `MAX_LIGHT`
+pub const LIGHT_LEVELS: usize = 32;
+// Brightest light the colormap can show (overlapping lights may brighten colors up to 2x)
+pub const MAX_LIGHT: f32 = 2.0;
+// Steps in the fog colormap, from no fog to only the fog color
+pub const FOG_LEVELS: usize = 16;
+// Color the palette is tinted towards for a damage flash
+pub const DAMAGE_FLASH_COLOR: [u8; 3] = [200, 0, 0];
+// Bits kept per channel in the RGB-to-index lookup table
+pub const INVERSE_BITS: u32 = 5;
+
+// Base colors of the default palette: each becomes a ramp of 16 shades from dark to full
+const RAMP_COLORS: [[u8; 3]; 16] = [
+    [255, 255, 255], // Gray
+    [255, 220, 170], // Warm white
+    [170, 190, 255], // Cool white
+    [60, 60, 140], // Dusk blue
+    [30, 40, 90], // Navy
+    [150, 80, 160], // Purple
+    [230, 130, 130], // Rose
+    [120, 50, 70], // Wine
+    [220, 50, 40], // Red
+    [240, 150, 80], // Orange
+    [240, 220, 90], // Yellow
+    [200, 160, 120], // Tan
+    [150, 120, 90], // Brown
+    [140, 140, 80], // Olive
+    [90, 160, 90], // Green
+    [80, 150, 150], // Teal
+];
+
+// A 256-color palette with a lookup table from RGB to the nearest palette index
+pub struct Palette {
+    colors: [[u8; 3]; PALETTE_SIZE], // RGB value of each index
+    inverse: Vec<u8>, // Nearest index for each RGB value, `INVERSE_BITS` bits per channel
+}
+
+impl Palette {
+    // Constructor to create a Palette from its colors
+    pub fn new(colors: [[u8; 3]; PALETTE_SIZE]) -> Palette {
+        let steps = 1usize << INVERSE_BITS;
+        let shift = 8 - INVERSE_BITS;
+        let mut inverse = Vec::with_capacity(steps * steps * steps);
+        for r in 0..steps {
+            for g in 0..steps {
+                for b in 0..steps {
+                    // Match against the middle of each bucket
+                    let center = |c: usize| ((c << shift) | (1 << (shift - 1))) as u8;
+                    inverse.push(nearest_color(&colors, [center(r), center(g), center(b)]));
+                }
+            }
+        }
+        Palette { colors, inverse }
+    }
+
+    // The default palette: 16 color ramps of 16 shades each
+    pub fn ramps() -> Palette {
+        let mut colors = [[0u8; 3]; PALETTE_SIZE];
+        for (ramp, base) in RAMP_COLORS.iter().enumerate() {
+            for shade in 0..16 {
+                let scale = ((shade + 1) as f32) / 16.0;
+                colors[ramp * 16 + shade] = base.map(|c| ((c as f32) * scale).round() as u8);
+            }
+        }
+        Palette::new(colors)
+    }
+
+    // Get the RGB value of every index
+    pub fn colors(&self) -> &[[u8; 3]; PALETTE_SIZE] {
+        &self.colors
+    }
+
+    // Index of the palette color closest to an RGB value
+    pub fn nearest(&self, color: [u8; 3]) -> u8 {
+        inverse_lookup(&self.inverse, color)
+    }
+
+    // Copy of the colors blended towards `tint` by `amount` (0.0 - 1.0), for palette-swap
+    // effects such as damage flashes; the indices in the pixel buffer stay the same
+    pub fn tinted(&self, tint: [u8; 3], amount: f32) -> [[u8; 3]; PALETTE_SIZE] {
+        let amount = amount.clamp(0.0, 1.0);
+        let mut colors = self.colors;
+        for color in colors.iter_mut() {
+            for (channel, &target) in color.iter_mut().zip(tint.iter()) {
+                let from = *channel as f32;
+                *channel = (from + ((target as f32) - from) * amount).round() as u8;
+            }
+        }
+        colors
+    }
+}
+
+// Precomputed light and fog tables for one palette and fog setting, as in classic engines:
+// lighting and fog map a palette index straight to another palette index
+pub struct Colormap {
+    inverse: Vec<u8>, // RGB-to-index lookup of the palette
+    light: Vec<[u8; PALETTE_SIZE]>, // Per light level, the index each color darkens or brightens to
+    fog: Vec<[u8; PALETTE_SIZE]>, // Per fog level, the index each color fades to
+    settings: Fog, // Fog curve used to pick a fog level from distance
+}
+
+impl Colormap {
+    // Build the tables for a palette and fog setting
+    pub fn new(palette: &Palette, settings: Fog) -> Colormap {
+        let colors = palette.colors();
+        let light = (0..LIGHT_LEVELS)
+            .map(|level| {
+                let amount = ((level as f32) / ((LIGHT_LEVELS - 1) as f32)) * MAX_LIGHT;
+                colors.map(|color| {
+                    nearest_color(colors, color.map(|c| ((c as f32) * amount).round().min(255.0) as u8))
+                })
+            })
+            .collect();
+        let fog = (0..FOG_LEVELS)
+            .map(|level| {
+                let amount = (level as f32) / ((FOG_LEVELS - 1) as f32);
+                let mut table = [0u8; PALETTE_SIZE];
+                for (entry, color) in table.iter_mut().zip(colors.iter()) {
+                    let mut fogged = *color;
+                    for (channel, &target) in fogged.iter_mut().zip(settings.color.iter()) {
+                        let from = *channel as f32;
+                        *channel = (from + ((target as f32) - from) * amount).round() as u8;
+                    }
+                    *entry = nearest_color(colors, fogged);
+                }
+                table
+            })
+            .collect();
+        Colormap { inverse: palette.inverse.clone(), light, fog, settings }
+    }
+
+    // RGB-to-index lookup, `INVERSE_BITS` bits per channel (red, then green, then blue)
+    pub fn inverse(&self) -> &[u8] {
+        &self.inverse
+    }
+
+    // Light tables, from black up to `MAX_LIGHT`
+    pub fn light_tables(&self) -> &[[u8; PALETTE_SIZE]] {
+        &self.light
+    }
+
+    // Fog tables, from no fog up to the full fog color
+    pub fn fog_tables(&self) -> &[[u8; PALETTE_SIZE]] {
+        &self.fog
+    }
+
+    // Index a color turns into under a light value. Palette lighting only has brightness, so
+    // colored light is reduced to its luminance.
+    pub fn light_index(&self, index: u8, light: [f32; 3]) -> u8 {
+        let luminance = light[0] * 0.299 + light[1] * 0.587 + light[2] * 0.114;
+        let level = ((luminance / MAX_LIGHT) * ((LIGHT_LEVELS - 1) as f32)).round();
+        self.light[(level.max(0.0) as usize).min(LIGHT_LEVELS - 1)][index as usize]
+    }
+
+    // Index a color turns into when seen through fog at the given distance
+    pub fn fog_index(&self, index: u8, dist: f32) -> u8 {
+        let level = (self.settings.factor(dist) * ((FOG_LEVELS - 1) as f32)).round() as usize;
+        self.fog[level.min(FOG_LEVELS - 1)][index as usize]
+    }
+}
+
+// Indexed rendering: pick the base color's palette index, then look up light and fog
+impl Shading for Colormap {
+    type Pixel = u8;
+
+    fn surface(&self, color: [u8; 3], light: [f32; 3], dist: f32) -> u8 {
+        self.fog_index(self.light_index(inverse_lookup(&self.inverse, color), light), dist)
+    }
+
+    fn unlit(&self, color: [u8; 3]) -> u8 {
+        inverse_lookup(&self.inverse, color)
+    }
+}
+
+// Expand a frame of palette indices to RGB through `colors` (what tex.frag does on the GPU), for
+// CPU work that needs RGB such as the software post-processing chain
+pub fn expand(indices: &FrameRows<u8>, colors: &[[u8; 3]; PALETTE_SIZE], pixels: &mut FrameRows<[u8; 3]>) {
+    for (row, indices) in pixels.iter_mut().zip(indices.iter()) {
+        for (pixel, &index) in row.iter_mut().zip(indices.iter()) {
+            *pixel = colors[index as usize];
+        }
+    }
+}
+
+// Search a palette for the color closest to `color` (squared RGB distance)
+fn nearest_color(colors: &[[u8; 3]; PALETTE_SIZE], color: [u8; 3]) -> u8 {
+    let mut best = 0;
+    let mut best_dist = i32::MAX;
+    for (index, candidate) in colors.iter().enumerate() {
+        let dist: i32 = candidate
+            .iter()
+            .zip(color.iter())
+            .map(|(&a, &b)| {
+                let d = (a as i32) - (b as i32);
+                d * d
+            })
+            .sum();
+        if dist < best_dist {
+            best = index;
+            best_dist = dist;
+        }
+    }
+    best as u8
+}
+
+// Nearest palette index for an RGB value, from a table built by `Palette::new`
+fn inverse_lookup(inverse: &[u8], color: [u8; 3]) -> u8 {
+    let shift = 8 - INVERSE_BITS;
+    let [r, g, b] = color.map(|c| (c >> shift) as usize);
+    inverse[(r << (2 * INVERSE_BITS)) | (g << INVERSE_BITS) | b]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window_gl::{ MAP_FOG, RENDER_X, RENDER_Y };
+
+    fn luminance(color: [u8; 3]) -> f32 {
+        (color[0] as f32) * 0.299 + (color[1] as f32) * 0.587 + (color[2] as f32) * 0.114
+    }
+
+    #[test]
+    fn nearest_color_finds_exact_entries() {
+        let palette = Palette::ramps();
+        for color in palette.colors() {
+            assert_eq!(palette.colors()[nearest_color(palette.colors(), *color) as usize], *color);
+        }
+    }
+
+    #[test]
+    fn lookup_table_stays_close_to_the_exact_search() {
+        let palette = Palette::ramps();
+        let error = |color: [u8; 3], index: u8| -> i32 {
+            palette.colors()[index as usize]
+                .iter()
+                .zip(color.iter())
+                .map(|(&a, &b)| ((a as i32) - (b as i32)).pow(2))
+                .sum()
+        };
+        for r in (0..=255).step_by(17) {
+            for g in (0..=255).step_by(17) {
+                for b in (0..=255).step_by(17) {
+                    let color = [r as u8, g as u8, b as u8];
+                    let exact = error(color, nearest_color(palette.colors(), color)) as f32;
+                    let table = error(color, palette.nearest(color)) as f32;
+                    // The table matches bucket centers, which lie at most 4 off per channel, so
+                    // its pick is at most twice that distance worse than the exact search
+                    assert!(table.sqrt() <= exact.sqrt() + 2.0 * (3.0f32 * 16.0).sqrt(), "{:?}", color);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn light_levels_only_brighten() {
+        let palette = Palette::ramps();
+        let colormap = Colormap::new(&palette, Fog::OFF);
+        let index = palette.nearest([240, 150, 80]);
+        let black = palette.colors()[nearest_color(palette.colors(), [0, 0, 0]) as usize];
+        assert_eq!(palette.colors()[colormap.light_index(index, [0.0; 3]) as usize], black);
+        let mut last = 0.0;
+        for step in 0..=20 {
+            let light = [(step as f32) / 10.0; 3];
+            let lit = luminance(palette.colors()[colormap.light_index(index, light) as usize]);
+            assert!(lit + 1.0 >= last, "light {:?}", light);
+            last = lit;
+        }
+    }
+
+    #[test]
+    fn fog_fades_to_the_fog_color() {
+        let palette = Palette::ramps();
+        let colormap = Colormap::new(&palette, MAP_FOG);
+        let index = palette.nearest([240, 220, 90]);
+        let near = palette.colors()[colormap.fog_index(index, 0.0) as usize];
+        assert_eq!(near, palette.colors()[index as usize]);
+        let far = palette.colors()[colormap.fog_index(index, MAP_FOG.end * 4.0) as usize];
+        assert_eq!(far, palette.colors()[nearest_color(palette.colors(), MAP_FOG.color) as usize]);
+    }
+
+    #[test]
+    fn colormap_shading_follows_rgb_shading() {
+        // Both `Shading` implementations agree up to what 256 colors and the light and fog levels
+        // can represent: off by a palette step at worst, by much less on average
+        let palette = Palette::ramps();
+        let colormap = Colormap::new(&palette, MAP_FOG);
+        let mut errors = Vec::new();
+        for &color in palette.colors().iter().step_by(7) {
+            for &light in &[0.25, 0.5, 1.0, 1.5] {
+                for &dist in &[0.0, MAP_FOG.start, (MAP_FOG.start + MAP_FOG.end) / 2.0, MAP_FOG.end] {
+                    let rgb = MAP_FOG.surface(color, [light; 3], dist);
+                    let indexed = palette.colors()[colormap.surface(color, [light; 3], dist) as usize];
+                    errors.push((luminance(rgb) - luminance(indexed)).abs());
+                }
+            }
+            let unlit = palette.colors()[colormap.unlit(color) as usize];
+            errors.push((luminance(MAP_FOG.unlit(color)) - luminance(unlit)).abs());
+        }
+        let worst = errors.iter().cloned().fold(0.0, f32::max);
+        let mean = errors.iter().sum::<f32>() / (errors.len() as f32);
+        assert!(worst < 32.0, "worst luminance error {}", worst);
+        assert!(mean < 4.0, "mean luminance error {}", mean);
+    }
+
+    #[test]
+    fn expand_looks_up_every_pixel() {
+        let palette = Palette::ramps();
+        let mut indices: Box<FrameRows<u8>> = Box::new([[0; RENDER_X as usize]; RENDER_Y as usize]);
+        indices[3][5] = 200;
+        let mut pixels: Box<FrameRows<[u8; 3]>> = Box::new([[[1; 3]; RENDER_X as usize]; RENDER_Y as usize]);
+        expand(&indices, palette.colors(), &mut pixels);
+        assert_eq!(pixels[3][5], palette.colors()[200]);
+        assert_eq!(pixels[0][0], palette.colors()[0]);
+    }
+
+    #[test]
+    fn tint_blends_every_color() {
+        let palette = Palette::ramps();
+        assert_eq!(&palette.tinted(DAMAGE_FLASH_COLOR, 0.0), palette.colors());
+        assert!(palette.tinted(DAMAGE_FLASH_COLOR, 1.0).iter().all(|&color| color == DAMAGE_FLASH_COLOR));
+    }
+}
@@ -0,0 +1,263 @@
+// Import the default worker count and the thread utilities
+use crate::window_gl::RENDER_THREADS;
+use std::any::Any;
+use std::cell::Cell;
+use std::panic::{ self, AssertUnwindSafe };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::mpsc::{ self, Receiver, SendError, Sender };
+use std::sync::{ Mutex, OnceLock, TryLockError };
+use std::thread;
+
+// Worker thread setting, starting out as `RENDER_THREADS` (0 = one per available CPU core)
+static THREAD_SETTING: AtomicUsize = AtomicUsize::new(RENDER_THREADS);
+
+// Change how many threads the software renderer uses from the next pass on (0 = one per core)
+pub fn set_thread_count(count: usize) {
+    THREAD_SETTING.store(count, Ordering::Relaxed);
+}
+
+// Number of worker threads the software renderer splits its columns and rows across
+pub fn thread_count() -> usize {
+    match THREAD_SETTING.load(Ordering::Relaxed) {
+        0 => core_count(),
+        count => count,
+    }
+}
+
+// Number of CPU cores available to the process
+pub fn core_count() -> usize {
+    static CORES: OnceLock<usize> = OnceLock::new();
+    *CORES.get_or_init(|| thread::available_parallelism().map_or(1, |count| count.get()))
+}
+
+thread_local! {
+    // Thread count for passes started on this thread, replacing `thread_count` (see `with_threads`)
+    static THREADS: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+// Run `f` with every pass it starts on this thread split across exactly `threads` threads, e.g. to
+// check that a result doesn't depend on the thread count
+pub fn with_threads<R>(threads: usize, f: impl FnOnce() -> R) -> R {
+    // Puts the previous count back, also when `f` panics
+    struct Restore(Option<usize>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            THREADS.with(|cell| cell.set(self.0));
+        }
+    }
+    let _restore = Restore(THREADS.with(|cell| cell.replace(Some(threads.max(1)))));
+    f()
+}
+
+// A chunk of a pass, handed to a worker
+type Job = Box<dyn FnOnce() + Send>;
+
+// Worker threads kept alive between passes and frames, so a pass only costs a message per worker
+// instead of spawning threads
+struct Pool {
+    workers: Vec<Sender<Job>>, // Job queue of each worker
+    done_sender: Sender<thread::Result<()>>, // Cloned into new workers
+    done: Receiver<thread::Result<()>>, // One message per finished job (with its panic, if any)
+}
+
+impl Pool {
+    // Start workers until there are at least `count`
+    fn grow(&mut self, count: usize) {
+        while self.workers.len() < count {
+            let (sender, jobs) = mpsc::channel::<Job>();
+            let done = self.done_sender.clone();
+            thread::Builder::new()
+                .name(format!("render-{}", self.workers.len() + 1))
+                .spawn(move || {
+                    for job in jobs {
+                        let _ = done.send(panic::catch_unwind(AssertUnwindSafe(job)));
+                    }
+                })
+                .expect("could not start a render worker");
+            self.workers.push(sender);
+        }
+    }
+}
+
+fn pool() -> &'static Mutex<Pool> {
+    static POOL: OnceLock<Mutex<Pool>> = OnceLock::new();
+    POOL.get_or_init(|| {
+        let (done_sender, done) = mpsc::channel();
+        Mutex::new(Pool { workers: Vec::new(), done_sender, done })
+    })
+}
+
+// Run `work` over `items` split into one contiguous chunk per thread. `work` gets the index of the
+// chunk's first item and the chunk itself; every item is processed exactly once, so as long as
+// `work` only depends on the item index the result doesn't depend on the thread count.
+// The calling thread takes the first chunk and the pool's workers the rest. A pass started while
+// the workers are busy (another thread's pass, or a pass inside `work`) runs on the calling thread.
+pub fn for_each_chunk<T, F>(items: &mut [T], work: F) where T: Send, F: Fn(usize, &mut [T]) + Sync {
+    let threads = THREADS.with(Cell::get)
+        .unwrap_or_else(thread_count)
+        .min(items.len())
+        .max(1);
+    if threads == 1 {
+        work(0, items);
+        return;
+    }
+    let mut pool = match pool().try_lock() {
+        Ok(pool) => pool,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(TryLockError::WouldBlock) => {
+            work(0, items);
+            return;
+        }
+    };
+    pool.grow(threads - 1);
+
+    let chunk_size = items.len().div_ceil(threads);
+    let mut chunks = items.chunks_mut(chunk_size).enumerate();
+    let (_, first) = chunks.next().unwrap();
+    let mut pending = Pending { done: &pool.done, count: 0, panicked: None };
+    for ((chunk_index, chunk), worker) in chunks.zip(&pool.workers) {
+        let work = &work;
+        let job: Box<dyn FnOnce() + Send + '_> = Box::new(move || work(chunk_index * chunk_size, chunk));
+        // SAFETY: the job borrows `items` and `work`. `pending` counts it once it is sent and waits
+        // for it when dropped, which happens before this function returns or unwinds, so the
+        // borrows outlive the job.
+        let job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + '_>, Job>(job) };
+        match worker.send(job) {
+            Ok(()) => {
+                pending.count += 1;
+            }
+            // The worker is gone; do its chunk here
+            Err(SendError(job)) => pending.run(job),
+        }
+    }
+    pending.run(Box::new(|| work(0, first)));
+
+    // Wait for every worker even if a chunk panicked, then pass the first panic on
+    pending.wait();
+    let panicked = pending.panicked.take();
+    drop(pending);
+    drop(pool);
+    if let Some(payload) = panicked {
+        panic::resume_unwind(payload);
+    }
+}
+
+// Jobs of one pass still out on the workers. Dropping it waits for all of them, so they can't
+// outlive what they borrow even if the calling thread unwinds.
+struct Pending<'a> {
+    done: &'a Receiver<thread::Result<()>>, // The pool's finished-job messages
+    count: usize, // Jobs sent and not yet finished
+    panicked: Option<Box<dyn Any + Send>>, // First panic of the pass
+}
+
+impl Pending<'_> {
+    // Run a chunk on the calling thread, keeping its panic for later
+    fn run(&mut self, job: Box<dyn FnOnce() + Send + '_>) {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+            self.panicked.get_or_insert(payload);
+        }
+    }
+
+    // Block until every sent job has finished
+    fn wait(&mut self) {
+        while self.count > 0 {
+            match self.done.recv() {
+                Ok(result) => {
+                    if let Err(payload) = result {
+                        self.panicked.get_or_insert(payload);
+                    }
+                }
+                // The pool holds a sender itself, so this can't happen; returning would leave
+                // jobs running with dangling borrows
+                Err(_) => std::process::abort(),
+            }
+            self.count -= 1;
+        }
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        self.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_item_is_processed_once() {
+        for threads in 1..=5 {
+            let mut items = vec![0usize; 37];
+            with_threads(threads, || {
+                for_each_chunk(&mut items, |start, chunk| {
+                    for (i, item) in chunk.iter_mut().enumerate() {
+                        *item += start + i + 1;
+                    }
+                });
+            });
+            assert!(items.iter().enumerate().all(|(i, &item)| item == i + 1), "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn workers_are_reused_between_passes() {
+        let mut items = vec![0u8; 64];
+        let names = Mutex::new(Vec::new());
+        with_threads(4, || {
+            for _ in 0..3 {
+                for_each_chunk(&mut items, |_, _| {
+                    names.lock().unwrap().push(thread::current().name().map(String::from));
+                });
+            }
+        });
+        let mut names = names.into_inner().unwrap();
+        names.sort();
+        names.dedup();
+        // The calling thread and three workers, whatever the number of passes
+        assert!(names.len() <= 4, "{:?}", names);
+    }
+
+    #[test]
+    fn a_panicking_chunk_reaches_the_caller() {
+        let mut items = vec![0u8; 8];
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            with_threads(4, || {
+                for_each_chunk(&mut items, |start, chunk| assert!(start + chunk.len() < 8, "chunk at {}", start));
+            })
+        }));
+        assert!(result.is_err());
+        // The pool still works afterwards
+        with_threads(4, || for_each_chunk(&mut items, |_, chunk| chunk.fill(1)));
+        assert!(items.iter().all(|&item| item == 1));
+    }
+
+    #[test]
+    fn a_panic_on_the_calling_thread_waits_for_the_workers() {
+        let mut items = vec![0u8; 8];
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            with_threads(4, || {
+                for_each_chunk(&mut items, |start, chunk| {
+                    if start == 0 {
+                        panic!("first chunk");
+                    }
+                    thread::sleep(std::time::Duration::from_millis(20));
+                    chunk.fill(1);
+                });
+            })
+        }));
+        assert!(result.is_err());
+        // Every worker chunk finished before the panic came back
+        assert!(items[2..].iter().all(|&item| item == 1), "{:?}", items);
+    }
+
+    #[test]
+    fn thread_count_is_a_runtime_setting() {
+        set_thread_count(3);
+        assert_eq!(thread_count(), 3);
+        set_thread_count(0);
+        assert_eq!(thread_count(), core_count());
+        set_thread_count(RENDER_THREADS);
+    }
+}
@@ -1,9 +1,3 @@
-// Import utility functions for converting coordinates
-use crate::draw_gl::get_y;
-use crate::draw_gl::get_x;
-// Import constants for window dimensions
-use crate::window_gl::HEIGHT;
-use crate::window_gl::WIDTH;
 // Import the constant for PI
 use std::f32::consts::PI;
 
@@ -31,11 +25,11 @@ impl Player {
         let x_dir = 0.0; // Initial x-direction vector
         let y_dir = 0.0; // Initial y-direction vector
 
-        // Calculate the corners of the player's representation
-        let tl_point = [get_x(x, WIDTH), get_y(y, HEIGHT), 0.0];
-        let tr_point = [get_x(x + 8.0, WIDTH), get_y(y, HEIGHT), 0.0];
-        let bl_point = [get_x(x, WIDTH), get_y(y + 8.0, HEIGHT), 0.0];
-        let br_point = [get_x(x + 8.0, WIDTH), get_y(y + 8.0, HEIGHT), 0.0];
+        // Calculate the corners of the player's representation (world coordinates)
+        let tl_point = [x, y, 0.0];
+        let tr_point = [x + 8.0, y, 0.0];
+        let bl_point = [x, y + 8.0, 0.0];
+        let br_point = [x + 8.0, y + 8.0, 0.0];
 
         let color = [0.0, 0.0, 1.0]; // Default color is blue
 
@@ -105,21 +99,21 @@ impl Player {
         return self.y_dir;
     }
 
-    // Get the player's x-coordinate in normalized OpenGL space with an offset
+    // Get the player's x-coordinate in the world with an offset
     pub fn get_player_x(&self, offset: f32) -> f32 {
-        return get_x(self.x_pos + offset, WIDTH);
+        return self.x_pos + offset;
     }
 
-    // Get the player's y-coordinate in normalized OpenGL space with an offset
+    // Get the player's y-coordinate in the world with an offset
     pub fn get_player_y(&self, offset: f32) -> f32 {
-        return get_y(self.y_pos + offset, HEIGHT);
+        return self.y_pos + offset;
     }
 
     // Recalculate the corners of the player's representation
     fn set_conrners(&mut self) {
-        self.tl_point = [get_x(self.x_pos, WIDTH), get_y(self.y_pos, HEIGHT), 0.0];
-        self.tr_point = [get_x(self.x_pos + 8.0, WIDTH), get_y(self.y_pos, HEIGHT), 0.0];
-        self.bl_point = [get_x(self.x_pos, WIDTH), get_y(self.y_pos + 8.0, HEIGHT), 0.0];
-        self.br_point = [get_x(self.x_pos + 8.0, WIDTH), get_y(self.y_pos + 8.0, HEIGHT), 0.0];
+        self.tl_point = [self.x_pos, self.y_pos, 0.0];
+        self.tr_point = [self.x_pos + 8.0, self.y_pos, 0.0];
+        self.bl_point = [self.x_pos, self.y_pos + 8.0, 0.0];
+        self.br_point = [self.x_pos + 8.0, self.y_pos + 8.0, 0.0];
     }
 }
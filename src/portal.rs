@@ -0,0 +1,202 @@
+// Import map constants and the list of portal links
+use crate::window_gl::{ MAP_S, PORTALS };
+// Import the constant for PI
+use std::f32::consts::PI;
+
+// The four faces of a map cell, named by the direction their outward normal points
+// (the world y-axis grows downwards, so North is towards smaller y)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Face {
+    North,
+    South,
+    East,
+    West,
+}
+
+impl Face {
+    // Outward normal of the face in world space
+    pub const fn normal(self) -> (f32, f32) {
+        match self {
+            Face::North => (0.0, -1.0),
+            Face::South => (0.0, 1.0),
+            Face::East => (1.0, 0.0),
+            Face::West => (-1.0, 0.0),
+        }
+    }
+
+    // Position of the face in per-face tables (North, South, East, West)
+    pub const fn index(self) -> usize {
+        match self {
+            Face::North => 0,
+            Face::South => 1,
+            Face::East => 2,
+            Face::West => 3,
+        }
+    }
+
+    // Angle of the outward normal in radians
+    pub fn normal_angle(self) -> f32 {
+        let (nx, ny) = self.normal();
+        ny.atan2(nx)
+    }
+}
+
+// A single face of a single map cell
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaceRef {
+    pub cell_x: i32, // Map column of the wall cell
+    pub cell_y: i32, // Map row of the wall cell
+    pub face: Face, // Which side of the cell
+}
+
+impl FaceRef {
+    // Constructor to create a new FaceRef
+    pub const fn new(cell_x: i32, cell_y: i32, face: Face) -> FaceRef {
+        FaceRef { cell_x, cell_y, face }
+    }
+
+    // World-space center point of the face
+    pub fn center(&self) -> (f32, f32) {
+        let map_s = MAP_S as f32;
+        let (nx, ny) = self.face.normal();
+        (
+            ((self.cell_x as f32) + 0.5 + nx * 0.5) * map_s,
+            ((self.cell_y as f32) + 0.5 + ny * 0.5) * map_s,
+        )
+    }
+}
+
+// Two linked wall faces: walking into one comes out of the other, in both directions
+pub struct Portal {
+    pub a: FaceRef,
+    pub b: FaceRef,
+}
+
+// One direction of a portal: maps points and angles from the entry face's frame
+// into the exit face's frame
+#[derive(Debug, Clone, Copy)]
+pub struct PortalLink {
+    entry: FaceRef, // Face the ray or player walks into
+    exit: FaceRef, // Face the ray or player comes out of
+    rotation: f32, // Rotation (radians) applied to directions passing through
+}
+
+impl PortalLink {
+    // Constructor to create a new PortalLink
+    pub fn new(entry: FaceRef, exit: FaceRef) -> PortalLink {
+        // Walking into the entry face (against its normal) must come out along the exit normal
+        let rotation = exit.face.normal_angle() - (entry.face.normal_angle() + PI);
+        PortalLink { entry, exit, rotation }
+    }
+
+    // Get the face this link is entered through
+    pub fn entry(&self) -> FaceRef {
+        self.entry
+    }
+
+    // Get the face this link comes out of
+    pub fn exit(&self) -> FaceRef {
+        self.exit
+    }
+
+    // Get the rotation (radians) applied to directions passing through
+    pub fn rotation(&self) -> f32 {
+        self.rotation
+    }
+
+    // Transform a world-space point from the entry frame into the exit frame
+    pub fn transform_point(&self, x: f32, y: f32) -> (f32, f32) {
+        let (ex, ey) = self.entry.center();
+        let (xx, xy) = self.exit.center();
+        let (sin, cos) = self.rotation.sin_cos();
+        let (rx, ry) = (x - ex, y - ey);
+        (xx + rx * cos - ry * sin, xy + rx * sin + ry * cos)
+    }
+
+    // Transform a direction angle from the entry frame into the exit frame, normalized to [0, 2π)
+    pub fn transform_angle(&self, angle: f32) -> f32 {
+        let mut new_angle = angle + self.rotation;
+        while new_angle < 0.0 {
+            new_angle += 2.0 * PI;
+        }
+        while new_angle >= 2.0 * PI {
+            new_angle -= 2.0 * PI;
+        }
+        new_angle
+    }
+}
+
+// Find the portal link entered through the given face, if that face is a portal
+pub fn find_link(cell_x: i32, cell_y: i32, face: Face) -> Option<PortalLink> {
+    let face_ref = FaceRef::new(cell_x, cell_y, face);
+    for portal in PORTALS.iter() {
+        if portal.a == face_ref {
+            return Some(PortalLink::new(portal.a, portal.b));
+        }
+        if portal.b == face_ref {
+            return Some(PortalLink::new(portal.b, portal.a));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn face_center_lies_on_the_cell_edge() {
+        let map_s = MAP_S as f32;
+        assert_eq!(FaceRef::new(2, 3, Face::North).center(), (2.5 * map_s, 3.0 * map_s));
+        assert_eq!(FaceRef::new(2, 3, Face::East).center(), (3.0 * map_s, 3.5 * map_s));
+    }
+
+    #[test]
+    fn entry_center_maps_to_exit_center() {
+        let entry = FaceRef::new(1, 1, Face::East);
+        let exit = FaceRef::new(5, 4, Face::South);
+        let (x, y) = PortalLink::new(entry, exit).transform_point(entry.center().0, entry.center().1);
+        assert!(close(x, exit.center().0) && close(y, exit.center().1));
+    }
+
+    #[test]
+    fn walking_in_comes_out_along_the_exit_normal() {
+        for entry_face in [Face::North, Face::South, Face::East, Face::West] {
+            for exit_face in [Face::North, Face::South, Face::East, Face::West] {
+                let link = PortalLink::new(FaceRef::new(1, 1, entry_face), FaceRef::new(4, 4, exit_face));
+                // Walking into the entry face means moving against its normal
+                let inward = (entry_face.normal_angle() + PI).rem_euclid(2.0 * PI);
+                let (sin, cos) = link.transform_angle(inward).sin_cos();
+                let (nx, ny) = exit_face.normal();
+                assert!(close(cos, nx) && close(sin, ny), "{:?} -> {:?}", entry_face, exit_face);
+            }
+        }
+    }
+
+    #[test]
+    fn transformed_angles_stay_in_one_turn() {
+        let link = PortalLink::new(FaceRef::new(1, 1, Face::West), FaceRef::new(4, 4, Face::North));
+        for step in 0..64 {
+            let angle = link.transform_angle((step as f32) * (2.0 * PI / 64.0));
+            assert!((0.0..2.0 * PI).contains(&angle));
+        }
+    }
+
+    #[test]
+    fn links_work_in_both_directions() {
+        for portal in PORTALS.iter() {
+            let forward = find_link(portal.a.cell_x, portal.a.cell_y, portal.a.face).unwrap();
+            let backward = find_link(portal.b.cell_x, portal.b.cell_y, portal.b.face).unwrap();
+            assert_eq!(forward.exit(), portal.b);
+            assert_eq!(backward.exit(), portal.a);
+            // Going through and back returns to the same point
+            let (x, y) = forward.transform_point(portal.a.center().0 + 3.0, portal.a.center().1 + 2.0);
+            let (bx, by) = backward.transform_point(x, y);
+            assert!(close(bx, portal.a.center().0 + 3.0) && close(by, portal.a.center().1 + 2.0));
+        }
+    }
+}
@@ -0,0 +1,305 @@
+// Import the shader program type, the render resolution and file utilities
+use crate::render_gl::Program;
+use crate::log::Level;
+use crate::log_event;
+use crate::window_gl::{ RENDER_X, RENDER_Y };
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::fs;
+use std::path::Path;
+
+// Post-processing applied to the raycast frame when it is drawn on the canvas
+#[derive(Debug, Clone, Copy)]
+pub struct PostFx {
+    pub crt: bool, // Bulge the image like a curved CRT screen
+    pub curvature: f32, // How strongly the CRT bulges (0.0 = flat)
+    pub aberration: bool, // Split red and blue towards the edges
+    pub aberration_amount: f32, // Offset of red and blue at the edges, as a fraction of the frame
+    pub grading: bool, // Remap colors through the color grading LUT
+    pub brightness: f32, // Color multiplier before gamma
+    pub gamma: f32, // Display gamma (1.0 = unchanged)
+    pub scanlines: bool, // Darken every other row
+    pub scanline_strength: f32, // How much darker the scanlines are (0.0 - 1.0)
+    pub vignette: bool, // Darken towards the corners
+    pub vignette_strength: f32, // How dark the corners get (0.0 - 1.0)
+    pub software: bool, // Apply the chain on the CPU (into the pixel buffer) instead of in tex.frag
+}
+
+impl PostFx {
+    // Pass the settings to tex.frag; with the software path on, the shader passes the frame through
+    pub fn set_uniforms(&self, program: &Program, lut: &ColorLut) {
+        let gpu = !self.software;
+        program.set_bool("crt", gpu && self.crt);
+        program.set_float("curvature", self.curvature);
+        program.set_bool("aberration", gpu && self.aberration);
+        program.set_float("aberration_amount", self.aberration_amount);
+        program.set_bool("grading", gpu && self.grading);
+        program.set_float("lut_size", lut.size as f32);
+        program.set_float("brightness", if gpu { self.brightness } else { 1.0 });
+        program.set_float("gamma", if gpu { self.gamma } else { 1.0 });
+        program.set_bool("scanlines", gpu && self.scanlines);
+        program.set_float("scanline_strength", self.scanline_strength);
+        program.set_bool("vignette", gpu && self.vignette);
+        program.set_float("vignette_strength", self.vignette_strength);
+    }
+
+    // Software fallback: the same chain as tex.frag, applied to an RGB pixel buffer
+    pub fn apply(&self, _pixels: &mut [[[u8; 3]; RENDER_X as usize]; RENDER_Y as usize], lut: &ColorLut) {
+        let source = _pixels.to_vec();
+        let fetch = |u: f32, v: f32| -> [f32; 3] {
+            let x = ((u * (RENDER_X as f32)) as i32).clamp(0, RENDER_X - 1) as usize;
+            let y = ((v * (RENDER_Y as f32)) as i32).clamp(0, RENDER_Y - 1) as usize;
+            source[y][x].map(|c| (c as f32) / 255.0)
+        };
+
+        for (y, row) in _pixels.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let mut u = ((x as f32) + 0.5) / (RENDER_X as f32);
+                let mut v = ((y as f32) + 0.5) / (RENDER_Y as f32);
+
+                // CRT curvature: bulge the image outwards, black outside the tube
+                if self.crt {
+                    let (cx, cy) = (u * 2.0 - 1.0, v * 2.0 - 1.0);
+                    u = (cx * (1.0 + self.curvature * cy * cy)) * 0.5 + 0.5;
+                    v = (cy * (1.0 + self.curvature * cx * cx)) * 0.5 + 0.5;
+                    if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+                        *pixel = [0, 0, 0];
+                        continue;
+                    }
+                }
+
+                // Chromatic aberration: red and blue drift apart towards the edges
+                let mut color = if self.aberration {
+                    let du = (u - 0.5) * self.aberration_amount;
+                    let dv = (v - 0.5) * self.aberration_amount;
+                    [fetch(u + du, v + dv)[0], fetch(u, v)[1], fetch(u - du, v - dv)[2]]
+                } else {
+                    fetch(u, v)
+                };
+
+                // LUT color grading
+                if self.grading {
+                    color = lut.sample(color);
+                }
+
+                // Brightness and gamma
+                color = color.map(|c| (c * self.brightness).clamp(0.0, 1.0).powf(1.0 / self.gamma));
+
+                // Scanlines: darken every other row of the frame
+                let row = ((v * (RENDER_Y as f32)) as i32).clamp(0, RENDER_Y - 1);
+                if self.scanlines && row % 2 == 1 {
+                    color = color.map(|c| c * (1.0 - self.scanline_strength));
+                }
+
+                // Vignette: darken towards the corners
+                if self.vignette {
+                    let dist = ((u - 0.5) * (u - 0.5) + (v - 0.5) * (v - 0.5)).sqrt() / FRAC_1_SQRT_2;
+                    color = color.map(|c| c * (1.0 - self.vignette_strength * dist * dist));
+                }
+
+                *pixel = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+            }
+        }
+    }
+}
+
+// 3D color lookup table for color grading: maps every input color to a graded output color
+pub struct ColorLut {
+    size: usize, // Entries along each axis
+    data: Vec<[f32; 3]>, // Output colors, red changing fastest, then green, then blue
+}
+
+impl ColorLut {
+    // A table that leaves every color unchanged
+    pub fn identity(size: usize) -> ColorLut {
+        let step = |i: usize| (i as f32) / ((size - 1) as f32);
+        let mut data = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push([step(r), step(g), step(b)]);
+                }
+            }
+        }
+        ColorLut { size, data }
+    }
+
+    // Load a 3D LUT in the .cube text format (LUT_3D_SIZE followed by one "r g b" line per entry)
+    pub fn load_cube(path: &Path) -> Result<ColorLut, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut size = 0;
+        let mut data = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let number = number + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("LUT_3D_SIZE") => {
+                    size = fields
+                        .next()
+                        .and_then(|field| field.parse().ok())
+                        .ok_or_else(|| format!("line {}: bad LUT_3D_SIZE", number))?;
+                }
+                Some(first) if first.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') => {
+                    let values: Vec<&str> = std::iter::once(first).chain(fields).collect();
+                    if values.len() < 3 {
+                        return Err(format!("line {}: expected 3 values, found {}", number, values.len()));
+                    }
+                    let mut color = [0.0; 3];
+                    for (channel, value) in color.iter_mut().zip(values) {
+                        *channel = value.parse().map_err(|_| format!("line {}: bad LUT entry: {}", number, line))?;
+                    }
+                    data.push(color);
+                }
+                _ => {} // TITLE, DOMAIN_MIN/MAX and other keywords
+            }
+        }
+        if size < 2 || data.len() != size * size * size {
+            return Err(format!("expected {} LUT entries, found {}", size * size * size, data.len()));
+        }
+        Ok(ColorLut { size, data })
+    }
+
+    // Load a .cube LUT, falling back to the identity table if it can't be read
+    pub fn load_or_default(path: &Path) -> ColorLut {
+        match ColorLut::load_cube(path) {
+            Ok(lut) => lut,
+            Err(error) => {
+                log_event!(Level::Warn, "assets", "could not load color LUT"; path = path.display(), error = error);
+                ColorLut::identity(2)
+            }
+        }
+    }
+
+    // Get the number of entries along each axis
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Get the output colors (red fastest), as uploaded to the 3D texture
+    pub fn data(&self) -> &[[f32; 3]] {
+        &self.data
+    }
+
+    // Graded color for an input color (0.0 - 1.0 per channel), with trilinear interpolation
+    pub fn sample(&self, color: [f32; 3]) -> [f32; 3] {
+        let max = (self.size - 1) as f32;
+        let scaled = color.map(|c| c.clamp(0.0, 1.0) * max);
+        let low = scaled.map(|c| (c.floor() as usize).min(self.size - 2));
+        let mut result = [0.0; 3];
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = 0;
+            let mut stride = 1;
+            for axis in 0..3 {
+                let high = (corner >> axis) & 1;
+                let t = scaled[axis] - (low[axis] as f32);
+                weight *= if high == 1 { t } else { 1.0 - t };
+                index += (low[axis] + high) * stride;
+                stride *= self.size;
+            }
+            for (channel, value) in result.iter_mut().zip(self.data[index].iter()) {
+                *channel += value * weight;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_text(name: &str, text: &str) -> Result<ColorLut, String> {
+        let path = std::env::temp_dir().join(format!("yggdrasil-{}-{}.cube", name, std::process::id()));
+        fs::write(&path, text).unwrap();
+        let result = ColorLut::load_cube(&path);
+        let _ = fs::remove_file(&path);
+        result
+    }
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-5)
+    }
+
+    #[test]
+    fn cube_with_keywords_and_comments() {
+        let mut text = String::from("# graded by hand\nTITLE \"test\"\nLUT_3D_SIZE 2\nDOMAIN_MIN 0 0 0\n");
+        for b in 0..2 {
+            for g in 0..2 {
+                for r in 0..2 {
+                    // Swap red and blue
+                    text.push_str(&format!("{} {} {}\n", b, g, r));
+                }
+            }
+        }
+        let lut = load_text("swap", &text).unwrap();
+        assert_eq!(lut.size(), 2);
+        assert!(close(lut.sample([1.0, 0.0, 0.0]), [0.0, 0.0, 1.0]));
+        assert!(close(lut.sample([0.25, 0.5, 0.75]), [0.75, 0.5, 0.25]));
+    }
+
+    #[test]
+    fn cube_rejects_missing_entries() {
+        assert!(load_text("short", "LUT_3D_SIZE 2\n0 0 0\n1 1 1\n").is_err());
+        assert!(load_text("bad", "LUT_3D_SIZE 2\n0 0 x\n").is_err());
+        assert!(load_text("sizeless", "0 0 0\n").is_err());
+    }
+
+    #[test]
+    fn cube_errors_name_the_line() {
+        let error = load_text("two-values", "# comment\nLUT_3D_SIZE 2\n0 0 0\n1 1\n").err().unwrap();
+        assert!(error.starts_with("line 4:"), "{}", error);
+        let error = load_text("not-a-number", "LUT_3D_SIZE 2\n0 0 x\n").err().unwrap();
+        assert!(error.starts_with("line 2:"), "{}", error);
+    }
+
+    #[test]
+    fn shipped_lut_loads() {
+        let lut = ColorLut::load_cube(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/luts/warm.cube"))).unwrap();
+        assert_eq!(lut.data().len(), lut.size().pow(3));
+    }
+
+    #[test]
+    fn identity_lut_keeps_colors() {
+        let lut = ColorLut::identity(17);
+        for color in [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0], [0.3, 0.6, 0.9]] {
+            assert!(close(lut.sample(color), color));
+        }
+    }
+
+    #[test]
+    fn software_chain_with_every_effect_off_keeps_the_frame() {
+        let post_fx = PostFx {
+            crt: false,
+            curvature: 0.1,
+            aberration: false,
+            aberration_amount: 0.01,
+            grading: false,
+            brightness: 1.0,
+            gamma: 1.0,
+            scanlines: false,
+            scanline_strength: 0.3,
+            vignette: false,
+            vignette_strength: 0.3,
+            software: true,
+        };
+        let mut pixels = Box::new([[[0u8; 3]; RENDER_X as usize]; RENDER_Y as usize]);
+        for (y, row) in pixels.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = [x as u8, y as u8, (x ^ y) as u8];
+            }
+        }
+        let before = pixels.clone();
+        post_fx.apply(&mut pixels, &ColorLut::identity(2));
+        assert!(pixels.iter().eq(before.iter()));
+
+        // Scanlines darken odd rows only
+        let scanlines = PostFx { scanlines: true, ..post_fx };
+        scanlines.apply(&mut pixels, &ColorLut::identity(2));
+        assert_eq!(pixels[0], before[0]);
+        assert!(pixels[1][200][0] < before[1][200][0]);
+    }
+}
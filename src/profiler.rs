@@ -0,0 +1,473 @@
+// Import OpenGL bindings, the drawing helpers for the overlay and timing and file utilities
+extern crate gl;
+use crate::camera;
+use crate::draw_gl::{ BufferArrayBinder, ColorVertex, VertexArrayWrapper };
+use crate::render_gl::Program;
+use crate::text::{ Align, TextRenderer, TextStyle };
+use crate::window_gl::Layout;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+// Frames kept for the graph, and frames averaged for the readouts
+const HISTORY: usize = 240;
+const AVERAGED: usize = 60;
+
+// Spans kept while recording a trace (about 7 minutes of frames at 60 FPS); past that the oldest
+// are dropped, so a forgotten recording doesn't grow without bound
+const TRACE_SPANS: usize = 1 << 18;
+
+// Timer queries in flight at once (results are read a few frames late, without stalling)
+const GPU_QUERIES: usize = 4;
+
+// Parts of a frame that are timed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Input, // Event handling and player movement
+    ConstructVertices, // `construct_vertices` (includes `cast_rays`)
+    CastRays, // `cast_rays`
+    DrawWalls, // `draw_walls_to_pixels` (or the sector renderer)
+    TextureUpload, // Software post-processing and streaming the frame into its texture
+    GlDraw, // Issuing the GL draw calls
+    Swap, // `gl_swap_window` (waits for vsync and the GPU)
+}
+
+impl Phase {
+    pub const ALL: [Phase; 7] = [
+        Phase::Input,
+        Phase::ConstructVertices,
+        Phase::CastRays,
+        Phase::DrawWalls,
+        Phase::TextureUpload,
+        Phase::GlDraw,
+        Phase::Swap,
+    ];
+
+    // Name in the overlay and the trace
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Input => "input",
+            Phase::ConstructVertices => "construct_vertices",
+            Phase::CastRays => "cast_rays",
+            Phase::DrawWalls => "draw_walls_to_pixels",
+            Phase::TextureUpload => "texture upload",
+            Phase::GlDraw => "GL draw",
+            Phase::Swap => "swap",
+        }
+    }
+
+    // Phases timed inside another one are indented under it in the overlay
+    fn nested(self) -> bool {
+        self == Phase::CastRays
+    }
+}
+
+// Times of one finished frame in milliseconds
+struct FrameTimes {
+    total: f32,
+    phases: [f32; Phase::ALL.len()],
+}
+
+// One span of a Chrome trace, in microseconds since the profiler started
+struct TraceEvent {
+    name: &'static str,
+    thread: u32, // 1 = CPU, 2 = GPU
+    start: f64,
+    duration: f64,
+}
+
+// Spans of a Chrome trace being recorded: the most recent `capacity` of them
+struct Trace {
+    spans: VecDeque<TraceEvent>, // Oldest first
+    capacity: usize,
+    dropped: usize, // Spans dropped to stay within the capacity
+}
+
+impl Trace {
+    fn new(capacity: usize) -> Trace {
+        Trace { spans: VecDeque::new(), capacity, dropped: 0 }
+    }
+
+    fn push(&mut self, event: TraceEvent) {
+        if self.spans.len() == self.capacity {
+            self.spans.pop_front();
+            self.dropped += 1;
+        }
+        self.spans.push_back(event);
+    }
+}
+
+// Frame profiler of the main thread: phase times of recent frames, GPU times as their timer
+// queries come back, and (while recording) every span for a Chrome trace
+pub struct Profiler {
+    epoch: Instant, // Time origin of the trace
+    frame_start: Option<Instant>, // Start of the frame being timed
+    current: [f32; Phase::ALL.len()], // Phase times of the frame being timed
+    frames: VecDeque<FrameTimes>, // Recent frames, oldest first
+    gpu: VecDeque<f32>, // Recent GPU frame times, oldest first
+    trace: Option<Trace>, // Spans recorded since the trace was started
+}
+
+thread_local! {
+    static PROFILER: RefCell<Profiler> = RefCell::new(Profiler::new());
+}
+
+impl Profiler {
+    fn new() -> Profiler {
+        Profiler {
+            epoch: Instant::now(),
+            frame_start: None,
+            current: [0.0; Phase::ALL.len()],
+            frames: VecDeque::with_capacity(HISTORY),
+            gpu: VecDeque::with_capacity(HISTORY),
+            trace: None,
+        }
+    }
+
+    fn micros(&self, instant: Instant) -> f64 {
+        instant.saturating_duration_since(self.epoch).as_secs_f64() * 1_000_000.0
+    }
+
+    fn record(&mut self, phase: Phase, start: Instant, end: Instant) {
+        let index = Phase::ALL.iter().position(|&p| p == phase).unwrap();
+        self.current[index] += (end - start).as_secs_f32() * 1000.0;
+        let (start, end) = (self.micros(start), self.micros(end));
+        if let Some(trace) = &mut self.trace {
+            trace.push(TraceEvent { name: phase.name(), thread: 1, start, duration: end - start });
+        }
+    }
+
+    // Average of the most recent frames (`select` picks the value out of a frame)
+    fn average<F>(&self, select: F) -> f32 where F: Fn(&FrameTimes) -> f32 {
+        let count = self.frames.len().min(AVERAGED);
+        if count == 0 {
+            return 0.0;
+        }
+        self.frames.iter().rev().take(count).map(select).sum::<f32>() / (count as f32)
+    }
+
+    // Text readout: FPS, frame time, GPU time and the time of each phase
+    fn readout(&self) -> String {
+        let frame = self.average(|frame| frame.total);
+        let fps = if frame > 0.0 { 1000.0 / frame } else { 0.0 };
+        let gpu = match self.gpu.back() {
+            Some(ms) => format!("{:.2} ms", ms),
+            None => String::from("n/a"),
+        };
+        let mut text = format!("FPS {:.1}  frame {:.2} ms  GPU {}\n", fps, frame, gpu);
+        for (index, phase) in Phase::ALL.iter().enumerate() {
+            let indent = if phase.nested() { "  " } else { "" };
+            let name = format!("{}{}", indent, phase.name());
+            let _ = writeln!(text, "{:<22}{:>7.2} ms", name, self.average(|frame| frame.phases[index]));
+        }
+        if let Some(trace) = &self.trace {
+            let _ = write!(text, "recording trace ({} spans", trace.spans.len());
+            if trace.dropped > 0 {
+                let _ = write!(text, ", {} oldest dropped", trace.dropped);
+            }
+            let _ = writeln!(text, "), F4 to save");
+        }
+        text
+    }
+}
+
+// Times the phase it was created for until it is dropped
+pub struct Scope {
+    phase: Phase,
+    start: Instant,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let end = Instant::now();
+        PROFILER.with(|profiler| profiler.borrow_mut().record(self.phase, self.start, end));
+    }
+}
+
+// Start timing a phase: `let _scope = profiler::scope(Phase::CastRays);`. Only the main thread's
+// phases are collected.
+pub fn scope(phase: Phase) -> Scope {
+    Scope { phase, start: Instant::now() }
+}
+
+// Mark the start of a frame
+pub fn begin_frame() {
+    PROFILER.with(|profiler| {
+        let mut profiler = profiler.borrow_mut();
+        profiler.frame_start = Some(Instant::now());
+        profiler.current = [0.0; Phase::ALL.len()];
+    });
+}
+
+// Mark the end of a frame, keeping its times for the overlay
+pub fn end_frame() {
+    let end = Instant::now();
+    PROFILER.with(|profiler| {
+        let mut profiler = profiler.borrow_mut();
+        let start = match profiler.frame_start.take() {
+            Some(start) => start,
+            None => {
+                return;
+            }
+        };
+        if profiler.frames.len() == HISTORY {
+            profiler.frames.pop_front();
+        }
+        let times = FrameTimes { total: (end - start).as_secs_f32() * 1000.0, phases: profiler.current };
+        profiler.frames.push_back(times);
+        let (start, end) = (profiler.micros(start), profiler.micros(end));
+        if let Some(trace) = &mut profiler.trace {
+            trace.push(TraceEvent { name: "frame", thread: 1, start, duration: end - start });
+        }
+    });
+}
+
+// Record the GPU time of a frame that started at `start`
+fn record_gpu(start: Instant, milliseconds: f32) {
+    PROFILER.with(|profiler| {
+        let mut profiler = profiler.borrow_mut();
+        if profiler.gpu.len() == HISTORY {
+            profiler.gpu.pop_front();
+        }
+        profiler.gpu.push_back(milliseconds);
+        let start = profiler.micros(start);
+        if let Some(trace) = &mut profiler.trace {
+            let duration = (milliseconds as f64) * 1000.0;
+            trace.push(TraceEvent { name: "GPU frame", thread: 2, start, duration });
+        }
+    });
+}
+
+// Whether a Chrome trace is being recorded
+pub fn is_tracing() -> bool {
+    PROFILER.with(|profiler| profiler.borrow().trace.is_some())
+}
+
+// Start recording every span for a Chrome trace
+pub fn start_trace() {
+    PROFILER.with(|profiler| profiler.borrow_mut().trace = Some(Trace::new(TRACE_SPANS)));
+}
+
+// Stop recording and write the spans as a Chrome trace (chrome://tracing, Perfetto). Returns the
+// number of spans written.
+pub fn stop_trace(path: &Path) -> Result<usize, String> {
+    let events = PROFILER.with(|profiler| profiler.borrow_mut().trace.take()).map_or_else(VecDeque::new, |trace| trace.spans);
+    let mut json = String::from("{\"traceEvents\":[\n");
+    json.push_str("{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":1,\"args\":{\"name\":\"CPU\"}},\n");
+    json.push_str("{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":2,\"args\":{\"name\":\"GPU\"}}");
+    for event in &events {
+        let _ = write!(
+            json,
+            ",\n{{\"name\":\"{}\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":{:.3},\"dur\":{:.3}}}",
+            event.name,
+            event.thread,
+            event.start,
+            event.duration
+        );
+    }
+    json.push_str("\n],\"displayTimeUnit\":\"ms\"}\n");
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    fs::write(path, json).map_err(|e| e.to_string())?;
+    Ok(events.len())
+}
+
+// GPU time of whole frames through `GL_TIME_ELAPSED` queries, read back a few frames later so the
+// CPU never waits for them. Does nothing if the driver has no timer queries.
+pub struct GpuTimer {
+    queries: Vec<gl::types::GLuint>, // Query objects (empty when unavailable)
+    started: [Option<Instant>; GPU_QUERIES], // Frame start of each query still in flight
+    next: usize, // Query the next frame uses (also the oldest one in flight)
+    active: Option<usize>, // Query of the frame being timed
+}
+
+impl GpuTimer {
+    // Constructor to create the query objects, if the driver supports them
+    pub fn new() -> GpuTimer {
+        let mut queries = Vec::new();
+        if gl::GenQueries::is_loaded() {
+            queries = vec![0; GPU_QUERIES];
+            unsafe {
+                gl::GenQueries(GPU_QUERIES as gl::types::GLsizei, queries.as_mut_ptr());
+            }
+        }
+        GpuTimer { queries, started: [None; GPU_QUERIES], next: 0, active: None }
+    }
+
+    // Start timing the GPU work of this frame
+    pub fn begin(&mut self) {
+        self.poll();
+        if self.queries.is_empty() || self.started[self.next].is_some() {
+            // Unavailable, or every query is still waiting for its result: skip this frame
+            return;
+        }
+        unsafe {
+            gl::BeginQuery(gl::TIME_ELAPSED, self.queries[self.next]);
+        }
+        self.started[self.next] = Some(Instant::now());
+        self.active = Some(self.next);
+    }
+
+    // Stop timing this frame
+    pub fn end(&mut self) {
+        if let Some(slot) = self.active.take() {
+            unsafe {
+                gl::EndQuery(gl::TIME_ELAPSED);
+            }
+            self.next = (slot + 1) % GPU_QUERIES;
+        }
+    }
+
+    // Hand the results that are ready to the profiler, oldest first
+    fn poll(&mut self) {
+        for step in 0..self.queries.len() {
+            let slot = (self.next + step) % GPU_QUERIES;
+            let start = match self.started[slot] {
+                Some(start) => start,
+                None => continue,
+            };
+            let mut available: gl::types::GLint = 0;
+            unsafe {
+                gl::GetQueryObjectiv(self.queries[slot], gl::QUERY_RESULT_AVAILABLE, &mut available);
+            }
+            if available == 0 {
+                // Later queries can't be ready before this one
+                break;
+            }
+            let mut nanoseconds: gl::types::GLuint64 = 0;
+            unsafe {
+                gl::GetQueryObjectui64v(self.queries[slot], gl::QUERY_RESULT, &mut nanoseconds);
+            }
+            self.started[slot] = None;
+            record_gpu(start, (nanoseconds as f32) / 1_000_000.0);
+        }
+    }
+}
+
+impl Default for GpuTimer {
+    fn default() -> Self {
+        GpuTimer::new()
+    }
+}
+
+// Automatically deletes the query objects when the timer goes out of scope
+impl Drop for GpuTimer {
+    fn drop(&mut self) {
+        if !self.queries.is_empty() {
+            unsafe {
+                gl::DeleteQueries(self.queries.len() as gl::types::GLsizei, self.queries.as_ptr());
+            }
+        }
+    }
+}
+
+// On-screen readout of the profiler: FPS, phase times and a graph of recent frame times, drawn in
+// the free strip under the canvas
+pub struct PerfOverlay {
+    buffers: BufferArrayBinder<ColorVertex>, // VAO/VBO the graph is streamed into
+}
+
+impl PerfOverlay {
+    // Window pixels of the readout and the graph (left, top, width, height of the graph)
+    const TEXT_AT: (i32, i32) = (524, 524);
+    const GRAPH: (f32, f32, f32, f32) = (790.0, 524.0, 480.0, 168.0);
+    // Graph pixels per millisecond, and the frame time of 60 FPS drawn as a line
+    const PIXELS_PER_MS: f32 = 4.0;
+    const TARGET_MS: f32 = 1000.0 / 60.0;
+
+    // Constructor to create the graph buffer
+    pub fn new() -> PerfOverlay {
+        PerfOverlay { buffers: BufferArrayBinder::new(gl::STREAM_DRAW) }
+    }
+
+    // Draw the graph with triangle.vert/triangle.frag and queue the readout on the overlay text
+    pub fn draw(&mut self, program: &Program, text: &mut TextRenderer, layout: &Layout) {
+        let (readout, totals): (String, Vec<f32>) = PROFILER.with(|profiler| {
+            let profiler = profiler.borrow();
+            (profiler.readout(), profiler.frames.iter().map(|frame| frame.total).collect())
+        });
+        let (text_x, text_y) = PerfOverlay::TEXT_AT;
+        text.queue(&readout, text_x, text_y, &TextStyle::new([230, 230, 230], 1, Align::Left));
+
+        // One bar per frame, newest on the right: green within the 60 FPS budget, yellow within
+        // two, red beyond
+        let (left, top, width, height) = PerfOverlay::GRAPH;
+        let bottom = top + height;
+        let bar = width / (HISTORY as f32);
+        let mut vertices = VertexArrayWrapper::new();
+        for (index, &total) in totals.iter().enumerate() {
+            let x = left + width - ((totals.len() - index) as f32) * bar;
+            let y = bottom - (total * PerfOverlay::PIXELS_PER_MS).min(height);
+            let color = if total <= PerfOverlay::TARGET_MS {
+                [0.2, 0.8, 0.3]
+            } else if total <= PerfOverlay::TARGET_MS * 2.0 {
+                [0.9, 0.8, 0.2]
+            } else {
+                [0.9, 0.25, 0.2]
+            };
+            let corners = [[x, y, 0.0], [x + bar, y, 0.0], [x, bottom, 0.0], [x + bar, bottom, 0.0]];
+            for &i in &[0, 1, 2, 1, 3, 2] {
+                vertices.push(ColorVertex::new(corners[i], color));
+            }
+        }
+        vertices.set_triangle_end(vertices.len());
+        let target_y = bottom - PerfOverlay::TARGET_MS * PerfOverlay::PIXELS_PER_MS;
+        vertices.push(ColorVertex::new([left, target_y, 0.0], [1.0, 1.0, 1.0]));
+        vertices.push(ColorVertex::new([left + width, target_y, 0.0], [1.0, 1.0, 1.0]));
+        vertices.set_line_end(vertices.len());
+
+        self.buffers.set_buffers(vertices.vertices());
+        program.set_used();
+        program.set_mat3("view", &camera::window_matrix(layout));
+        self.buffers.draw_arrays(gl::TRIANGLES, vertices.triangles());
+        self.buffers.draw_arrays(gl::LINES, vertices.lines());
+    }
+}
+
+impl Default for PerfOverlay {
+    fn default() -> Self {
+        PerfOverlay::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: f64) -> TraceEvent {
+        TraceEvent { name: "frame", thread: 1, start, duration: 1.0 }
+    }
+
+    #[test]
+    fn trace_keeps_the_most_recent_spans() {
+        let mut trace = Trace::new(3);
+        for start in 0..5 {
+            trace.push(span(start as f64));
+        }
+        let starts: Vec<f64> = trace.spans.iter().map(|event| event.start).collect();
+        assert_eq!(starts, vec![2.0, 3.0, 4.0]);
+        assert_eq!(trace.dropped, 2);
+    }
+
+    #[test]
+    fn scopes_record_when_their_block_ends() {
+        begin_frame();
+        {
+            let _scope = scope(Phase::Swap);
+            start_trace();
+        }
+        end_frame();
+        PROFILER.with(|profiler| {
+            let profiler = profiler.borrow();
+            let names: Vec<&str> = profiler.trace.as_ref().unwrap().spans.iter().map(|event| event.name).collect();
+            assert_eq!(names, vec!["swap", "frame"]);
+            assert_eq!(profiler.frames.len(), 1);
+        });
+        assert!(is_tracing());
+        PROFILER.with(|profiler| profiler.borrow_mut().trace = None);
+    }
+}
@@ -0,0 +1,636 @@
+// Import the raycaster's per-ray results, the player and file utilities
+use crate::player::Player;
+use crate::render_gl::{ self, RayBuffers, WallSide };
+use crate::window_gl::RAYS_COUNT;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+// Fields of a ray record, in file order (CSV columns and JSON keys)
+const FIELDS: [&str; 13] = [
+    "index",
+    "angle",
+    "h_x",
+    "h_y",
+    "h_dist",
+    "v_x",
+    "v_y",
+    "v_dist",
+    "s_dist",
+    "side",
+    "corrected_dist",
+    "wall_top",
+    "wall_bottom",
+];
+
+// One ray of a frame, as the grid raycaster saw it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayRecord {
+    pub index: usize, // Ray index, left to right
+    pub angle: f32, // World angle of the ray
+    pub h_hit: (f32, f32), // Last leg's candidate hit on a horizontal grid line
+    pub h_dist: f32, // Total distance to `h_hit` (`hrays`)
+    pub v_hit: (f32, f32), // Last leg's candidate hit on a vertical grid line
+    pub v_dist: f32, // Total distance to `v_hit` (`vrays`)
+    pub s_dist: f32, // Total distance to a wall segment (`srays`, infinity when none)
+    pub side: WallSide, // The wall the ray's column shows
+    pub corrected_dist: f32, // Fisheye-corrected distance to that wall
+    pub wall_top: i32, // First row of the wall slice
+    pub wall_bottom: i32, // Row just below the wall slice
+}
+
+impl RayRecord {
+    // Field values as text, in `FIELDS` order (floats in their shortest exact form)
+    fn values(&self) -> [String; 13] {
+        [
+            self.index.to_string(),
+            self.angle.to_string(),
+            self.h_hit.0.to_string(),
+            self.h_hit.1.to_string(),
+            self.h_dist.to_string(),
+            self.v_hit.0.to_string(),
+            self.v_hit.1.to_string(),
+            self.v_dist.to_string(),
+            self.s_dist.to_string(),
+            self.side.name().to_string(),
+            self.corrected_dist.to_string(),
+            self.wall_top.to_string(),
+            self.wall_bottom.to_string(),
+        ]
+    }
+
+    // Record from field values in `FIELDS` order (non-finite floats as `inf`, `-inf` or `NaN`)
+    fn parse(values: &[&str]) -> Result<RayRecord, String> {
+        if values.len() != FIELDS.len() {
+            return Err(format!("expected {} fields, found {}", FIELDS.len(), values.len()));
+        }
+        let number = |at: usize| {
+            values[at].trim().parse::<f32>().map_err(|_| format!("bad {} `{}`", FIELDS[at], values[at]))
+        };
+        let integer = |at: usize| {
+            values[at].trim().parse::<i32>().map_err(|_| format!("bad {} `{}`", FIELDS[at], values[at]))
+        };
+        let side = match values[9].trim() {
+            "horizontal" => WallSide::Horizontal,
+            "vertical" => WallSide::Vertical,
+            "segment" => WallSide::Segment,
+            other => {
+                return Err(format!("bad side `{}`", other));
+            }
+        };
+        Ok(RayRecord {
+            index: integer(0)? as usize,
+            angle: number(1)?,
+            h_hit: (number(2)?, number(3)?),
+            h_dist: number(4)?,
+            v_hit: (number(5)?, number(6)?),
+            v_dist: number(7)?,
+            s_dist: number(8)?,
+            side,
+            corrected_dist: number(10)?,
+            wall_top: integer(11)?,
+            wall_bottom: integer(12)?,
+        })
+    }
+}
+
+// Every ray of one frame, with the pose it was cast from
+#[derive(Debug, Clone, PartialEq)]
+pub struct RayDump {
+    pub pose: (f32, f32, f32), // Player x, y and direction
+    pub rays: Vec<RayRecord>,
+}
+
+impl RayDump {
+    // Record every ray of a frame from the buffers `cast_rays` filled for the player's pose
+    pub fn capture(player: &Player, buffers: &RayBuffers) -> RayDump {
+        let angles = render_gl::ray_angles(player);
+        let rays = (0..RAYS_COUNT as usize)
+            .map(|ray| {
+                let (side, raw_dist) = render_gl::nearest_wall(buffers.hrays[ray], buffers.vrays[ray], buffers.srays[ray]);
+                let corrected_dist = raw_dist * render_gl::column_screen_angle(render_gl::ray_column(ray)).cos();
+                let (wall_top, wall_bottom) = render_gl::wall_span(corrected_dist);
+                RayRecord {
+                    index: ray,
+                    angle: angles[ray],
+                    h_hit: buffers.h_hits[ray],
+                    h_dist: buffers.hrays[ray],
+                    v_hit: buffers.v_hits[ray],
+                    v_dist: buffers.vrays[ray],
+                    s_dist: buffers.srays[ray],
+                    side,
+                    corrected_dist,
+                    wall_top,
+                    wall_bottom,
+                }
+            })
+            .collect();
+        RayDump { pose: (player.x_pos, player.y_pos, player.get_dir()), rays }
+    }
+
+    // Write the dump as CSV if the path ends in `.csv`, otherwise as JSON
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = if is_csv(path) { self.to_csv() } else { self.to_json() };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        fs::write(path, text).map_err(|e| e.to_string())
+    }
+
+    // Read a dump written by `save` (CSV or JSON by extension; one record per line)
+    pub fn load(path: &Path) -> Result<RayDump, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let dump = if is_csv(path) { RayDump::from_csv(&text) } else { RayDump::from_json(&text) };
+        dump.map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // A `# pose` comment line, a header row and one row per ray
+    fn to_csv(&self) -> String {
+        let (x, y, dir) = self.pose;
+        let mut csv = format!("# pose {} {} {}\n{}\n", x, y, dir, FIELDS.join(","));
+        for ray in &self.rays {
+            csv.push_str(&ray.values().join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    fn from_csv(text: &str) -> Result<RayDump, String> {
+        let mut pose = None;
+        let mut rays = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if let Some(values) = line.strip_prefix("# pose") {
+                let values: Vec<f32> = values.split_whitespace().filter_map(|value| value.parse().ok()).collect();
+                if let [x, y, dir] = values[..] {
+                    pose = Some((x, y, dir));
+                }
+            } else if !line.is_empty() && !line.starts_with('#') && line != FIELDS.join(",") {
+                let values: Vec<&str> = line.split(',').collect();
+                rays.push(RayRecord::parse(&values).map_err(|e| format!("line {}: {}", number + 1, e))?);
+            }
+        }
+        Ok(RayDump { pose: pose.ok_or("missing `# pose` line")?, rays })
+    }
+
+    // A JSON object with the pose and the rays, one ray object per line. JSON has no infinity or
+    // NaN, so non-finite values (e.g. `s_dist` without a wall segment) are written as the strings
+    // "inf", "-inf" and "NaN".
+    fn to_json(&self) -> String {
+        let (x, y, dir) = self.pose;
+        let mut json = format!("{{\n\"pose\": {{\"x\": {}, \"y\": {}, \"dir\": {}}},\n\"rays\": [", x, y, dir);
+        for (index, ray) in self.rays.iter().enumerate() {
+            let fields: Vec<String> = FIELDS.iter()
+                .zip(ray.values().iter())
+                .map(|(name, value)| {
+                    match value.as_str() {
+                        "inf" | "-inf" | "NaN" => format!("\"{}\": \"{}\"", name, value),
+                        _ if *name == "side" => format!("\"{}\": \"{}\"", name, value),
+                        _ => format!("\"{}\": {}", name, value),
+                    }
+                })
+                .collect();
+            let separator = if index == 0 { "\n" } else { ",\n" };
+            let _ = write!(json, "{}{{{}}}", separator, fields.join(", "));
+        }
+        json.push_str("\n]\n}\n");
+        json
+    }
+
+    fn from_json(text: &str) -> Result<RayDump, String> {
+        let document = Json::parse(text)?;
+        let pose = document.get("pose").ok_or("missing pose")?;
+        let coordinate = |name: &str| -> Result<f32, String> {
+            pose.get(name)
+                .and_then(Json::as_number)
+                .and_then(|value| value.parse().ok())
+                .ok_or(format!("bad pose {}", name))
+        };
+        let pose = (coordinate("x")?, coordinate("y")?, coordinate("dir")?);
+        let rays = match document.get("rays") {
+            Some(Json::Array(rays)) => rays,
+            _ => {
+                return Err(String::from("missing rays"));
+            }
+        };
+        let rays = rays
+            .iter()
+            .enumerate()
+            .map(|(at, ray)| {
+                let values = FIELDS.iter()
+                    .map(|name| {
+                        match ray.get(name) {
+                            Some(Json::Number(value)) => Ok(value.as_str()),
+                            // Strings: the side, and non-finite numbers
+                            Some(Json::String(value)) => Ok(value.as_str()),
+                            Some(_) => Err(format!("bad {}", name)),
+                            None => Err(format!("missing {}", name)),
+                        }
+                    })
+                    .collect::<Result<Vec<&str>, String>>();
+                values.and_then(|values| RayRecord::parse(&values)).map_err(|e| format!("ray {}: {}", at, e))
+            })
+            .collect::<Result<Vec<RayRecord>, String>>()?;
+        Ok(RayDump { pose, rays })
+    }
+}
+
+// --- JSON Documents, as Read by `RayDump::load` ---
+// Numbers keep their text, so floats read back exactly as they were written
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    // Parse a whole document (one value, surrounded by nothing but whitespace)
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut reader = JsonReader { bytes: text.as_bytes(), at: 0 };
+        let value = reader.value()?;
+        reader.skip_whitespace();
+        if reader.at < reader.bytes.len() {
+            return Err(reader.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    // A member of an object
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    // The text of a number
+    fn as_number(&self) -> Option<&str> {
+        match self {
+            Json::Number(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+// Recursive descent over the bytes of a JSON document
+struct JsonReader<'a> {
+    bytes: &'a [u8],
+    at: usize, // Next byte to read
+}
+
+impl JsonReader<'_> {
+    fn error(&self, what: &str) -> String {
+        format!("{} at byte {}", what, self.at)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.at < self.bytes.len() && matches!(self.bytes[self.at], b' ' | b'\t' | b'\n' | b'\r') {
+            self.at += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.at).copied()
+    }
+
+    // Consume `literal` if the input continues with it
+    fn eat(&mut self, literal: &str) -> bool {
+        if self.bytes[self.at..].starts_with(literal.as_bytes()) {
+            self.at += literal.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ if self.eat("null") => Ok(Json::Null),
+            _ if self.eat("true") => Ok(Json::Bool(true)),
+            _ if self.eat("false") => Ok(Json::Bool(false)),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.at += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.eat("}") {
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a member name"));
+            }
+            let name = self.string()?;
+            self.skip_whitespace();
+            if !self.eat(":") {
+                return Err(self.error("expected `:`"));
+            }
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            if self.eat("}") {
+                return Ok(Json::Object(members));
+            }
+            if !self.eat(",") {
+                return Err(self.error("expected `,` or `}`"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.at += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.eat("]") {
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            if self.eat("]") {
+                return Ok(Json::Array(items));
+            }
+            if !self.eat(",") {
+                return Err(self.error("expected `,` or `]`"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.at += 1;
+        let mut text = String::new();
+        loop {
+            let start = self.at;
+            while self.at < self.bytes.len() && !matches!(self.bytes[self.at], b'"' | b'\\' | 0..=0x1f) {
+                self.at += 1;
+            }
+            // Only ASCII delimiters were skipped, so the run is valid UTF-8
+            text.push_str(std::str::from_utf8(&self.bytes[start..self.at]).map_err(|e| e.to_string())?);
+            match self.peek() {
+                Some(b'"') => {
+                    self.at += 1;
+                    return Ok(text);
+                }
+                Some(b'\\') => {
+                    self.at += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let code = self.hex_escape()?;
+                            // A high surrogate is followed by the low half of the pair
+                            let code = if (0xd800..0xdc00).contains(&code) && self.eat("\\u") {
+                                self.at -= 1;
+                                let low = self.hex_escape()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("unpaired surrogate"));
+                                }
+                                0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00)
+                            } else {
+                                code
+                            };
+                            self.at -= 1;
+                            char::from_u32(code).ok_or_else(|| self.error("bad \\u escape"))?
+                        }
+                        _ => {
+                            return Err(self.error("bad escape"));
+                        }
+                    };
+                    self.at += 1;
+                    text.push(escaped);
+                }
+                Some(_) => {
+                    return Err(self.error("control character in string"));
+                }
+                None => {
+                    return Err(self.error("unterminated string"));
+                }
+            }
+        }
+    }
+
+    // Read the four hex digits after a `u`, leaving `at` just past them
+    fn hex_escape(&mut self) -> Result<u32, String> {
+        let digits = self.bytes
+            .get(self.at + 1..self.at + 5)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("bad \\u escape"))?;
+        self.at += 5;
+        Ok(digits)
+    }
+
+    // `-? (0 | [1-9][0-9]*) (. [0-9]+)? ([eE] [+-]? [0-9]+)?`
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.at;
+        let digits = |reader: &mut JsonReader| {
+            let from = reader.at;
+            while reader.peek().is_some_and(|byte| byte.is_ascii_digit()) {
+                reader.at += 1;
+            }
+            reader.at > from
+        };
+        self.eat("-");
+        if !self.eat("0") && !digits(self) {
+            return Err(self.error("bad number"));
+        }
+        if self.eat(".") && !digits(self) {
+            return Err(self.error("bad fraction"));
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.at += 1;
+            if !self.eat("+") {
+                self.eat("-");
+            }
+            if !digits(self) {
+                return Err(self.error("bad exponent"));
+            }
+        }
+        // The number's text is ASCII
+        Ok(Json::Number(String::from_utf8_lossy(&self.bytes[start..self.at]).into_owned()))
+    }
+}
+
+// Whether a dump path is written as CSV
+fn is_csv(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("csv"))
+}
+
+// One field of one ray that differs between two dumps
+#[derive(Debug, Clone)]
+pub struct FieldChange {
+    pub ray: usize, // Ray index
+    pub field: &'static str, // Field name (as in `FIELDS`)
+    pub before: String, // Value in the first dump
+    pub after: String, // Value in the second dump
+}
+
+// --- Compare Two Dumps Ray by Ray ---
+// Numeric fields count as changed when they differ by more than `tolerance` (infinities match
+// each other); rays missing from either dump are reported under the `ray` field
+pub fn diff(before: &RayDump, after: &RayDump, tolerance: f32) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    for ray in 0..before.rays.len().max(after.rays.len()) {
+        let (old, new) = match (before.rays.get(ray), after.rays.get(ray)) {
+            (Some(old), Some(new)) => (old.values(), new.values()),
+            (old, new) => {
+                let present = |record: Option<&RayRecord>| String::from(if record.is_some() { "present" } else { "missing" });
+                changes.push(FieldChange { ray, field: "ray", before: present(old), after: present(new) });
+                continue;
+            }
+        };
+        for (at, field) in FIELDS.iter().enumerate() {
+            let same = match (old[at].parse::<f32>(), new[at].parse::<f32>()) {
+                (Ok(a), Ok(b)) if a.is_finite() && b.is_finite() => (a - b).abs() <= tolerance,
+                _ => old[at] == new[at],
+            };
+            if !same {
+                changes.push(FieldChange { ray, field, before: old[at].clone(), after: new[at].clone() });
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw_gl::VertexArrayWrapper;
+    use crate::segment::SegmentIndex;
+    use crate::window_gl::SEGMENTS;
+
+    // The rays seen from a pose, as `cast_rays` leaves them
+    fn cast(x: f32, y: f32, dir: f32) -> RayDump {
+        let mut player = Player::new(x, y);
+        player.update_dir(dir);
+        let mut buffers = RayBuffers::new();
+        render_gl::cast_rays(&mut VertexArrayWrapper::new(), &player, &SegmentIndex::new(&SEGMENTS), &mut buffers, false);
+        RayDump::capture(&player, &buffers)
+    }
+
+    // The same dump with some values JSON can't write as numbers
+    fn with_odd_values(mut dump: RayDump) -> RayDump {
+        dump.rays[0].s_dist = f32::NEG_INFINITY;
+        dump.rays[1].h_dist = f32::NAN;
+        dump.rays[2].angle = 1e-7;
+        dump
+    }
+
+    // Dumps compare field by field as text, so NaNs in the same place are equal
+    fn assert_same(a: &RayDump, b: &RayDump) {
+        assert_eq!(a.pose, b.pose);
+        assert_eq!(a.rays.len(), b.rays.len());
+        for (a, b) in a.rays.iter().zip(&b.rays) {
+            assert_eq!(a.values(), b.values());
+        }
+    }
+
+    #[test]
+    fn capture_matches_the_inspected_rays() {
+        // Looking through the portal and at a wall segment
+        for &(x, y, dir) in &[(150.0, 405.0, std::f32::consts::PI), (80.0, 100.0, 3.9)] {
+            let dump = cast(x, y, dir);
+            let mut player = Player::new(x, y);
+            player.update_dir(dir);
+            let segments = SegmentIndex::new(&SEGMENTS);
+            assert_eq!(dump.rays.len(), RAYS_COUNT as usize);
+            for record in &dump.rays {
+                let report = render_gl::inspect_ray(&player, &segments, render_gl::ray_column(record.index));
+                let last = report.legs.last().unwrap();
+                assert_eq!(record.angle, report.angle);
+                assert_eq!((record.h_hit, record.v_hit), (last.h_hit, last.v_hit), "ray {}", record.index);
+                assert_eq!((record.h_dist, record.v_dist, record.s_dist), (report.h_dist, report.v_dist, report.s_dist));
+                assert_eq!(record.side, report.side);
+                assert_eq!(record.corrected_dist, report.corrected_dist);
+            }
+        }
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let dump = with_odd_values(cast(200.0, 200.0, 0.0));
+        assert_same(&RayDump::from_csv(&dump.to_csv()).unwrap(), &dump);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let dump = with_odd_values(cast(80.0, 100.0, 3.9));
+        let json = dump.to_json();
+        assert!(json.contains("\"s_dist\": \"-inf\"") && json.contains("\"h_dist\": \"NaN\""));
+        assert_same(&RayDump::from_json(&json).unwrap(), &dump);
+    }
+
+    #[test]
+    fn json_rejects_nulls_and_malformed_documents() {
+        let json = cast(200.0, 200.0, 0.0).to_json();
+        let nulled = json.replacen("\"s_dist\": ", "\"s_dist\": null, \"was\": ", 1);
+        assert!(RayDump::from_json(&nulled).unwrap_err().contains("bad s_dist"));
+        assert!(RayDump::from_json(&json.replacen("\"h_dist\": ", "\"h_dist\": \"huge\", \"was\": ", 1)).is_err());
+        assert!(RayDump::from_json(&json[..json.len() / 2]).is_err());
+        assert!(RayDump::from_json(&format!("{} {{}}", json)).is_err());
+        assert!(RayDump::from_json("{\"pose\": {\"x\": 1, \"y\": 2, \"dir\": .5}, \"rays\": []}").is_err());
+    }
+
+    #[test]
+    fn json_reader_handles_the_whole_grammar() {
+        let value = Json::parse(" {\"a\": [1, -0.5e+3, true, false, null], \"b\\u00e9\\n\": \"\\ud83d\\ude00\\\"x\", \"c\": {}} ").unwrap();
+        assert_eq!(
+            value.get("a"),
+            Some(
+                &Json::Array(
+                    vec![
+                        Json::Number("1".into()),
+                        Json::Number("-0.5e+3".into()),
+                        Json::Bool(true),
+                        Json::Bool(false),
+                        Json::Null
+                    ]
+                )
+            )
+        );
+        assert_eq!(value.get("b\u{e9}\n"), Some(&Json::String("\u{1f600}\"x".into())));
+        assert_eq!(value.get("c"), Some(&Json::Object(Vec::new())));
+        for bad in ["01", "[1,]", "{\"a\" 1}", "\"\\ud83d\"", "\"tab\there\"", "nul", "-"] {
+            assert!(Json::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn diff_lists_changed_fields_within_the_tolerance() {
+        let before = cast(200.0, 200.0, 0.0);
+        let mut after = before.clone();
+        after.rays[3].h_dist += 0.5;
+        after.rays[7].side = WallSide::Segment;
+        after.rays[9].s_dist = f32::NAN;
+        after.rays.pop();
+
+        let changes = diff(&before, &after, 0.1);
+        let fields: Vec<(usize, &str)> = changes.iter().map(|change| (change.ray, change.field)).collect();
+        let last = before.rays.len() - 1;
+        assert_eq!(fields, vec![(3, "h_dist"), (7, "side"), (9, "s_dist"), (last, "ray")]);
+        assert_eq!(changes[3].after, "missing");
+
+        // Within the tolerance only the other changes remain
+        assert_eq!(diff(&before, &after, 1.0).len(), 3);
+        assert!(diff(&before, &before, 0.0).is_empty());
+    }
+}
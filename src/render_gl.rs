@@ -1,11 +1,10 @@
 // Import OpenGL bindings and standard library utilities
 use gl;
 use std;
+use std::cell::RefCell;
+use std::collections::{ HashMap, HashSet };
 use std::ffi::{ CStr, CString };
 use crate::window_gl::{
-    HEIGHT,
-    WIDTH,
-    MAP,
     MAP_S,
     MAP_X,
     MAP_Y,
@@ -14,21 +13,84 @@ use crate::window_gl::{
     RENDER_Y,
     RAYS_COUNT,
     FOV,
+    TILE_EMPTY,
+    TILE_WALL,
+    TILE_PORTAL,
+    SKY_CELLS,
+    Layout,
 };
+use std::convert::TryInto;
 use std::f32::consts::PI;
-use crate::draw_gl::{ get_x, get_y, VertexArrayWrapper, Color };
+use crate::draw_gl::{ get_x, get_y, BufferArrayBinder, ColorVertex, TexturedVertex, TileInstance, VertexArrayWrapper, Color };
+use crate::lightmap::Lighting;
+use crate::log::Level;
+use crate::log_event;
+use crate::gl_check;
+use crate::sky::Sky;
 use crate::player;
-use crate::square;
+use crate::portal::{ self, Face };
+use crate::segment::{ SegmentHit, SegmentIndex };
+use crate::parallel;
+use crate::profiler::{ self, Phase };
+use crate::simd::{ self, F32x4, LANES };
 // Import the `draw_gl` module for drawing utilities
 
 // Represents an OpenGL shader program
 pub struct Program {
     id: gl::types::GLuint, // OpenGL ID for the program
+    uniforms: HashMap<String, ActiveUniform>, // Active uniforms by name, queried once after linking
+    names: String, // Shaders the program was linked from, for error messages
+    reported: RefCell<HashSet<String>>, // Uniform problems already logged (each is logged once)
+}
+
+// Location and GLSL type of an active uniform
+#[derive(Debug, Clone, Copy)]
+struct ActiveUniform {
+    location: gl::types::GLint, // Location passed to `glProgramUniform*`
+    kind: gl::types::GLenum, // GLSL type, e.g. FLOAT_VEC3 or SAMPLER_2D
+}
+
+// Value types the typed setters write, each accepting some GLSL uniform types
+#[derive(Debug, Clone, Copy)]
+enum UniformType {
+    Int,
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+    Mat3,
+    Mat4,
+    Sampler,
+}
+
+impl UniformType {
+    // Whether a uniform of the GLSL type can be set with this setter
+    fn accepts(self, kind: gl::types::GLenum) -> bool {
+        match self {
+            UniformType::Int => kind == gl::INT || kind == gl::BOOL,
+            UniformType::Float => kind == gl::FLOAT,
+            UniformType::Vec2 => kind == gl::FLOAT_VEC2,
+            UniformType::Vec3 => kind == gl::FLOAT_VEC3,
+            UniformType::Vec4 => kind == gl::FLOAT_VEC4,
+            UniformType::Mat3 => kind == gl::FLOAT_MAT3,
+            UniformType::Mat4 => kind == gl::FLOAT_MAT4,
+            UniformType::Sampler => {
+                matches!(
+                    kind,
+                    gl::SAMPLER_2D | gl::SAMPLER_3D | gl::SAMPLER_CUBE | gl::INT_SAMPLER_2D | gl::UNSIGNED_INT_SAMPLER_2D
+                )
+            }
+        }
+    }
 }
 
 impl Program {
     // Creates a new shader program from a list of shaders
+    #[track_caller]
     pub fn from_shaders(shaders: &[Shader]) -> Result<Program, String> {
+        let caller = std::panic::Location::caller();
+        let names: Vec<&str> = shaders.iter().map(|shader| shader.name()).collect();
+
         // Create a new OpenGL program
         let program_id = unsafe { gl::CreateProgram() };
 
@@ -68,7 +130,18 @@ impl Program {
                 );
             }
 
-            return Err(error.to_string_lossy().into_owned());
+            unsafe {
+                gl::DeleteProgram(program_id);
+            }
+            return Err(
+                format!(
+                    "linking program ({}) failed at {}:{}:\n{}",
+                    names.join(" + "),
+                    caller.file(),
+                    caller.line(),
+                    error.to_string_lossy().trim_end()
+                )
+            );
         }
 
         // Detach shaders after linking
@@ -78,7 +151,13 @@ impl Program {
             }
         }
 
-        Ok(Program { id: program_id })
+        let uniforms = active_uniforms(program_id);
+        Ok(Program {
+            id: program_id,
+            uniforms,
+            names: names.join(" + "),
+            reported: RefCell::new(HashSet::new()),
+        })
     }
 
     // Returns the OpenGL ID of the program
@@ -89,8 +168,86 @@ impl Program {
     // Sets this program as the active program in OpenGL
     pub fn set_used(&self) {
         unsafe {
-            gl::UseProgram(self.id);
+            gl_check!(gl::UseProgram(self.id), "using program {}", self.id);
+        }
+    }
+
+    // Returns the location of a uniform by name (-1 if the program has no such active uniform)
+    pub fn uniform_location(&self, name: &str) -> gl::types::GLint {
+        self.uniforms.get(name).map_or(-1, |uniform| uniform.location)
+    }
+
+    // Typed uniform setters. They write through `glProgramUniform*`, so the program doesn't have to
+    // be in use. In debug builds, names the program doesn't have and values of the wrong type are
+    // logged (once per uniform); the driver drops uniforms a shader never reads, which shows up
+    // here as an unknown name.
+    pub fn set_int(&self, name: &str, value: i32) {
+        if let Some(location) = self.checked_location(name, UniformType::Int) {
+            unsafe { gl::ProgramUniform1i(self.id, location, value) }
+        }
+    }
+
+    pub fn set_bool(&self, name: &str, value: bool) {
+        self.set_int(name, value as i32);
+    }
+
+    pub fn set_float(&self, name: &str, value: f32) {
+        if let Some(location) = self.checked_location(name, UniformType::Float) {
+            unsafe { gl::ProgramUniform1f(self.id, location, value) }
+        }
+    }
+
+    pub fn set_vec2(&self, name: &str, value: [f32; 2]) {
+        if let Some(location) = self.checked_location(name, UniformType::Vec2) {
+            unsafe { gl::ProgramUniform2f(self.id, location, value[0], value[1]) }
+        }
+    }
+
+    pub fn set_vec3(&self, name: &str, value: [f32; 3]) {
+        if let Some(location) = self.checked_location(name, UniformType::Vec3) {
+            unsafe { gl::ProgramUniform3f(self.id, location, value[0], value[1], value[2]) }
+        }
+    }
+
+    pub fn set_vec4(&self, name: &str, value: [f32; 4]) {
+        if let Some(location) = self.checked_location(name, UniformType::Vec4) {
+            unsafe { gl::ProgramUniform4f(self.id, location, value[0], value[1], value[2], value[3]) }
+        }
+    }
+
+    // Matrices are given column by column, like GLSL stores them
+    pub fn set_mat3(&self, name: &str, columns: &[[f32; 3]; 3]) {
+        if let Some(location) = self.checked_location(name, UniformType::Mat3) {
+            unsafe { gl::ProgramUniformMatrix3fv(self.id, location, 1, gl::FALSE, columns.as_ptr() as *const f32) }
+        }
+    }
+
+    pub fn set_mat4(&self, name: &str, columns: &[[f32; 4]; 4]) {
+        if let Some(location) = self.checked_location(name, UniformType::Mat4) {
+            unsafe { gl::ProgramUniformMatrix4fv(self.id, location, 1, gl::FALSE, columns.as_ptr() as *const f32) }
+        }
+    }
+
+    // Point a sampler at a texture unit (0 = TEXTURE0)
+    pub fn set_sampler(&self, name: &str, unit: u32) {
+        if let Some(location) = self.checked_location(name, UniformType::Sampler) {
+            unsafe { gl::ProgramUniform1i(self.id, location, unit as i32) }
+        }
+    }
+
+    // Location of a uniform for a setter, or None (logged in debug builds) if it can't be set
+    fn checked_location(&self, name: &str, setter: UniformType) -> Option<gl::types::GLint> {
+        let problem = match self.uniforms.get(name) {
+            Some(uniform) if setter.accepts(uniform.kind) => {
+                return Some(uniform.location);
+            }
+            Some(uniform) => format!("{:?} setter used on uniform `{}` of type {:#06x}", setter, name, uniform.kind),
+            None => format!("no active uniform named `{}`", name),
+        };
+        if cfg!(debug_assertions) && self.reported.borrow_mut().insert(name.to_string()) {
+            log_event!(Level::Warn, "gl", "uniform problem"; program = self.names, problem = problem);
         }
+        None
     }
 }
 
@@ -106,29 +263,41 @@ impl Drop for Program {
 // Represents an OpenGL shader (vertex or fragment)
 pub struct Shader {
     id: gl::types::GLuint, // OpenGL ID for the shader
+    name: String, // Name used in error messages (usually the file name)
 }
 
 impl Shader {
     // Creates a shader from source code and a shader type (e.g., vertex or fragment)
-    pub fn from_source(source: &CStr, kind: gl::types::GLenum) -> Result<Shader, String> {
-        let id = shader_from_source(source, kind)?;
-        Ok(Shader { id })
+    #[track_caller]
+    pub fn from_source(name: &str, source: &CStr, kind: gl::types::GLenum) -> Result<Shader, String> {
+        let caller = std::panic::Location::caller();
+        let id = shader_from_source(source, kind).map_err(|error| {
+            format!("compiling {} failed at {}:{}:\n{}", name, caller.file(), caller.line(), error.trim_end())
+        })?;
+        Ok(Shader { id, name: name.to_string() })
     }
 
     // Convenience function to create a vertex shader
-    pub fn from_vert_source(source: &CStr) -> Result<Shader, String> {
-        Shader::from_source(source, gl::VERTEX_SHADER)
+    #[track_caller]
+    pub fn from_vert_source(name: &str, source: &CStr) -> Result<Shader, String> {
+        Shader::from_source(name, source, gl::VERTEX_SHADER)
     }
 
     // Convenience function to create a fragment shader
-    pub fn from_frag_source(source: &CStr) -> Result<Shader, String> {
-        Shader::from_source(source, gl::FRAGMENT_SHADER)
+    #[track_caller]
+    pub fn from_frag_source(name: &str, source: &CStr) -> Result<Shader, String> {
+        Shader::from_source(name, source, gl::FRAGMENT_SHADER)
     }
 
     // Returns the OpenGL ID of the shader
     pub fn id(&self) -> gl::types::GLuint {
         self.id
     }
+
+    // Returns the name of the shader
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 // Automatically deletes the shader when it goes out of scope
@@ -175,12 +344,99 @@ fn shader_from_source(source: &CStr, kind: gl::types::GLenum) -> Result<gl::type
             );
         }
 
+        unsafe {
+            gl::DeleteShader(id);
+        }
         return Err(error.to_string_lossy().into_owned());
     }
 
     Ok(id)
 }
 
+// Queries every active uniform of a linked program (arrays are listed under `name` and `name[0]`)
+fn active_uniforms(id: gl::types::GLuint) -> HashMap<String, ActiveUniform> {
+    let mut count: gl::types::GLint = 0;
+    let mut max_len: gl::types::GLint = 0;
+    unsafe {
+        gl::GetProgramiv(id, gl::ACTIVE_UNIFORMS, &mut count);
+        gl::GetProgramiv(id, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_len);
+    }
+
+    let mut uniforms = HashMap::new();
+    for index in 0..count.max(0) as gl::types::GLuint {
+        let mut buffer = vec![0u8; max_len.max(1) as usize];
+        let mut len: gl::types::GLsizei = 0;
+        let mut size: gl::types::GLint = 0;
+        let mut kind: gl::types::GLenum = 0;
+        unsafe {
+            gl::GetActiveUniform(
+                id,
+                index,
+                buffer.len() as gl::types::GLsizei,
+                &mut len,
+                &mut size,
+                &mut kind,
+                buffer.as_mut_ptr() as *mut gl::types::GLchar
+            );
+        }
+        buffer.truncate(len.max(0) as usize);
+        let name = String::from_utf8_lossy(&buffer).into_owned();
+        let c_name = CString::new(name.clone()).unwrap();
+        let location = unsafe { gl::GetUniformLocation(id, c_name.as_ptr()) };
+        if location < 0 {
+            continue; // Uniform block members have no location
+        }
+        let uniform = ActiveUniform { location, kind };
+        if let Some(base) = name.strip_suffix("[0]") {
+            uniforms.insert(base.to_string(), uniform);
+        }
+        uniforms.insert(name, uniform);
+    }
+    uniforms
+}
+
+// In debug builds, ask the driver once per program whether the program in use can run with the
+// current state. Called right before drawing: validating right after linking reports sampler
+// conflicts for programs whose samplers simply haven't been set yet.
+pub fn validate_current_program() {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    thread_local! {
+        static VALIDATED: RefCell<HashSet<gl::types::GLuint>> = RefCell::new(HashSet::new());
+    }
+    let mut id: gl::types::GLint = 0;
+    unsafe {
+        gl::GetIntegerv(gl::CURRENT_PROGRAM, &mut id);
+    }
+    let id = id as gl::types::GLuint;
+    if id == 0 || !VALIDATED.with(|validated| validated.borrow_mut().insert(id)) {
+        return;
+    }
+    if let Err(error) = validate_program(id) {
+        log_event!(Level::Warn, "gl", "program did not validate"; program = id, error = error);
+    }
+}
+
+// Checks whether a linked program can run in the current GL state
+fn validate_program(id: gl::types::GLuint) -> Result<(), String> {
+    let mut status: gl::types::GLint = 1;
+    let mut len: gl::types::GLint = 0;
+    unsafe {
+        gl::ValidateProgram(id);
+        gl::GetProgramiv(id, gl::VALIDATE_STATUS, &mut status);
+        gl::GetProgramiv(id, gl::INFO_LOG_LENGTH, &mut len);
+    }
+    if status != 0 {
+        return Ok(());
+    }
+    let error = create_whitespace_cstring_with_len(len.max(0) as usize);
+    unsafe {
+        gl::GetProgramInfoLog(id, len, std::ptr::null_mut(), error.as_ptr() as *mut gl::types::GLchar);
+    }
+    Err(error.to_string_lossy().trim_end().to_string())
+}
+
 // Creates a CString filled with whitespace of the specified length
 fn create_whitespace_cstring_with_len(len: usize) -> CString {
     // Allocate a buffer of the specified size
@@ -193,60 +449,114 @@ fn create_whitespace_cstring_with_len(len: usize) -> CString {
     }
 }
 
-// --- Construct Vertices for All Geometry (map, player, lines, rays, canvas) ---
+// Minimap tiles, kept in their own static buffer as one instance per cell and drawn with a single
+// instanced call; the buffer is rebuilt only when the map it shows changes
+pub struct MinimapMesh {
+    buffers: BufferArrayBinder<TileInstance>, // VAO/VBO holding one instance per tile
+    tiles: [[u8; MAP_X as usize]; MAP_Y as usize], // Map the buffer was built from
+    tile_count: usize, // Number of instances in the buffer
+}
+
+impl MinimapMesh {
+    // Constructor to build and upload the tiles of a map
+    pub fn new(map: &[[u8; MAP_X as usize]; MAP_Y as usize]) -> MinimapMesh {
+        let mut mesh = MinimapMesh {
+            buffers: BufferArrayBinder::new(gl::STATIC_DRAW),
+            tiles: *map,
+            tile_count: 0,
+        };
+        mesh.upload();
+        mesh
+    }
+
+    // Rebuild the buffer if the map differs from the one last uploaded
+    pub fn update(&mut self, map: &[[u8; MAP_X as usize]; MAP_Y as usize]) {
+        if self.tiles != *map {
+            self.tiles = *map;
+            self.upload();
+        }
+    }
+
+    // Draw the tiles with minimap.vert (the program must be in use and its `view` set)
+    pub fn draw(&self, program: &Program) {
+        program.set_float("cell_size", MAP_S as f32);
+        // Two triangles per tile
+        self.buffers.draw_instanced(gl::TRIANGLES, 6, self.tile_count);
+    }
+
+    fn upload(&mut self) {
+        let instances = tile_instances(&self.tiles);
+        self.buffers.set_buffers(&instances);
+        self.tile_count = instances.len();
+    }
+}
+
+// --- Build One Minimap Instance per Map Cell ---
+pub fn tile_instances(map: &[[u8; MAP_X as usize]; MAP_Y as usize]) -> Vec<TileInstance> {
+    let mut instances = Vec::with_capacity((MAP_X * MAP_Y) as usize);
+    for (i, row) in map.iter().enumerate() {
+        for (ii, &tile) in row.iter().enumerate() {
+            let color = match tile {
+                TILE_WALL => Color::new(1.0, 1.0, 1.0), // Walls are white
+                TILE_PORTAL => Color::new(0.6, 0.2, 0.8), // Portal tiles are purple
+                _ => Color::new(0.0, 0.0, 0.0), // Empty space is black
+            };
+            instances.push(TileInstance { cell: [ii as f32, i as f32], color: color.get_colors() });
+        }
+    }
+    instances
+}
+
+// --- Construct Vertices for the Per-Frame Geometry (player, lines, FOV cone) ---
+// Positions are in world units (the debug view's camera matrix places them on screen); individual
+// ray lines are only pushed when `debug_rays` is set
 pub fn construct_vertices(
     player: &player::Player,
+    segments: &SegmentIndex,
     mut vertices: &mut VertexArrayWrapper,
-    hrays: &mut [f32; RAYS_COUNT as usize],
-    vrays: &mut [f32; RAYS_COUNT as usize],
-    _is_log: i32
+    rays: &mut RayBuffers,
+    debug_rays: bool
 ) {
-    // Map squares
-    for i in 0..=7 {
-        for ii in 0..=7 {
-            if MAP[i][ii] == 1 {
-                push_square_vertices(
-                    &mut vertices,
-                    square::Square::new(ii as i32, i as i32, Color::new(1.0, 1.0, 1.0))
-                );
-            } else {
-                push_square_vertices(
-                    &mut vertices,
-                    square::Square::new(ii as i32, i as i32, Color::new(0.0, 0.0, 0.0))
-                );
-            }
-        }
-    }
     // Player quad
     push_player_vertices(&mut vertices, player);
     // Player direction line
     push_line_vertices(&mut vertices, player);
-    // Ray lines and ray distances
-    cast_rays(&mut vertices, player, hrays, vrays, _is_log);
-    // Canvas quad (for displaying the raycasted texture)
-    create_canvas(&mut vertices);
-}
-
-// --- Create Canvas Quad Vertices (for displaying the texture) ---
-fn create_canvas(vertices: &mut VertexArrayWrapper) {
-    let points = [
-        [get_x(513.0, WIDTH), get_y(0.0, HEIGHT), 0.0, 1.0, 1.0, 1.0, 0.0, 0.0], // top-left
-        [get_x(1537.0, WIDTH), get_y(0.0, HEIGHT), 0.0, 1.0, 1.0, 1.0, 1.0, 0.0], // top-right
-        [get_x(513.0, WIDTH), get_y(512.0, HEIGHT), 0.0, 1.0, 1.0, 1.0, 0.0, 1.0], // bottom-left
-        [get_x(1537.0, WIDTH), get_y(512.0, HEIGHT), 0.0, 1.0, 1.0, 1.0, 1.0, 1.0], // bottom-right
-    ];
-    // First triangle
-    for &i in &[0, 1, 2] {
-        for &num in &points[i] {
-            vertices.push(num);
-        }
-    }
-    // Second triangle
-    for &i in &[1, 3, 2] {
-        for &num in &points[i] {
-            vertices.push(num);
-        }
-    }
+    // Wall segment lines
+    push_segment_vertices(vertices, segments);
+    // Ray distances, the FOV cone and (optionally) the ray lines
+    cast_rays(&mut vertices, player, segments, rays, debug_rays);
+}
+
+// --- Canvas Quad Vertices (for displaying the raycasted texture; rebuilt when the window is resized) ---
+pub fn canvas_vertices(layout: &Layout) -> [TexturedVertex; 6] {
+    let corner = |x: f32, y: f32, texcoord: [f32; 2]| TexturedVertex {
+        position: [get_x(x, layout.width as u32), get_y(y, layout.height as u32), 0.0],
+        color: [1.0, 1.0, 1.0],
+        texcoord,
+    };
+    let (x, y, width, height) = layout.canvas_view;
+    let (left, top) = (x as f32, y as f32);
+    let (right, bottom) = ((x + width) as f32, (y + height) as f32);
+    let top_left = corner(left, top, [0.0, 0.0]);
+    let top_right = corner(right, top, [1.0, 0.0]);
+    let bottom_left = corner(left, bottom, [0.0, 1.0]);
+    let bottom_right = corner(right, bottom, [1.0, 1.0]);
+    // Two triangles
+    [top_left, top_right, bottom_left, top_right, bottom_right, bottom_left]
+}
+
+// --- Full-Target Quad Vertices (for shaders drawing the whole canvas texture, e.g. raycast.frag) ---
+// Texture coordinates follow the texture's rows, so row 0 of the target gets v = 0 like an upload
+pub fn target_vertices() -> [TexturedVertex; 6] {
+    let corner = |x: f32, y: f32| TexturedVertex {
+        position: [x, y, 0.0],
+        color: [1.0, 1.0, 1.0],
+        texcoord: [(x + 1.0) / 2.0, (y + 1.0) / 2.0],
+    };
+    let (bottom_left, bottom_right) = (corner(-1.0, -1.0), corner(1.0, -1.0));
+    let (top_left, top_right) = (corner(-1.0, 1.0), corner(1.0, 1.0));
+    // Two triangles
+    [bottom_left, bottom_right, top_left, bottom_right, top_right, top_left]
 }
 
 // --- Push Vertices for the Player Quad ---
@@ -257,237 +567,705 @@ fn push_player_vertices(vertices: &mut VertexArrayWrapper, player: &player::Play
         player.bl_point,
         player.br_point,
     ];
-    for i in 0..=2 {
-        for num in points[i] {
-            vertices.push(num);
-        }
-        for num in player.color {
-            vertices.push(num);
-        }
+    for point in &points[0..=2] {
+        vertices.push(ColorVertex::new(*point, player.color));
     }
-    for i in 1..=3 {
-        for num in points[i] {
-            vertices.push(num);
-        }
-        for num in player.color {
-            vertices.push(num);
-        }
+    for point in &points[1..=3] {
+        vertices.push(ColorVertex::new(*point, player.color));
     }
     vertices.set_triangle_end(vertices.len());
 }
 
-// --- Push Vertices for a Map Square (as two triangles) ---
-fn push_square_vertices(vertices: &mut VertexArrayWrapper, wall: square::Square) {
-    let points: [[f32; 3]; 4] = wall.get_vertices();
-    for i in 0..=2 {
-        for num in points[i] {
-            vertices.push(num);
-        }
-        for num in wall.get_color() {
-            vertices.push(num);
-        }
-    }
-    for i in 1..=3 {
-        for num in points[i] {
-            vertices.push(num);
-        }
-        for num in wall.get_color() {
-            vertices.push(num);
+// --- Push Vertices for the Player's Direction Line ---
+fn push_line_vertices(vertices: &mut VertexArrayWrapper, player: &player::Player) {
+    let yellow = [1.0, 1.0, 0.0];
+    vertices.push(ColorVertex::new([player.get_player_x(4.0), player.get_player_y(4.0), 0.0], yellow));
+    vertices.push(
+        ColorVertex::new(
+            [
+                player.get_player_x(4.0 + player.get_x_dir() * 20.0),
+                player.get_player_y(4.0 + player.get_y_dir() * 20.0),
+                0.0,
+            ],
+            yellow
+        )
+    );
+}
+
+// --- Push Vertices for the Wall Segments (white lines) ---
+fn push_segment_vertices(vertices: &mut VertexArrayWrapper, segments: &SegmentIndex) {
+    for segment in segments.segments() {
+        for (x, y) in [
+            (segment.x1, segment.y1),
+            (segment.x2, segment.y2),
+        ] {
+            vertices.push(ColorVertex::new([x, y, 0.0], [1.0, 1.0, 1.0]));
         }
     }
 }
 
-// --- Push Vertices for the Player's Direction Line ---
-fn push_line_vertices(vertices: &mut VertexArrayWrapper, player: &player::Player) {
-    vertices.push(player.get_player_x(4.0));
-    vertices.push(player.get_player_y(4.0));
-    vertices.push(0.0);
-    vertices.push(1.0);
-    vertices.push(1.0);
-    vertices.push(0.0);
-    vertices.push(player.get_player_x(4.0 + player.get_x_dir() * 20.0));
-    vertices.push(player.get_player_y(4.0 + player.get_y_dir() * 20.0));
-    vertices.push(0.0);
-    vertices.push(1.0);
-    vertices.push(1.0);
-    vertices.push(0.0);
-}
-
-// --- Raycasting: Cast Rays, Store Distances, and Push Ray Vertices ---
-fn cast_rays(
+// --- Raycasting: Cast Rays, Store Distances, and Push the FOV Cone (and Debug Ray Lines) ---
+pub fn cast_rays(
     vertices: &mut VertexArrayWrapper,
     player: &player::Player,
-    hrays: &mut [f32; RAYS_COUNT as usize],
-    vrays: &mut [f32; RAYS_COUNT as usize],
-    _is_log: i32
+    segments: &SegmentIndex,
+    rays: &mut RayBuffers,
+    debug_rays: bool
 ) {
+    let _scope = profiler::scope(Phase::CastRays);
     let map = single_index_map();
-    let dr: f32 = FOV / (RAYS_COUNT as f32); // Ray angle increment scales with FOV and ray count
-    let mut mx: i32;
-    let mut my: i32;
-    let mut _mp: i32;
-    let mut dof: i32;
+    let angles = ray_angles(player);
+
+    // Trace the rays in parallel; each one only reads the map and the segments
+    let ox = player.x_pos + 4.0;
+    let oy = player.y_pos + 4.0;
+    let setups = RaySetups::new(ox, oy, &angles);
+    let mut traces: Vec<Option<RayTrace>> = (0..RAYS_COUNT).map(|_| None).collect();
+    parallel::for_each_chunk(&mut traces, |start, chunk| {
+        for (i, trace) in chunk.iter_mut().enumerate() {
+            let r = start + i;
+            *trace = Some(trace_ray(&map, ox, oy, angles[r], &setups.get(r), segments, &mut |_| {}));
+        }
+    });
+    store_traces(vertices, rays, &angles, (ox, oy), traces, debug_rays);
+}
 
-    let mut rx: f32;
-    let mut ry: f32;
-    let mut xo: f32;
-    let mut yo: f32;
-    let mut ra: f32 = player.get_dir() - dr * ((RAYS_COUNT as f32) / 2.0); // Start angle for rays
+// --- Raycasting: Store Traced Rays and Push the FOV Cone (and Debug Ray Lines) ---
+fn store_traces(
+    vertices: &mut VertexArrayWrapper,
+    rays: &mut RayBuffers,
+    angles: &[f32; RAYS_COUNT as usize],
+    (ox, oy): (f32, f32),
+    traces: Vec<Option<RayTrace>>,
+    debug_rays: bool
+) {
+    // Store the results and push the debug ray lines in ray order
+    let traces: Vec<RayTrace> = traces.into_iter().flatten().collect();
+    for (r, trace) in traces.iter().enumerate() {
+        if debug_rays {
+            for leg in &trace.legs {
+                push_trace_vertices(vertices, leg.ox, leg.oy, &leg.trace);
+            }
+            if let Some((leg_x, leg_y, hit)) = &trace.segment_hit {
+                push_segment_hit_vertices(vertices, *leg_x, *leg_y, hit);
+            }
+        }
+        rays.hrays[r] = trace.h_dist;
+        rays.vrays[r] = trace.v_dist;
+        rays.srays[r] = trace.s_dist;
+        let last = &trace.legs[trace.legs.len() - 1].trace;
+        rays.h_hits[r] = (last.h_hit.x, last.h_hit.y);
+        rays.v_hits[r] = (last.v_hit.x, last.v_hit.y);
+        rays.hits[r] = trace.hit;
+        rays.portal_counts[r] = trace.legs.len() - 1;
+        for (portal, leg) in rays.portals[r].iter_mut().zip(&trace.legs[1..]) {
+            *portal = PortalLeg::new(leg.start, leg.ox, leg.oy, leg.angle);
+        }
+        log_event!(Level::Trace, "raycast", "ray";
+            column = r,
+            angle = angles[r],
+            h_dist = trace.h_dist,
+            v_dist = trace.v_dist,
+            s_dist = trace.s_dist,
+            legs = trace.legs.len()
+        );
+    }
+    vertices.set_line_end(vertices.len());
 
-    for _r in 0..RAYS_COUNT {
+    // FOV cone: a fan from the eye through the point where each ray's first leg ends
+    let cone_color = [0.45, 0.4, 0.15];
+    vertices.push(ColorVertex::new([ox, oy, 0.0], cone_color));
+    for trace in &traces {
+        let (x, y) = trace.first_leg_end();
+        vertices.push(ColorVertex::new([x, y, 0.0], cone_color));
+    }
+    vertices.set_fan_end(vertices.len());
+}
+
+// --- Raycasting: Angle of Every Ray, Left to Right Across the View ---
+// The angles are stepped one after another up front, so every ray gets exactly the same angle
+// however the rays are split between threads
+pub fn ray_angles(player: &player::Player) -> [f32; RAYS_COUNT as usize] {
+    let dr: f32 = FOV / (RAYS_COUNT as f32); // Ray angle increment scales with FOV and ray count
+    let mut ra: f32 = player.get_dir() - dr * ((RAYS_COUNT as f32) / 2.0); // Start angle for rays
+    let mut angles = [0.0f32; RAYS_COUNT as usize];
+    for angle in angles.iter_mut() {
         // Normalize ra
         if ra < 0.0 {
             ra += 2.0 * PI;
         } else if ra > 2.0 * PI {
             ra -= 2.0 * PI;
         }
+        *angle = ra;
+        ra += dr;
+    }
+    angles
+}
 
-        dof = 0;
-        let a_tan: f32 = -1.0 / ra.tan();
-
-        if ra > PI && ra < 2.0 * PI {
-            // Looking down
-            ry = ((player.y_pos + 4.0) / (MAP_S as f32)).floor() * (MAP_S as f32) - 0.0001;
-            rx = (player.y_pos + 4.0 - ry) * a_tan + player.x_pos + 4.0;
-            yo = -MAP_S as f32;
-            xo = -yo * a_tan;
-        } else if ra > 0.0 && ra < PI {
-            // Looking up
-            ry = ((player.y_pos + 4.0) / (MAP_S as f32)).floor() * (MAP_S as f32) + (MAP_S as f32);
-            rx = (player.y_pos + 4.0 - ry) * a_tan + player.x_pos + 4.0;
-            yo = MAP_S as f32;
-            xo = -yo * a_tan;
-        } else {
-            // Exactly horizontal (left or right)
-            let sign = if ra == 0.0 || ra == 2.0 * PI { 1.0 } else { -1.0 };
-            rx = player.x_pos + 4.0 + 100.0 * sign;
-            ry = player.y_pos + 4.0;
-            yo = 0.0;
-            xo = 100.0 * sign;
-            dof = 8;
-        }
+// One straight part of a ray, between the eye or a portal exit and the next wall or portal
+struct RayLeg {
+    start: f32, // Distance along the whole ray at which the leg begins
+    ox: f32, // World x-coordinate of the leg's origin
+    oy: f32, // World y-coordinate of the leg's origin
+    angle: f32, // Direction of the leg (portals may turn the ray)
+    trace: GridTrace, // What the leg struck on the grid lines
+}
+
+// Everything found along one ray, through any portals
+struct RayTrace {
+    legs: Vec<RayLeg>, // Each leg, in order
+    segment_hit: Option<(f32, f32, SegmentHit)>, // Wall segment that stopped the ray, with its leg's origin
+    h_dist: f32, // Total distance to the last leg's horizontal grid line wall
+    v_dist: f32, // Total distance to the last leg's vertical grid line wall
+    s_dist: f32, // Total distance to the wall segment (infinity when none)
+    hit: WallHit, // The wall the ray shows
+}
 
-        // Add epsilon to prevent floating-point precision issues
-        let epsilon = 0.0001;
-        if ra > PI && ra < 2.0 * PI {
-            rx -= epsilon;
-        } else if ra < PI {
-            rx += epsilon;
+impl RayTrace {
+    // Where the ray leaves the player's own view: its wall segment hit if it stopped on the first
+    // leg, otherwise the nearer grid hit of the first leg (a wall or the portal it went through)
+    fn first_leg_end(&self) -> (f32, f32) {
+        if let (1, Some((_, _, hit))) = (self.legs.len(), &self.segment_hit) {
+            return (hit.x, hit.y);
         }
+        self.legs[0].trace.nearer_hit()
+    }
+}
 
-        mx = (rx as i32) / MAP_S;
-        my = (ry as i32) / MAP_S;
-        _mp = my * MAP_X + mx;
+// --- Raycasting: Trace One Ray from (ox, oy) at Angle ra, Following Portals ---
+// Every grid line crossing the ray examines is passed to `on_step` (a no-op except for the ray
+// inspector)
+fn trace_ray<F>(
+    map: &[u8; MAP_S as usize],
+    ox: f32,
+    oy: f32,
+    ra: f32,
+    first_leg: &LegSetup,
+    segments: &SegmentIndex,
+    on_step: &mut F
+) -> RayTrace
+    where F: FnMut(GridStep)
+{
+    // Origin and angle of the current ray leg (both change when passing through a portal)
+    let mut ox = ox;
+    let mut oy = oy;
+    let mut la = ra;
+    let mut travelled = 0.0; // Distance covered by earlier legs
+
+    let mut trace = walk_grid(map, ox, oy, la, first_leg, &mut |step| on_step(GridStep { leg: 0, ..step }));
+    let mut legs = Vec::new();
+    let mut s_dist = f32::INFINITY;
+    let mut segment_hit = None;
+
+    for _depth in 0..=MAX_PORTAL_DEPTH {
+        // Only the nearer of the two hits is the face actually seen
+        let (hit, dist) = if trace.h_dist < trace.v_dist {
+            (trace.h_hit, trace.h_dist)
+        } else {
+            (trace.v_hit, trace.v_dist)
+        };
 
-        while dof < 8 {
-            mx = (rx as i32) / MAP_S;
-            my = (ry as i32) / MAP_S;
-            _mp = my * MAP_X + mx;
+        // A wall segment in front of the grid wall blocks the ray (and any portal behind it)
+        if let Some(hit) = segments.cast(ox, oy, la, dist) {
+            s_dist = travelled + hit.dist;
+            segment_hit = Some((ox, oy, hit));
+            break;
+        }
 
-            // Break if out of map bounds
-            if mx < 0 || mx >= MAP_X || my < 0 || my >= MAP_Y {
+        if _depth == MAX_PORTAL_DEPTH {
+            break;
+        }
+        let link = match portal::find_link(hit.cell_x, hit.cell_y, hit.face) {
+            Some(link) => link,
+            None => {
                 break;
             }
+        };
 
-            // Stop if a wall is hit
-            if (0..MAP_X * MAP_Y).contains(&_mp) && map[_mp as usize] == 1 {
-                break;
-            }
+        // Continue tracing from the exit face, nudged off it so the exit wall isn't hit again
+        let (nx, ny) = link.exit().face.normal();
+        let (px, py) = link.transform_point(hit.x, hit.y);
+        legs.push(RayLeg { start: travelled, ox, oy, angle: la, trace });
+        ox = px + nx * 0.01;
+        oy = py + ny * 0.01;
+        la = link.transform_angle(la);
+        travelled += dist;
+
+        let leg = legs.len();
+        trace = walk_grid(map, ox, oy, la, &leg_setup(ox, oy, la), &mut |step| on_step(GridStep { leg, ..step }));
+    }
 
-            // Step to next grid intersection
-            rx += xo;
-            ry += yo;
-            dof += 1;
+    // Remember what the column actually shows
+    let hit = match segment_hit {
+        Some((_, _, hit)) => WallHit::at(hit.x, hit.y, None),
+        None if trace.h_dist < trace.v_dist => {
+            WallHit::at(trace.h_hit.x, trace.h_hit.y, Some(trace.h_hit.face))
         }
+        None => WallHit::at(trace.v_hit.x, trace.v_hit.y, Some(trace.v_hit.face)),
+    };
+    let h_dist = travelled + trace.h_dist;
+    let v_dist = travelled + trace.v_dist;
+    legs.push(RayLeg { start: travelled, ox, oy, angle: la, trace });
 
-        hrays[_r as usize] = distance_3d(
-            (player.x_pos + 4.0, player.y_pos + 4.0, 0.0),
-            (rx, ry, 0.0)
-        );
+    RayTrace { legs, segment_hit, h_dist, v_dist, s_dist, hit }
+}
 
-        vertices.push(player.get_player_x(4.0));
-        vertices.push(player.get_player_y(4.0));
-        vertices.push(0.0);
-        vertices.push(0.0);
-        vertices.push(1.0);
-        vertices.push(0.0);
-        vertices.push(get_x(rx, WIDTH));
-        vertices.push(get_y(ry, HEIGHT));
-        vertices.push(0.0);
-        vertices.push(0.0);
-        vertices.push(1.0);
-        vertices.push(0.0);
-
-        dof = 0;
-        let n_tan: f32 = -ra.tan();
-        const P2: f32 = PI / 2.0;
-        const P3: f32 = (3.0 * PI) / 2.0;
-
-        if ra > P2 && ra < P3 {
-            // Looking left
-            rx = ((player.x_pos + 4.0) / (MAP_S as f32)).floor() * (MAP_S as f32) - 0.0001;
-            ry = (player.x_pos + 4.0 - rx) * n_tan + player.y_pos + 4.0;
-            xo = -MAP_S as f32;
-            yo = -xo * n_tan;
-        } else if ra < P2 || ra > P3 {
-            // Looking right
-            rx = ((player.x_pos + 4.0) / (MAP_S as f32)).floor() * (MAP_S as f32) + (MAP_S as f32);
-            ry = (player.x_pos + 4.0 - rx) * n_tan + player.y_pos + 4.0;
-            xo = MAP_S as f32;
-            yo = -xo * n_tan;
-        } else {
-            // Exactly vertical (up or down)
-            let sign = if ra == P2 { 1.0 } else { -1.0 };
-            rx = player.x_pos + 4.0;
-            ry = player.y_pos + 4.0 + 100.0 * sign;
-            xo = 0.0;
-            yo = 100.0 * sign;
-            dof = 8;
-        }
-
-        while dof < 8 {
-            mx = (rx as i32) / MAP_S;
-            my = (ry as i32) / MAP_S;
-            _mp = my * MAP_X + mx;
-
-            // Break if out of map bounds
-            if mx < 0 || mx >= MAP_X || my < 0 || my >= MAP_Y {
-                break;
-            }
+// Nearest wall struck by a ray, after any portals
+#[derive(Debug, Clone, Copy)]
+pub struct WallHit {
+    pub x: f32, // World x-coordinate of the hit (in the frame of the last portal passed)
+    pub y: f32, // World y-coordinate of the hit
+    pub cell_x: i32, // Map column the hit point lies in
+    pub cell_y: i32, // Map row the hit point lies in
+    pub face: Option<Face>, // Face of the grid cell that was hit, `None` for wall segments
+}
 
-            // Stop if a wall is hit
-            if (0..MAP_X * MAP_Y).contains(&_mp) && map[_mp as usize] == 1 {
-                break;
+impl WallHit {
+    // Constructor to create a WallHit from a world-space hit point
+    pub fn at(x: f32, y: f32, face: Option<Face>) -> WallHit {
+        WallHit {
+            x,
+            y,
+            cell_x: (x / (MAP_S as f32)).floor() as i32,
+            cell_y: (y / (MAP_S as f32)).floor() as i32,
+            face,
+        }
+    }
+}
+
+// Where a ray carries on after passing through a portal
+#[derive(Debug, Clone, Copy)]
+pub struct PortalLeg {
+    pub start: f32, // Distance along the whole ray at which the leg begins
+    pub ox: f32, // World x-coordinate of the leg's origin (just off the exit face)
+    pub oy: f32, // World y-coordinate of the leg's origin
+    pub dir_x: f32, // Cosine of the leg's angle
+    pub dir_y: f32, // Sine of the leg's angle
+}
+
+impl PortalLeg {
+    // Constructor to create a PortalLeg from the leg's origin and angle
+    pub fn new(start: f32, ox: f32, oy: f32, angle: f32) -> PortalLeg {
+        let (dir_y, dir_x) = angle.sin_cos();
+        PortalLeg { start, ox, oy, dir_x, dir_y }
+    }
+}
+
+// Per-column results of the raycaster
+pub struct RayBuffers {
+    pub hrays: [f32; RAYS_COUNT as usize], // Distance to the nearest horizontal grid line wall
+    pub vrays: [f32; RAYS_COUNT as usize], // Distance to the nearest vertical grid line wall
+    pub srays: [f32; RAYS_COUNT as usize], // Distance to the nearest wall segment (infinity when none)
+    pub h_hits: [(f32, f32); RAYS_COUNT as usize], // Last leg's candidate hit on a horizontal grid line
+    pub v_hits: [(f32, f32); RAYS_COUNT as usize], // Last leg's candidate hit on a vertical grid line
+    pub hits: [WallHit; RAYS_COUNT as usize], // The wall each column shows
+    pub portals: [[PortalLeg; MAX_PORTAL_DEPTH as usize]; RAYS_COUNT as usize], // Legs after each portal passed
+    pub portal_counts: [usize; RAYS_COUNT as usize], // Number of portals each ray passed
+}
+
+impl RayBuffers {
+    // Constructor to create empty ray buffers
+    pub fn new() -> RayBuffers {
+        RayBuffers {
+            hrays: [0.0; RAYS_COUNT as usize],
+            vrays: [0.0; RAYS_COUNT as usize],
+            srays: [f32::INFINITY; RAYS_COUNT as usize],
+            h_hits: [(0.0, 0.0); RAYS_COUNT as usize],
+            v_hits: [(0.0, 0.0); RAYS_COUNT as usize],
+            hits: [WallHit::at(0.0, 0.0, None); RAYS_COUNT as usize],
+            portals: [[PortalLeg::new(0.0, 0.0, 0.0, 0.0); MAX_PORTAL_DEPTH as usize]; RAYS_COUNT as usize],
+            portal_counts: [0; RAYS_COUNT as usize],
+        }
+    }
+}
+
+impl Default for RayBuffers {
+    fn default() -> Self {
+        RayBuffers::new()
+    }
+}
+
+// Maximum number of portals a single ray may pass through
+pub const MAX_PORTAL_DEPTH: i32 = 4;
+
+// Where a ray leg stopped on a horizontal or vertical grid line
+#[derive(Debug, Clone, Copy)]
+struct GridHit {
+    x: f32, // World x-coordinate of the hit
+    y: f32, // World y-coordinate of the hit
+    cell_x: i32, // Map column of the cell that was hit
+    cell_y: i32, // Map row of the cell that was hit
+    face: Face, // Face of that cell the ray struck
+}
+
+// Result of tracing one ray leg against both horizontal and vertical grid lines
+struct GridTrace {
+    h_hit: GridHit, // Nearest wall on a horizontal grid line
+    v_hit: GridHit, // Nearest wall on a vertical grid line
+    h_dist: f32, // Distance from the leg origin to `h_hit`
+    v_dist: f32, // Distance from the leg origin to `v_hit`
+}
+
+impl GridTrace {
+    // The nearer of the two hits: the face the leg actually sees
+    fn nearer_hit(&self) -> (f32, f32) {
+        let hit = if self.h_dist < self.v_dist { self.h_hit } else { self.v_hit };
+        (hit.x, hit.y)
+    }
+}
+
+// What a ray found at one grid line crossing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepCell {
+    Empty(i32, i32), // An open map cell (column, row); the ray steps on
+    Wall(i32, i32), // A wall cell; the leg stops here
+    Outside, // Off the map; the leg stops here
+}
+
+// One grid line crossing examined while walking a ray leg (reported to the ray inspector)
+#[derive(Debug, Clone, Copy)]
+pub struct GridStep {
+    pub leg: usize, // Leg of the ray (0 before any portal)
+    pub vertical: bool, // Crossing of a vertical grid line, otherwise a horizontal one
+    pub x: f32, // World x-coordinate of the crossing
+    pub y: f32, // World y-coordinate of the crossing
+    pub cell: StepCell, // What was found there
+}
+
+// Where a ray leg first crosses a grid line, and the step from one crossing to the next
+#[derive(Debug, Clone, Copy)]
+struct LineSetup {
+    rx: f32, // World x-coordinate of the first crossing
+    ry: f32, // World y-coordinate of the first crossing
+    xo: f32, // x step between crossings
+    yo: f32, // y step between crossings
+    done: bool, // Whether the ray runs parallel to these grid lines (no stepping needed)
+}
+
+// Grid line setup of one ray leg: horizontal and vertical grid lines
+#[derive(Debug, Clone, Copy)]
+struct LegSetup {
+    h: LineSetup, // Horizontal grid lines
+    v: LineSetup, // Vertical grid lines
+}
+
+// Grid line setup of every ray's first leg, one array per field (structure of arrays), so the
+// packed path can load and store several rays at once
+struct RaySetups {
+    h_rx: [f32; RAYS_COUNT as usize],
+    h_ry: [f32; RAYS_COUNT as usize],
+    h_xo: [f32; RAYS_COUNT as usize],
+    h_yo: [f32; RAYS_COUNT as usize],
+    h_done: [bool; RAYS_COUNT as usize],
+    v_rx: [f32; RAYS_COUNT as usize],
+    v_ry: [f32; RAYS_COUNT as usize],
+    v_xo: [f32; RAYS_COUNT as usize],
+    v_yo: [f32; RAYS_COUNT as usize],
+    v_done: [bool; RAYS_COUNT as usize],
+}
+
+impl RaySetups {
+    // Set up the first leg of every ray from (ox, oy), packed where possible
+    fn new(ox: f32, oy: f32, angles: &[f32; RAYS_COUNT as usize]) -> RaySetups {
+        let mut setups = RaySetups {
+            h_rx: [0.0; RAYS_COUNT as usize],
+            h_ry: [0.0; RAYS_COUNT as usize],
+            h_xo: [0.0; RAYS_COUNT as usize],
+            h_yo: [0.0; RAYS_COUNT as usize],
+            h_done: [false; RAYS_COUNT as usize],
+            v_rx: [0.0; RAYS_COUNT as usize],
+            v_ry: [0.0; RAYS_COUNT as usize],
+            v_xo: [0.0; RAYS_COUNT as usize],
+            v_yo: [0.0; RAYS_COUNT as usize],
+            v_done: [false; RAYS_COUNT as usize],
+        };
+        let packed = if simd::enabled() { angles.len() - (angles.len() % LANES) } else { 0 };
+        for r in (0..packed).step_by(LANES) {
+            setups.set_packed(r, ox, oy, angles[r..r + LANES].try_into().unwrap());
+        }
+        for (r, &ra) in angles.iter().enumerate().skip(packed) {
+            setups.set(r, &leg_setup(ox, oy, ra));
+        }
+        setups
+    }
+
+    // Get the setup of one ray
+    fn get(&self, r: usize) -> LegSetup {
+        LegSetup {
+            h: LineSetup {
+                rx: self.h_rx[r],
+                ry: self.h_ry[r],
+                xo: self.h_xo[r],
+                yo: self.h_yo[r],
+                done: self.h_done[r],
+            },
+            v: LineSetup {
+                rx: self.v_rx[r],
+                ry: self.v_ry[r],
+                xo: self.v_xo[r],
+                yo: self.v_yo[r],
+                done: self.v_done[r],
+            },
+        }
+    }
+
+    // Store the setup of one ray
+    fn set(&mut self, r: usize, setup: &LegSetup) {
+        self.h_rx[r] = setup.h.rx;
+        self.h_ry[r] = setup.h.ry;
+        self.h_xo[r] = setup.h.xo;
+        self.h_yo[r] = setup.h.yo;
+        self.h_done[r] = setup.h.done;
+        self.v_rx[r] = setup.v.rx;
+        self.v_ry[r] = setup.v.ry;
+        self.v_xo[r] = setup.v.xo;
+        self.v_yo[r] = setup.v.yo;
+        self.v_done[r] = setup.v.done;
+    }
+
+    // Set up rays r..r + LANES together: the same arithmetic as `leg_setup`, one lane per ray.
+    // Rays exactly parallel to a grid axis fall back to `leg_setup`.
+    fn set_packed(&mut self, r: usize, ox: f32, oy: f32, angles: [f32; LANES]) {
+        let map_s = MAP_S as f32;
+        let ra = F32x4::from_array(angles);
+        let tan = F32x4::from_array(angles.map(f32::tan)); // No packed tangent; one per lane
+        let zero = F32x4::splat(0.0);
+        let epsilon = F32x4::splat(0.0001);
+
+        // Horizontal grid lines
+        let a_tan = F32x4::splat(-1.0) / tan;
+        let down = ra.gt(F32x4::splat(PI)).and(ra.lt(F32x4::splat(2.0 * PI)));
+        let up = ra.gt(zero).and(ra.lt(F32x4::splat(PI)));
+        let row = (oy / map_s).floor() * map_s;
+        let ry = down.select(F32x4::splat(row - 0.0001), F32x4::splat(row + map_s));
+        let rx = (F32x4::splat(oy) - ry) * a_tan + F32x4::splat(ox);
+        let yo = down.select(F32x4::splat(-map_s), F32x4::splat(map_s));
+        let xo = down.select(F32x4::splat(map_s), F32x4::splat(-map_s)) * a_tan; // -yo * a_tan
+        let rx = down.select(rx - epsilon, rx + epsilon);
+        store_lanes(&mut self.h_rx, r, rx);
+        store_lanes(&mut self.h_ry, r, ry);
+        store_lanes(&mut self.h_xo, r, xo);
+        store_lanes(&mut self.h_yo, r, yo);
+
+        // Vertical grid lines
+        let n_tan = tan * F32x4::splat(-1.0);
+        let left = ra.gt(F32x4::splat(PI / 2.0)).and(ra.lt(F32x4::splat((3.0 * PI) / 2.0)));
+        let right = ra.lt(F32x4::splat(PI / 2.0)).or(ra.gt(F32x4::splat((3.0 * PI) / 2.0)));
+        let column = (ox / map_s).floor() * map_s;
+        let rx = left.select(F32x4::splat(column - 0.0001), F32x4::splat(column + map_s));
+        let ry = (F32x4::splat(ox) - rx) * n_tan + F32x4::splat(oy);
+        let xo = left.select(F32x4::splat(-map_s), F32x4::splat(map_s));
+        let yo = left.select(F32x4::splat(map_s), F32x4::splat(-map_s)) * n_tan; // -xo * n_tan
+        store_lanes(&mut self.v_rx, r, rx);
+        store_lanes(&mut self.v_ry, r, ry);
+        store_lanes(&mut self.v_xo, r, xo);
+        store_lanes(&mut self.v_yo, r, yo);
+
+        let h_stepped = down.or(up).to_array();
+        let v_stepped = left.or(right).to_array();
+        for lane in 0..LANES {
+            self.h_done[r + lane] = false;
+            self.v_done[r + lane] = false;
+            if !h_stepped[lane] || !v_stepped[lane] {
+                self.set(r + lane, &leg_setup(ox, oy, angles[lane]));
             }
+        }
+    }
+}
+
+// Read consecutive array entries into the lanes of a packed value
+fn load_lanes(values: &[f32], start: usize) -> F32x4 {
+    F32x4::from_array(values[start..start + LANES].try_into().unwrap())
+}
+
+// Write the lanes of a packed value into consecutive array entries
+fn store_lanes(values: &mut [f32], start: usize, packed: F32x4) {
+    values[start..start + LANES].copy_from_slice(&packed.to_array());
+}
+
+// --- Raycasting: Set Up a Ray Leg from (ox, oy) at Angle ra ---
+fn leg_setup(ox: f32, oy: f32, ra: f32) -> LegSetup {
+    let mut rx: f32;
+    let ry: f32;
+    let xo: f32;
+    let yo: f32;
+    let mut done = false;
+
+    let a_tan: f32 = -1.0 / ra.tan();
+
+    if ra > PI && ra < 2.0 * PI {
+        // Looking down
+        ry = (oy / (MAP_S as f32)).floor() * (MAP_S as f32) - 0.0001;
+        rx = (oy - ry) * a_tan + ox;
+        yo = -MAP_S as f32;
+        xo = -yo * a_tan;
+    } else if ra > 0.0 && ra < PI {
+        // Looking up
+        ry = (oy / (MAP_S as f32)).floor() * (MAP_S as f32) + (MAP_S as f32);
+        rx = (oy - ry) * a_tan + ox;
+        yo = MAP_S as f32;
+        xo = -yo * a_tan;
+    } else {
+        // Exactly horizontal (left or right)
+        let sign = if ra == 0.0 || ra == 2.0 * PI { 1.0 } else { -1.0 };
+        rx = ox + 100.0 * sign;
+        ry = oy;
+        yo = 0.0;
+        xo = 100.0 * sign;
+        done = true;
+    }
+
+    // Add epsilon to prevent floating-point precision issues
+    let epsilon = 0.0001;
+    if ra > PI && ra < 2.0 * PI {
+        rx -= epsilon;
+    } else if ra < PI {
+        rx += epsilon;
+    }
+    let h = LineSetup { rx, ry, xo, yo, done };
+
+    let rx: f32;
+    let ry: f32;
+    let xo: f32;
+    let yo: f32;
+    let mut done = false;
+
+    let n_tan: f32 = -ra.tan();
+    const P2: f32 = PI / 2.0;
+    const P3: f32 = (3.0 * PI) / 2.0;
+
+    if ra > P2 && ra < P3 {
+        // Looking left
+        rx = (ox / (MAP_S as f32)).floor() * (MAP_S as f32) - 0.0001;
+        ry = (ox - rx) * n_tan + oy;
+        xo = -MAP_S as f32;
+        yo = -xo * n_tan;
+    } else if !(P2..=P3).contains(&ra) {
+        // Looking right
+        rx = (ox / (MAP_S as f32)).floor() * (MAP_S as f32) + (MAP_S as f32);
+        ry = (ox - rx) * n_tan + oy;
+        xo = MAP_S as f32;
+        yo = -xo * n_tan;
+    } else {
+        // Exactly vertical (up or down)
+        let sign = if ra == P2 { 1.0 } else { -1.0 };
+        rx = ox;
+        ry = oy + 100.0 * sign;
+        xo = 0.0;
+        yo = 100.0 * sign;
+        done = true;
+    }
+    let v = LineSetup { rx, ry, xo, yo, done };
 
-            // Step to next grid intersection
-            rx += xo;
-            ry += yo;
-            dof += 1;
+    LegSetup { h, v }
+}
+
+// --- Raycasting: Step a Set-Up Ray Leg Along the Grid Until It Hits Walls ---
+fn walk_grid<F>(
+    map: &[u8; MAP_S as usize],
+    ox: f32,
+    oy: f32,
+    ra: f32,
+    setup: &LegSetup,
+    on_step: &mut F
+) -> GridTrace
+    where F: FnMut(GridStep)
+{
+    let (rx, ry) = walk_lines(map, &setup.h, &mut |x, y, cell| {
+        on_step(GridStep { leg: 0, vertical: false, x, y, cell });
+    });
+    let h_hit = GridHit {
+        x: rx,
+        y: ry,
+        cell_x: (rx / (MAP_S as f32)).floor() as i32,
+        cell_y: (ry / (MAP_S as f32)).floor() as i32,
+        face: if ra > PI && ra < 2.0 * PI { Face::South } else { Face::North },
+    };
+    let h_dist = distance_3d((ox, oy, 0.0), (rx, ry, 0.0));
+
+    const P2: f32 = PI / 2.0;
+    const P3: f32 = (3.0 * PI) / 2.0;
+    let (rx, ry) = walk_lines(map, &setup.v, &mut |x, y, cell| {
+        on_step(GridStep { leg: 0, vertical: true, x, y, cell });
+    });
+    let v_hit = GridHit {
+        x: rx,
+        y: ry,
+        cell_x: (rx / (MAP_S as f32)).floor() as i32,
+        cell_y: (ry / (MAP_S as f32)).floor() as i32,
+        face: if ra > P2 && ra < P3 { Face::East } else { Face::West },
+    };
+    let v_dist = distance_3d((ox, oy, 0.0), (rx, ry, 0.0));
+
+    GridTrace { h_hit, v_hit, h_dist, v_dist }
+}
+
+// --- Raycasting: Step from Crossing to Crossing of One Set of Grid Lines ---
+// `on_step` gets each crossing that is examined and what was found there
+fn walk_lines<F>(map: &[u8; MAP_S as usize], setup: &LineSetup, on_step: &mut F) -> (f32, f32)
+    where F: FnMut(f32, f32, StepCell)
+{
+    let mut mx: i32;
+    let mut my: i32;
+    let mut _mp: i32;
+    let mut dof: i32 = if setup.done { 8 } else { 0 };
+    let mut rx = setup.rx;
+    let mut ry = setup.ry;
+
+    while dof < 8 {
+        mx = (rx as i32) / MAP_S;
+        my = (ry as i32) / MAP_S;
+        _mp = my * MAP_X + mx;
+
+        // Break if out of map bounds
+        if !(0..MAP_X).contains(&mx) || !(0..MAP_Y).contains(&my) {
+            on_step(rx, ry, StepCell::Outside);
+            break;
         }
 
-        vrays[_r as usize] = distance_3d(
-            (player.x_pos + 4.0, player.y_pos + 4.0, 0.0),
-            (rx, ry, 0.0)
-        );
+        // Stop if a wall is hit
+        if (0..MAP_X * MAP_Y).contains(&_mp) && map[_mp as usize] != TILE_EMPTY {
+            on_step(rx, ry, StepCell::Wall(mx, my));
+            break;
+        }
+        on_step(rx, ry, StepCell::Empty(mx, my));
 
-        vertices.push(player.get_player_x(4.0));
-        vertices.push(player.get_player_y(4.0));
-        vertices.push(0.0);
-        vertices.push(1.0);
-        vertices.push(0.0);
-        vertices.push(0.0);
-        vertices.push(get_x(rx, WIDTH));
-        vertices.push(get_y(ry, HEIGHT));
-        vertices.push(0.0);
-        vertices.push(1.0);
-        vertices.push(0.0);
-        vertices.push(0.0);
+        // Step to next grid intersection
+        rx += setup.xo;
+        ry += setup.yo;
+        dof += 1;
+    }
+    (rx, ry)
+}
 
-        ra += dr;
+// --- Push the Horizontal (green) and Vertical (red) Lines of a Ray Leg ---
+fn push_trace_vertices(vertices: &mut VertexArrayWrapper, ox: f32, oy: f32, trace: &GridTrace) {
+    for (hit, color) in [
+        (trace.h_hit, [0.0, 1.0, 0.0]),
+        (trace.v_hit, [1.0, 0.0, 0.0]),
+    ] {
+        vertices.push(ColorVertex::new([ox, oy, 0.0], color));
+        vertices.push(ColorVertex::new([hit.x, hit.y, 0.0], color));
     }
+}
 
-    vertices.set_line_end(vertices.len());
+// --- Push the Line from a Ray Leg's Origin to a Wall Segment Hit (yellow) ---
+fn push_segment_hit_vertices(vertices: &mut VertexArrayWrapper, ox: f32, oy: f32, hit: &SegmentHit) {
+    for (x, y) in [
+        (ox, oy),
+        (hit.x, hit.y),
+    ] {
+        vertices.push(ColorVertex::new([x, y, 0.0], [1.0, 1.0, 0.0]));
+    }
+}
+
+// --- Check Whether the Cell Under a World Point Is Open to the Sky ---
+fn is_sky_cell(x: f32, y: f32) -> bool {
+    let cell_x = (x / (MAP_S as f32)).floor() as i32;
+    let cell_y = (y / (MAP_S as f32)).floor() as i32;
+    (0..MAP_X).contains(&cell_x) &&
+        (0..MAP_Y).contains(&cell_y) &&
+        SKY_CELLS[cell_y as usize][cell_x as usize] != 0
 }
 
 // --- Utility: 3D Distance Calculation ---
@@ -498,62 +1276,585 @@ fn distance_3d(begin: (f32, f32, f32), end: (f32, f32, f32)) -> f32 {
     (dx * dx + dy * dy + dz * dz).sqrt()
 }
 
-// --- Raycasting: Draw Walls to Pixel Buffer (with fisheye correction) ---
-pub fn draw_walls_to_pixels(
-    _pixels: &mut [[[u8; 3]; RENDER_X as usize]; RENDER_Y as usize],
-    hrays: &[f32; RAYS_COUNT as usize],
-    vrays: &[f32; RAYS_COUNT as usize],
-    horiz_color: [u8; 3],
-    vert_color: [u8; 3],
-    background_color: [u8; 3]
-) {
+// Flat colors used by the software renderer
+pub struct WallColors {
+    pub horizontal: [u8; 3], // Walls hit on a horizontal grid line
+    pub vertical: [u8; 3], // Walls hit on a vertical grid line
+    pub segment: [u8; 3], // Free-standing wall segments
+    pub background: [u8; 3], // Ceiling
+    pub floor: [u8; 3], // Floor
+}
+
+// How the software renderers turn a base color, its light and its distance into a stored pixel:
+// RGB with `Fog`, or palette indices with a `palette::Colormap`
+pub trait Shading: Sync {
+    type Pixel: Copy + Send + Sync; // One entry of the pixel buffer
+
+    // A lit surface seen at the given distance
+    fn surface(&self, color: [u8; 3], light: [f32; 3], dist: f32) -> Self::Pixel;
+
+    // A color shown as-is, without light or fog (the sky)
+    fn unlit(&self, color: [u8; 3]) -> Self::Pixel;
+}
+
+// Per-column results of the first pass of `draw_walls_to_pixels`
+#[derive(Debug, Clone, Copy)]
+struct ColumnSetup<P> {
+    wall_top: i32, // First row of the wall slice
+    wall_bottom: i32, // Row just below the wall slice
+    wall: P, // Lit and fogged wall pixel
+    column_angle: f32, // World angle of the column's ray
+    dir_x: f32, // Cosine of `column_angle`
+    dir_y: f32, // Sine of `column_angle`
+    cos_angle: f32, // Cosine of the angle from the view direction (fisheye correction)
+}
+
+// First-pass results for every column, one array per field (structure of arrays), so the fill can
+// load several columns' ray directions at once
+struct ColumnBuffers<P> {
+    wall_top: Vec<i32>,
+    wall_bottom: Vec<i32>,
+    wall: Vec<P>,
+    column_angle: Vec<f32>,
+    dir_x: Vec<f32>,
+    dir_y: Vec<f32>,
+    cos_angle: Vec<f32>,
+}
+
+impl<P: Copy> ColumnBuffers<P> {
+    // Constructor to split per-column results into arrays
+    fn new(columns: &[ColumnSetup<P>]) -> ColumnBuffers<P> {
+        ColumnBuffers {
+            wall_top: columns.iter().map(|column| column.wall_top).collect(),
+            wall_bottom: columns.iter().map(|column| column.wall_bottom).collect(),
+            wall: columns.iter().map(|column| column.wall).collect(),
+            column_angle: columns.iter().map(|column| column.column_angle).collect(),
+            dir_x: columns.iter().map(|column| column.dir_x).collect(),
+            dir_y: columns.iter().map(|column| column.dir_y).collect(),
+            cos_angle: columns.iter().map(|column| column.cos_angle).collect(),
+        }
+    }
+
+    // World position of the floor (or ceiling) point seen by each column on a row at `row_dist`
+    fn floor_points(&self, eye_x: f32, eye_y: f32, row_dist: f32, floor_x: &mut [f32], floor_y: &mut [f32]) {
+        let count = self.cos_angle.len();
+        let packed = if simd::enabled() { count - (count % LANES) } else { 0 };
+        for x in (0..packed).step_by(LANES) {
+            let dist = F32x4::splat(row_dist) / load_lanes(&self.cos_angle, x);
+            store_lanes(floor_x, x, F32x4::splat(eye_x) + load_lanes(&self.dir_x, x) * dist);
+            store_lanes(floor_y, x, F32x4::splat(eye_y) + load_lanes(&self.dir_y, x) * dist);
+        }
+        for x in packed..count {
+            floor_x[x] = eye_x + self.dir_x[x] * (row_dist / self.cos_angle[x]);
+            floor_y[x] = eye_y + self.dir_y[x] * (row_dist / self.cos_angle[x]);
+        }
+    }
+}
+
+// --- Move Floor Points Seen Through a Portal onto the Far Side ---
+// `floor_points` follows each column's ray in a straight line; once the ray has passed a portal,
+// the floor (and its light) lies along the leg leaving the portal's exit face instead
+fn follow_portals(rays: &RayBuffers, cos_angle: &[f32], row_dist: f32, floor_x: &mut [f32], floor_y: &mut [f32]) {
+    for x in 0..floor_x.len() {
+        if let Some((px, py)) = portal_floor_point(rays, column_ray(x as i32), row_dist / cos_angle[x]) {
+            floor_x[x] = px;
+            floor_y[x] = py;
+        }
+    }
+}
+
+// Floor point `along` units down a ray that has passed a portal by then, `None` before any portal
+fn portal_floor_point(rays: &RayBuffers, ray: usize, along: f32) -> Option<(f32, f32)> {
+    let portals = &rays.portals[ray][..rays.portal_counts[ray]];
+    let leg = portals.iter().rev().find(|leg| leg.start <= along)?;
+    Some((leg.ox + leg.dir_x * (along - leg.start), leg.oy + leg.dir_y * (along - leg.start)))
+}
+
+// Which kind of wall a column shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WallSide {
+    Horizontal, // A wall on a horizontal grid line
+    Vertical, // A wall on a vertical grid line
+    Segment, // A free-standing wall segment
+}
+
+impl WallSide {
+    // Name as shown by the ray inspector
+    pub fn name(self) -> &'static str {
+        match self {
+            WallSide::Horizontal => "horizontal",
+            WallSide::Vertical => "vertical",
+            WallSide::Segment => "segment",
+        }
+    }
+}
+
+// --- Pick the Wall a Column Shows from Its Ray's Distances (uncorrected) ---
+pub fn nearest_wall(h_dist: f32, v_dist: f32, s_dist: f32) -> (WallSide, f32) {
+    let h_dist = h_dist.max(0.0001);
+    let v_dist = v_dist.max(0.0001);
+    let s_dist = s_dist.max(0.0001);
+    if s_dist < h_dist && s_dist < v_dist {
+        (WallSide::Segment, s_dist)
+    } else if h_dist < v_dist {
+        (WallSide::Horizontal, h_dist)
+    } else {
+        (WallSide::Vertical, v_dist)
+    }
+}
+
+// --- Ray Shown by a Canvas Column ---
+pub fn column_ray(x: i32) -> usize {
+    let ray_index = (((x as f32) * (RAYS_COUNT as f32)) / (RENDER_X as f32)) as usize;
+    ray_index.min((RAYS_COUNT as usize) - 1) // Clamp to array bounds
+}
+
+// --- First Canvas Column Showing a Ray ---
+pub fn ray_column(ray: usize) -> i32 {
+    ((ray as i32) * RENDER_X + RAYS_COUNT - 1) / RAYS_COUNT
+}
+
+// --- Rows of the Wall Slice Drawn for a Fisheye-Corrected Distance (top, and just below the bottom) ---
+pub fn wall_span(dist: f32) -> (i32, i32) {
+    let screen_height = RENDER_Y as f32;
+    let proj_plane_dist = (RENDER_X as f32) / 2.0 / (FOV / 2.0).tan();
+    let wall_height_world = 1.0;
+    let wall_height = ((20.0 * (wall_height_world * proj_plane_dist)) / dist).min(screen_height);
+    (((screen_height - wall_height) / 2.0).round() as i32, ((screen_height + wall_height) / 2.0).round() as i32)
+}
+
+// --- Angle of a Canvas Column from the View Direction (for fisheye correction) ---
+pub fn column_screen_angle(x: i32) -> f32 {
+    let screen_width = RENDER_X as f32;
+    (((x as f32) - screen_width / 2.0) / (screen_width / 2.0)) * (FOV / 2.0)
+}
+
+// One leg of an inspected ray
+#[derive(Debug, Clone, Copy)]
+pub struct LegReport {
+    pub origin: (f32, f32), // Where the leg starts (the eye, or just off a portal exit)
+    pub angle: f32, // Direction of the leg
+    pub h_hit: (f32, f32), // Candidate hit on a horizontal grid line
+    pub v_hit: (f32, f32), // Candidate hit on a vertical grid line
+    pub h_dist: f32, // Distance from the leg origin to `h_hit`
+    pub v_dist: f32, // Distance from the leg origin to `v_hit`
+    pub end: (f32, f32), // Where the leg stops: its wall, portal or segment hit
+}
+
+// Everything the ray inspector shows about the ray behind one canvas column
+#[derive(Debug, Clone)]
+pub struct RayReport {
+    pub column: i32, // Canvas column
+    pub ray: usize, // Index of the column's ray
+    pub angle: f32, // World angle of the ray
+    pub legs: Vec<LegReport>, // Legs in order, through any portals
+    pub steps: Vec<GridStep>, // Every grid line crossing examined, in the order walked
+    pub segment_hit: Option<(f32, f32)>, // Wall segment the ray stopped on
+    pub h_dist: f32, // Total distance to the last leg's horizontal candidate (`hrays`)
+    pub v_dist: f32, // Total distance to the last leg's vertical candidate (`vrays`)
+    pub s_dist: f32, // Total distance to the segment (`srays`, infinity when none)
+    pub side: WallSide, // The wall the column shows
+    pub raw_dist: f32, // Distance to that wall along the ray
+    pub corrected_dist: f32, // `raw_dist` with the fisheye correction applied
+}
+
+// --- Trace the Ray Behind One Canvas Column Again, Recording Every Step ---
+// Uses the same angle and first-leg setup as `cast_rays`, so the result matches the frame
+pub fn inspect_ray(player: &player::Player, segments: &SegmentIndex, column: i32) -> RayReport {
+    let map = single_index_map();
+    let angles = ray_angles(player);
+    let ray = column_ray(column);
+    let ox = player.x_pos + 4.0;
+    let oy = player.y_pos + 4.0;
+    let setup = RaySetups::new(ox, oy, &angles).get(ray);
+    let mut steps = Vec::new();
+    let trace = trace_ray(&map, ox, oy, angles[ray], &setup, segments, &mut |step| steps.push(step));
+
+    let last = trace.legs.len() - 1;
+    let legs = trace.legs
+        .iter()
+        .enumerate()
+        .map(|(index, leg)| LegReport {
+            origin: (leg.ox, leg.oy),
+            angle: leg.angle,
+            h_hit: (leg.trace.h_hit.x, leg.trace.h_hit.y),
+            v_hit: (leg.trace.v_hit.x, leg.trace.v_hit.y),
+            h_dist: leg.trace.h_dist,
+            v_dist: leg.trace.v_dist,
+            end: match &trace.segment_hit {
+                Some((_, _, hit)) if index == last => (hit.x, hit.y),
+                _ => leg.trace.nearer_hit(),
+            },
+        })
+        .collect();
+    let (side, raw_dist) = nearest_wall(trace.h_dist, trace.v_dist, trace.s_dist);
+    RayReport {
+        column,
+        ray,
+        angle: angles[ray],
+        legs,
+        steps,
+        segment_hit: trace.segment_hit.map(|(_, _, hit)| (hit.x, hit.y)),
+        h_dist: trace.h_dist,
+        v_dist: trace.v_dist,
+        s_dist: trace.s_dist,
+        side,
+        raw_dist,
+        corrected_dist: raw_dist * column_screen_angle(column).cos(),
+    }
+}
+
+// --- Raycasting: Distance to the Floor (and Ceiling) Seen on Each Row ---
+// Before fisheye correction is undone. The eye sits halfway up the walls, so a row's distance
+// follows from the wall height formula.
+fn row_dists() -> [f32; RENDER_Y as usize] {
     let screen_height = RENDER_Y;
-    let screen_width = RENDER_X;
-    let proj_plane_dist = (screen_width as f32) / 2.0 / (FOV / 2.0).tan();
+    let proj_plane_dist = (RENDER_X as f32) / 2.0 / (FOV / 2.0).tan();
     let wall_height_world = 1.0;
+    let mut row_dists = [0.0f32; RENDER_Y as usize];
+    for (y, row_dist) in row_dists.iter_mut().enumerate() {
+        let offset = ((y as f32) + 0.5 - (screen_height as f32) / 2.0).abs();
+        *row_dist = (10.0 * (wall_height_world * proj_plane_dist)) / offset;
+    }
+    row_dists
+}
+
+// --- Raycasting: Work Out Each Column's Wall Slice and Ray Direction (columns in parallel) ---
+fn column_setups<S: Shading>(
+    player: &player::Player,
+    rays: &RayBuffers,
+    colors: &WallColors,
+    shading: &S,
+    lighting: &Lighting
+) -> Vec<ColumnSetup<S::Pixel>> {
+    let screen_width = RENDER_X;
+    let mut columns: Vec<Option<ColumnSetup<S::Pixel>>> = (0..screen_width).map(|_| None).collect();
+    parallel::for_each_chunk(&mut columns, |start, chunk| {
+        for (i, column) in chunk.iter_mut().enumerate() {
+            let x = (start + i) as i32;
+
+            // Map screen column to ray index (since we may have different ray count vs screen width)
+            let ray_index = column_ray(x);
+
+            // Use the shortest distance for wall height
+            let (side, raw_dist) = nearest_wall(
+                rays.hrays[ray_index],
+                rays.vrays[ray_index],
+                rays.srays[ray_index]
+            );
+            let color = match side {
+                WallSide::Horizontal => colors.horizontal,
+                WallSide::Vertical => colors.vertical,
+                WallSide::Segment => colors.segment,
+            };
+
+            // --- Better fisheye correction: use screen-space angle calculation ---
+            let screen_angle = column_screen_angle(x);
+            let dist = raw_dist * screen_angle.cos();
+
+            // Calculate the projected wall slice in pixels
+            let (wall_top, wall_bottom) = wall_span(dist);
+
+            // Walls are lit by the baked and dynamic light on their face, then fade into the fog
+            // with their corrected distance
+            let hit = &rays.hits[ray_index];
+            let light = lighting.wall_light(hit.cell_x, hit.cell_y, hit.face, hit.x, hit.y);
+
+            // Direction of this column's ray, for finding where floor and ceiling rows land in the world
+            let column_angle = player.get_dir() + screen_angle;
+            let (dir_y, dir_x) = column_angle.sin_cos();
+
+            *column = Some(ColumnSetup {
+                wall_top,
+                wall_bottom,
+                wall: shading.surface(color, light, dist),
+                column_angle,
+                dir_x,
+                dir_y,
+                cos_angle: screen_angle.cos(),
+            });
+        }
+    });
+    columns.into_iter().flatten().collect()
+}
+
+// --- Raycasting: Draw Walls to Pixel Buffer (with fisheye correction) ---
+pub fn draw_walls_to_pixels<S: Shading>(
+    _pixels: &mut [[S::Pixel; RENDER_X as usize]; RENDER_Y as usize],
+    player: &player::Player,
+    rays: &RayBuffers,
+    colors: &WallColors,
+    shading: &S,
+    lighting: &Lighting,
+    sky: &Sky
+) {
+    let row_dists = row_dists();
+
+    // First pass: work out each column's wall slice and ray direction
+    let columns = ColumnBuffers::new(&column_setups(player, rays, colors, shading, lighting));
+
+    // Second pass: fill the pixel buffer (bands of rows in parallel)
+    let eye_x = player.x_pos + 4.0;
+    let eye_y = player.y_pos + 4.0;
+    parallel::for_each_chunk(_pixels.as_mut_slice(), |start, rows| {
+        let mut floor_x = [0.0f32; RENDER_X as usize];
+        let mut floor_y = [0.0f32; RENDER_X as usize];
+        for (i, row) in rows.iter_mut().enumerate() {
+            let y = (start + i) as i32;
+            let row_dist = row_dists[y as usize];
+            columns.floor_points(eye_x, eye_y, row_dist, &mut floor_x, &mut floor_y);
+            follow_portals(rays, &columns.cos_angle, row_dist, &mut floor_x, &mut floor_y);
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let wall_top = columns.wall_top[x];
+                if y >= wall_top && y < columns.wall_bottom[x] {
+                    *pixel = columns.wall[x]; // Wall
+                    continue;
+                }
+                if y < wall_top && is_sky_cell(floor_x[x], floor_y[x]) {
+                    // Open ceiling: the sky scrolls with the view direction and ignores light and fog
+                    *pixel = shading.unlit(sky.sample_row(columns.column_angle[x], y));
+                    continue;
+                }
+                let light = lighting.floor_light(floor_x[x], floor_y[x]);
+                if y < wall_top {
+                    *pixel = shading.surface(colors.background, light, row_dist); // Ceiling
+                } else {
+                    *pixel = shading.surface(colors.floor, light, row_dist); // Floor
+                }
+            }
+        }
+    });
+}
 
-    for x in 0..screen_width {
-        // Map screen column to ray index (since we may have different ray count vs screen width)
-        let ray_index = (((x as f32) * (RAYS_COUNT as f32)) / (screen_width as f32)) as usize;
-        let ray_index = ray_index.min((RAYS_COUNT as usize) - 1); // Clamp to array bounds
+// --- Hidden Window with an OpenGL 4.1 Core Context for Tests, or None Where There Is No Display ---
+#[cfg(test)]
+pub fn test_context() -> Option<(sdl2::Sdl, sdl2::video::Window, sdl2::video::GLContext)> {
+    let sdl = sdl2::init().ok()?;
+    let video = sdl.video().ok()?;
+    let gl_attr = video.gl_attr();
+    gl_attr.set_context_profile(sdl2::video::GLProfile::Core);
+    gl_attr.set_context_version(4, 1);
+    let window = video.window("yggdrasil test", 64, 64).opengl().hidden().build().ok()?;
+    let context = window.gl_create_context().ok()?;
+    gl::load_with(|name| video.gl_get_proc_address(name) as *const std::os::raw::c_void);
+    Some((sdl, window, context))
+}
 
-        let h_dist = hrays[ray_index].max(0.0001);
-        let v_dist = vrays[ray_index].max(0.0001);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw_gl::FrameBuffer;
+    use crate::light::LightCache;
+    use crate::lightmap::Lightmap;
+    use crate::window_gl::{ LIGHTS, MAP_AMBIENT_LIGHT, MAP_FOG, SEGMENTS, WALL_COLORS };
+
+    #[test]
+    fn floor_points_past_a_portal_follow_the_next_leg() {
+        let mut rays = RayBuffers::new();
+        let ray = column_ray(0);
+        rays.portals[ray][0] = PortalLeg::new(50.0, 300.0, 200.0, PI / 2.0);
+        rays.portal_counts[ray] = 1;
+        let cos_angle = vec![1.0; RENDER_X as usize];
+        let mut floor_x = vec![10.0; RENDER_X as usize];
+        let mut floor_y = vec![20.0; RENDER_X as usize];
+
+        // Nearer than the portal: the straight ray's point is kept
+        follow_portals(&rays, &cos_angle, 40.0, &mut floor_x, &mut floor_y);
+        assert_eq!((floor_x[0], floor_y[0]), (10.0, 20.0));
+
+        // Beyond it: the point lies along the leg leaving the exit face
+        follow_portals(&rays, &cos_angle, 80.0, &mut floor_x, &mut floor_y);
+        assert!((floor_x[0] - 300.0).abs() < 1e-4);
+        assert!((floor_y[0] - 230.0).abs() < 1e-4);
+        assert_eq!((floor_x[1], floor_y[1]), (10.0, 20.0));
+    }
 
-        // Use the shorter distance for wall height
-        let (raw_dist, color) = if h_dist < v_dist {
-            (h_dist, horiz_color)
-        } else {
-            (v_dist, vert_color)
-        };
+    // Every field of two setups, as bits
+    fn setup_bits(setup: &LegSetup) -> [(u32, u32, u32, u32, bool); 2] {
+        let bits = |line: &LineSetup| (line.rx.to_bits(), line.ry.to_bits(), line.xo.to_bits(), line.yo.to_bits(), line.done);
+        [bits(&setup.h), bits(&setup.v)]
+    }
 
-        // --- Better fisheye correction: use screen-space angle calculation ---
-        let screen_angle =
-            (((x as f32) - (screen_width as f32) / 2.0) / ((screen_width as f32) / 2.0)) *
-            (FOV / 2.0);
-        let dist = raw_dist * screen_angle.cos();
-
-        // Calculate projected wall height in pixels
-        let mut wall_height = (20.0 * (wall_height_world * proj_plane_dist)) / dist;
-        if wall_height > (screen_height as f32) {
-            wall_height = screen_height as f32;
-        }
-
-        // Compute top and bottom of the wall slice
-        let wall_top = (((screen_height as f32) - wall_height) / 2.0).round() as i32;
-        let wall_bottom = (((screen_height as f32) + wall_height) / 2.0).round() as i32;
-
-        // Fill the pixel buffer for this column
-        for y in 0..screen_height {
-            if (y as i32) < wall_top {
-                _pixels[y as usize][x as usize] = background_color; // Ceiling
-            } else if (y as i32) >= wall_top && (y as i32) < wall_bottom {
-                _pixels[y as usize][x as usize] = color; // Wall
-            } else {
-                _pixels[y as usize][x as usize] = background_color; // Floor
+    #[test]
+    fn packed_ray_setup_matches_the_scalar_setup() {
+        // Arbitrary angles plus the ones exactly along a grid axis, which take the scalar fallback
+        let mut angles = [0.0f32; RAYS_COUNT as usize];
+        for (r, angle) in angles.iter_mut().enumerate() {
+            *angle = ((r as f32) * 0.0137) % (2.0 * PI);
+        }
+        angles[..8].copy_from_slice(&[0.0, PI / 2.0, PI, (3.0 * PI) / 2.0, 0.3, 2.0 * PI - 0.001, 1.2, 5.9]);
+        for &(ox, oy) in &[(204.0, 204.0), (97.3, 411.9), (64.0, 128.0)] {
+            let mut setups = RaySetups::new(ox, oy, &angles);
+            for r in (0..angles.len() - (angles.len() % LANES)).step_by(LANES) {
+                setups.set_packed(r, ox, oy, angles[r..r + LANES].try_into().unwrap());
             }
+            for (r, &ra) in angles.iter().enumerate() {
+                assert_eq!(setup_bits(&setups.get(r)), setup_bits(&leg_setup(ox, oy, ra)), "ray {} at {}", r, ra);
+            }
+        }
+    }
+
+    #[test]
+    fn packed_floor_points_match_the_scalar_math() {
+        let columns: Vec<ColumnSetup<[u8; 3]>> = (0..RENDER_X)
+            .map(|x| {
+                let angle = 1.0 + column_screen_angle(x);
+                ColumnSetup {
+                    wall_top: 0,
+                    wall_bottom: 0,
+                    wall: [0; 3],
+                    column_angle: angle,
+                    dir_x: angle.cos(),
+                    dir_y: angle.sin(),
+                    cos_angle: column_screen_angle(x).cos(),
+                }
+            })
+            .collect();
+        let buffers = ColumnBuffers::new(&columns);
+        let mut floor_x = vec![0.0; RENDER_X as usize];
+        let mut floor_y = vec![0.0; RENDER_X as usize];
+        for &row_dist in &[3.5, 40.0, 1234.5] {
+            buffers.floor_points(204.0, 96.0, row_dist, &mut floor_x, &mut floor_y);
+            for (x, column) in columns.iter().enumerate() {
+                let along = row_dist / column.cos_angle;
+                assert_eq!(floor_x[x].to_bits(), (204.0 + column.dir_x * along).to_bits(), "column {}", x);
+                assert_eq!(floor_y[x].to_bits(), (96.0 + column.dir_y * along).to_bits(), "column {}", x);
+            }
+        }
+    }
+
+    #[test]
+    fn packed_and_scalar_paths_draw_the_same_frame() {
+        let segments = SegmentIndex::new(&SEGMENTS);
+        let lightmap = Lightmap::bake(&LIGHTS, MAP_AMBIENT_LIGHT, &segments);
+        let dynamic = LightCache::new([0.0; 3]);
+        let lighting = Lighting { baked: &lightmap, dynamic: &dynamic };
+        let sky = Sky::gradient([20, 24, 60], [140, 110, 130]);
+        let packed_enabled = simd::enabled();
+        for &(x, y, dir) in &[(200.0, 200.0, 0.0), (150.0, 300.0, 2.0), (330.0, 120.0, 4.5)] {
+            let mut player = player::Player::new(x, y);
+            player.update_dir(dir);
+            let frames: Vec<(RayBuffers, FrameBuffer<[u8; 3]>)> = [true, false]
+                .iter()
+                .map(|&packed| {
+                    simd::set_enabled(packed);
+                    let mut rays = RayBuffers::new();
+                    let mut pixels = FrameBuffer::new([0u8; 3]);
+                    cast_rays(&mut VertexArrayWrapper::new(), &player, &segments, &mut rays, false);
+                    draw_walls_to_pixels(&mut pixels, &player, &rays, &WALL_COLORS, &MAP_FOG, &lighting, &sky);
+                    (rays, pixels)
+                })
+                .collect();
+            let (packed, scalar) = (&frames[0], &frames[1]);
+            let same_bits = |a: &[f32], b: &[f32]| a.iter().zip(b).all(|(a, b)| a.to_bits() == b.to_bits());
+            assert!(same_bits(&packed.0.hrays, &scalar.0.hrays));
+            assert!(same_bits(&packed.0.vrays, &scalar.0.vrays));
+            assert!(same_bits(&packed.0.srays, &scalar.0.srays));
+            assert!(packed.1.as_bytes() == scalar.1.as_bytes(), "pixels differ at ({}, {}, {})", x, y, dir);
+        }
+        simd::set_enabled(packed_enabled);
+    }
+
+    // Cast and draw one frame with the given number of threads
+    fn render_with_threads(threads: usize, player: &player::Player) -> (RayBuffers, FrameBuffer<[u8; 3]>) {
+        let segments = SegmentIndex::new(&SEGMENTS);
+        let lightmap = Lightmap::bake(&LIGHTS, MAP_AMBIENT_LIGHT, &segments);
+        let dynamic = LightCache::new([0.0; 3]);
+        let lighting = Lighting { baked: &lightmap, dynamic: &dynamic };
+        let sky = Sky::gradient([20, 24, 60], [140, 110, 130]);
+        let mut rays = RayBuffers::new();
+        let mut pixels = FrameBuffer::new([0u8; 3]);
+        parallel::with_threads(threads, || {
+            cast_rays(&mut VertexArrayWrapper::new(), player, &segments, &mut rays, true);
+            draw_walls_to_pixels(&mut pixels, player, &rays, &WALL_COLORS, &MAP_FOG, &lighting, &sky);
+        });
+        (rays, pixels)
+    }
+
+    #[test]
+    fn frames_do_not_depend_on_the_thread_count() {
+        for &(x, y, dir) in &[(200.0, 200.0, 0.0), (150.0, 300.0, 2.0), (330.0, 120.0, 4.5)] {
+            let mut player = player::Player::new(x, y);
+            player.update_dir(dir);
+            let (single_rays, single_pixels) = render_with_threads(1, &player);
+            for threads in [2, 3, 7] {
+                let (rays, pixels) = render_with_threads(threads, &player);
+                let same_bits = |a: &[f32], b: &[f32]| a.iter().zip(b).all(|(a, b)| a.to_bits() == b.to_bits());
+                assert!(same_bits(&rays.hrays, &single_rays.hrays), "{} threads", threads);
+                assert!(same_bits(&rays.vrays, &single_rays.vrays), "{} threads", threads);
+                assert!(same_bits(&rays.srays, &single_rays.srays), "{} threads", threads);
+                assert_eq!(rays.portal_counts, single_rays.portal_counts);
+                for (hit, single) in rays.hits.iter().zip(single_rays.hits.iter()) {
+                    assert_eq!((hit.x.to_bits(), hit.y.to_bits(), hit.face), (single.x.to_bits(), single.y.to_bits(), single.face));
+                }
+                assert!(pixels.as_bytes() == single_pixels.as_bytes(), "pixels differ with {} threads", threads);
+            }
+        }
+    }
+
+    #[test]
+    fn setters_accept_only_their_glsl_types() {
+        assert!(UniformType::Int.accepts(gl::INT) && UniformType::Int.accepts(gl::BOOL));
+        assert!(!UniformType::Int.accepts(gl::FLOAT) && !UniformType::Int.accepts(gl::SAMPLER_2D));
+        assert!(UniformType::Float.accepts(gl::FLOAT) && !UniformType::Float.accepts(gl::FLOAT_VEC2));
+        assert!(UniformType::Vec3.accepts(gl::FLOAT_VEC3) && !UniformType::Vec3.accepts(gl::FLOAT_VEC4));
+        assert!(UniformType::Mat3.accepts(gl::FLOAT_MAT3) && !UniformType::Mat3.accepts(gl::FLOAT_MAT4));
+        assert!(UniformType::Sampler.accepts(gl::SAMPLER_2D) && UniformType::Sampler.accepts(gl::UNSIGNED_INT_SAMPLER_2D));
+        assert!(!UniformType::Sampler.accepts(gl::INT));
+    }
+
+    // Needs an OpenGL 4.1 context; run with `cargo test -- --ignored` on a machine with a display
+    #[test]
+    #[ignore]
+    fn typed_setters_write_matching_uniforms_only() {
+        let _context = test_context().expect("no OpenGL 4.1 context available");
+        let source = |text: &str| CString::new(text).unwrap();
+        let program = Program::from_shaders(&[
+            Shader::from_vert_source("test.vert", &source(
+                "#version 410 core\nvoid main() { gl_Position = vec4(0.0, 0.0, 0.0, 1.0); }\n"
+            )).unwrap(),
+            Shader::from_frag_source("test.frag", &source(
+                "#version 410 core\nuniform float brightness;\nuniform vec3 tint;\nuniform int steps;\n\
+                 uniform sampler2D canvas;\nout vec4 color;\n\
+                 void main() { color = texture(canvas, vec2(brightness)) * vec4(tint, float(steps)); }\n"
+            )).unwrap(),
+        ]).unwrap();
+        let read_float = |name: &str, count: usize| {
+            let mut value = [0.0f32; 4];
+            unsafe { gl::GetUniformfv(program.id(), program.uniform_location(name), value.as_mut_ptr()) };
+            value[..count].to_vec()
+        };
+        let read_int = |name: &str| {
+            let mut value = 0;
+            unsafe { gl::GetUniformiv(program.id(), program.uniform_location(name), &mut value) };
+            value
+        };
+
+        // Cached locations are the ones the driver reports
+        for name in ["brightness", "tint", "steps", "canvas"] {
+            let location = unsafe { gl::GetUniformLocation(program.id(), source(name).as_ptr()) };
+            assert!(location >= 0);
+            assert_eq!(program.uniform_location(name), location, "{}", name);
+        }
+        assert_eq!(program.uniform_location("missing"), -1);
+
+        program.set_float("brightness", 0.5);
+        program.set_vec3("tint", [0.25, 0.5, 1.0]);
+        program.set_int("steps", 7);
+        program.set_sampler("canvas", 3);
+        assert_eq!(read_float("brightness", 1), [0.5]);
+        assert_eq!(read_float("tint", 3), [0.25, 0.5, 1.0]);
+        assert_eq!((read_int("steps"), read_int("canvas")), (7, 3));
+
+        // A setter of the wrong type or an unknown name writes nothing and is reported once
+        program.set_float("steps", 2.0);
+        program.set_vec4("tint", [0.0; 4]);
+        program.set_float("missing", 1.0);
+        assert_eq!(read_int("steps"), 7);
+        assert_eq!(read_float("tint", 3), [0.25, 0.5, 1.0]);
+        for name in ["steps", "tint", "missing"] {
+            assert_eq!(program.reported.borrow().contains(name), cfg!(debug_assertions), "{}", name);
         }
-        // Uncomment for debugging wall heights:
-        // println!("x: {}, wall_height: {}", x, wall_height);
+        assert!(!program.reported.borrow().contains("brightness"));
     }
 }
@@ -0,0 +1,473 @@
+// Import render constants, the shared wall colors and the map lighting
+use crate::window_gl::{ RENDER_X, RENDER_Y, FOV };
+use crate::render_gl::{ Shading, WallColors };
+use crate::lightmap::Lighting;
+use crate::sky::Sky;
+use crate::player;
+use std::collections::VecDeque;
+
+// Height of the player's eye above the floor of the sector they stand in
+pub const EYE_HEIGHT: f32 = 32.0;
+// Distance of the near clipping plane in world units
+const NEAR_Z: f32 = 1.0;
+// Upper bound on how often a sector may be queued per frame (guards against portal loops)
+const MAX_SECTOR_VISITS: usize = 32;
+// How far in front of a wall its light is sampled (the lightmap is dark inside map walls)
+const WALL_LIGHT_OFFSET: f32 = 1.0;
+
+// A convex polygonal room with its own floor and ceiling height
+pub struct Sector {
+    pub floor: f32, // Floor height in world units
+    pub ceiling: f32, // Ceiling height in world units
+    pub sky: bool, // Whether the ceiling is open to the sky
+    pub vertices: &'static [(f32, f32)], // Corners in world space, clockwise on the minimap
+    pub neighbors: &'static [Option<usize>], // Sector across edge i (vertex i to i + 1), if it is a portal
+}
+
+impl Sector {
+    // Check whether a world-space point lies inside this (convex) sector
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        let count = self.vertices.len();
+        for i in 0..count {
+            let (x1, y1) = self.vertices[i];
+            let (x2, y2) = self.vertices[(i + 1) % count];
+            if (x2 - x1) * (y - y1) - (y2 - y1) * (x - x1) < 0.0 {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Find the index of the sector containing a world-space point
+pub fn find_sector(sectors: &[Sector], x: f32, y: f32) -> Option<usize> {
+    sectors.iter().position(|sector| sector.contains(x, y))
+}
+
+// A sector waiting to be drawn, limited to the screen columns its portal covers
+struct SectorSpan {
+    sector: usize, // Index into the sector list
+    start_x: i32, // First screen column
+    end_x: i32, // Last screen column (inclusive)
+}
+
+// Rows of one screen column around a wall slice (inclusive ranges, empty when start > end)
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ColumnSpans {
+    ceiling: (i32, i32), // This sector's ceiling above the wall
+    upper: (i32, i32), // Solid wall, or the upper wall where the next sector's ceiling is lower
+    lower: (i32, i32), // Lower wall where the next sector's floor is higher (empty for solid walls)
+    floor: (i32, i32), // This sector's floor below the wall
+    open: (i32, i32), // Clip range left for the sector behind a portal (empty once closed)
+}
+
+// Split the open rows `top..=bottom` of a column around a wall whose ceiling and floor project to
+// rows `ya` and `yb`; `next` holds the projected ceiling and floor of the sector behind a portal
+fn column_spans(top: i32, bottom: i32, ya: f32, yb: f32, next: Option<(f32, f32)>) -> ColumnSpans {
+    let ya = (ya as i32).clamp(top, bottom + 1);
+    let yb = (yb as i32).clamp(top - 1, bottom);
+    let ceiling = (top, ya - 1);
+    let floor = (yb + 1, bottom);
+    match next {
+        Some((nya, nyb)) => {
+            let nya = (nya as i32).clamp(top, bottom + 1);
+            let nyb = (nyb as i32).clamp(top - 1, bottom);
+            ColumnSpans {
+                ceiling,
+                upper: (ya, nya - 1),
+                lower: (nyb + 1, yb),
+                floor,
+                open: (ya.max(nya), yb.min(nyb)), // Only the opening between both sectors
+            }
+        }
+        None => ColumnSpans { ceiling, upper: (ya, yb), lower: (yb + 1, yb), floor, open: (bottom + 1, top - 1) },
+    }
+}
+
+// --- Sector Rendering: Draw Sectors Front to Back into the Pixel Buffer ---
+pub fn draw_sectors_to_pixels<S: Shading>(
+    _pixels: &mut [[S::Pixel; RENDER_X as usize]; RENDER_Y as usize],
+    player: &player::Player,
+    sectors: &[Sector],
+    colors: &WallColors,
+    shading: &S,
+    lighting: &Lighting,
+    sky: &Sky
+) {
+    draw_sectors(_pixels, player, sectors, colors, shading, lighting, sky);
+}
+
+// Draw the sectors visible from the player; returns how often each sector was drawn
+fn draw_sectors<S: Shading>(
+    _pixels: &mut [[S::Pixel; RENDER_X as usize]; RENDER_Y as usize],
+    player: &player::Player,
+    sectors: &[Sector],
+    colors: &WallColors,
+    shading: &S,
+    lighting: &Lighting,
+    sky: &Sky
+) -> Vec<usize> {
+    let screen_width = RENDER_X;
+    let screen_height = RENDER_Y;
+    let half_width = (screen_width as f32) / 2.0;
+    let half_height = (screen_height as f32) / 2.0;
+    let x_scale = half_width / (FOV / 2.0).tan();
+    // The canvas stretches the square texture to a 2:1 quad, so squash heights to match
+    let y_scale = x_scale * 0.5;
+
+    // Player camera (center of the player's quad)
+    let px = player.x_pos + 4.0;
+    let py = player.y_pos + 4.0;
+    let (sin, cos) = player.get_dir().sin_cos();
+
+    // Start with everything cleared to the background (fully fogged, so its light doesn't matter)
+    let background = shading.surface(colors.background, [1.0; 3], f32::INFINITY);
+    for row in _pixels.iter_mut() {
+        for pixel in row.iter_mut() {
+            *pixel = background;
+        }
+    }
+
+    let mut visits = vec![0usize; sectors.len()];
+    let start_sector = match find_sector(sectors, px, py) {
+        Some(sector) => sector,
+        None => {
+            return visits; // Outside the sector level, nothing to draw
+        }
+    };
+    let eye_z = sectors[start_sector].floor + EYE_HEIGHT;
+
+    // Per-column clip range still open for drawing (inclusive)
+    let mut y_top = [0i32; RENDER_X as usize];
+    let mut y_bottom = [screen_height - 1; RENDER_X as usize];
+
+    let mut queue = VecDeque::new();
+    queue.push_back(SectorSpan { sector: start_sector, start_x: 0, end_x: screen_width - 1 });
+
+    while let Some(span) = queue.pop_front() {
+        if visits[span.sector] >= MAX_SECTOR_VISITS {
+            continue;
+        }
+        visits[span.sector] += 1;
+
+        let sector = &sectors[span.sector];
+        let count = sector.vertices.len();
+        for edge in 0..count {
+            let (wx1, wy1) = sector.vertices[edge];
+            let (wx2, wy2) = sector.vertices[(edge + 1) % count];
+
+            // Transform the edge into view space (tz forward, tx to the right)
+            let (vx1, vy1) = (wx1 - px, wy1 - py);
+            let (vx2, vy2) = (wx2 - px, wy2 - py);
+            let mut tz1 = vx1 * cos + vy1 * sin;
+            let mut tx1 = -vx1 * sin + vy1 * cos;
+            let mut tz2 = vx2 * cos + vy2 * sin;
+            let mut tx2 = -vx2 * sin + vy2 * cos;
+
+            // Skip edges entirely behind the player, clip edges crossing the near plane
+            if tz1 < NEAR_Z && tz2 < NEAR_Z {
+                continue;
+            }
+            if tz1 < NEAR_Z {
+                let t = (NEAR_Z - tz1) / (tz2 - tz1);
+                tx1 += (tx2 - tx1) * t;
+                tz1 = NEAR_Z;
+            } else if tz2 < NEAR_Z {
+                let t = (NEAR_Z - tz2) / (tz1 - tz2);
+                tx2 += (tx1 - tx2) * t;
+                tz2 = NEAR_Z;
+            }
+
+            // Perspective projection of the edge's endpoints onto screen columns
+            let x1 = (half_width + (tx1 * x_scale) / tz1) as i32;
+            let x2 = (half_width + (tx2 * x_scale) / tz2) as i32;
+            if x1 >= x2 || x2 < span.start_x || x1 > span.end_x {
+                continue; // Back-facing or outside the visible span
+            }
+
+            // Heights relative to the eye, for this sector and the one behind the edge
+            let ceiling = sector.ceiling - eye_z;
+            let floor = sector.floor - eye_z;
+            let neighbor = sector.neighbors[edge];
+            let (n_ceiling, n_floor) = match neighbor {
+                Some(index) => (sectors[index].ceiling - eye_z, sectors[index].floor - eye_z),
+                None => (0.0, 0.0),
+            };
+
+            // Project ceiling and floor heights at both ends of the edge
+            let y1a = half_height - (ceiling * y_scale) / tz1;
+            let y1b = half_height - (floor * y_scale) / tz1;
+            let y2a = half_height - (ceiling * y_scale) / tz2;
+            let y2b = half_height - (floor * y_scale) / tz2;
+            let ny1a = half_height - (n_ceiling * y_scale) / tz1;
+            let ny1b = half_height - (n_floor * y_scale) / tz1;
+            let ny2a = half_height - (n_ceiling * y_scale) / tz2;
+            let ny2b = half_height - (n_floor * y_scale) / tz2;
+
+            // Axis-aligned edges get the grid renderer's colors, others sit in between
+            let wall_color = if wy1 == wy2 {
+                colors.horizontal
+            } else if wx1 == wx2 {
+                colors.vertical
+            } else {
+                colors.segment
+            };
+            // Walls take the light on the floor just inside this sector
+            let edge_length = ((wx2 - wx1).powi(2) + (wy2 - wy1).powi(2)).sqrt();
+            let inward_x = (-(wy2 - wy1) / edge_length) * WALL_LIGHT_OFFSET;
+            let inward_y = ((wx2 - wx1) / edge_length) * WALL_LIGHT_OFFSET;
+
+            let plane = PlaneProjection {
+                eye: (px, py),
+                forward: (cos, sin),
+                half_width,
+                half_height,
+                x_scale,
+                y_scale,
+                shading,
+                lighting,
+            };
+            let begin_x = x1.max(span.start_x);
+            let end_x = x2.min(span.end_x);
+            let mut open_x: Option<(i32, i32)> = None; // Columns left open behind a portal
+            for x in begin_x..=end_x {
+                let column = x as usize;
+                let t = ((x - x1) as f32) / ((x2 - x1) as f32);
+                let top = y_top[column];
+                let bottom = y_bottom[column];
+                if top > bottom {
+                    continue; // Column already fully drawn
+                }
+
+                // Perspective-correct depth of the wall at this column, for light and fog
+                let depth = 1.0 / (1.0 / tz1 + (1.0 / tz2 - 1.0 / tz1) * t);
+                let (hit_x, hit_y) = plane.world_point(x, depth);
+                let light = lighting.floor_light(hit_x + inward_x, hit_y + inward_y);
+                let wall_color = shading.surface(wall_color, light, depth);
+
+                let next = neighbor.map(|_| (ny1a + (ny2a - ny1a) * t, ny1b + (ny2b - ny1b) * t));
+                let spans = column_spans(top, bottom, y1a + (y2a - y1a) * t, y1b + (y2b - y1b) * t, next);
+
+                // Ceiling and floor of this sector around the wall slice
+                let column_angle = player.get_dir() + (((x as f32) + 0.5 - half_width) / x_scale).atan();
+                if sector.sky {
+                    plane.fill_sky(_pixels, x, spans.ceiling, sky, column_angle);
+                } else {
+                    plane.fill(_pixels, x, spans.ceiling, colors.background, ceiling);
+                }
+                plane.fill(_pixels, x, spans.floor, colors.floor, floor);
+
+                // Between two sky sectors the sky continues instead of an upper wall
+                if sector.sky && neighbor.is_some_and(|index| sectors[index].sky) {
+                    plane.fill_sky(_pixels, x, spans.upper, sky, column_angle);
+                } else {
+                    fill_column(_pixels, x, spans.upper, wall_color);
+                }
+                fill_column(_pixels, x, spans.lower, wall_color);
+
+                y_top[column] = spans.open.0;
+                y_bottom[column] = spans.open.1;
+                if spans.open.0 <= spans.open.1 {
+                    open_x = Some((open_x.map_or(x, |(first, _)| first), x));
+                }
+            }
+
+            // Only columns the portal left open can show the sector behind it
+            if let (Some(index), Some((start_x, end_x))) = (neighbor, open_x) {
+                queue.push_back(SectorSpan { sector: index, start_x, end_x });
+            }
+        }
+    }
+    visits
+}
+
+// Projection parameters needed to find the world point and distance of a floor or ceiling pixel
+struct PlaneProjection<'a, S> {
+    eye: (f32, f32), // Player camera in world space
+    forward: (f32, f32), // Unit view direction (cos, sin)
+    half_width: f32, // Screen column of the view center
+    half_height: f32, // Screen row of the horizon
+    x_scale: f32, // Horizontal projection scale
+    y_scale: f32, // Vertical projection scale
+    shading: &'a S, // Fog (and palette lookups) applied by distance
+    lighting: &'a Lighting<'a>, // Baked and dynamic map light
+}
+
+impl<S: Shading> PlaneProjection<'_, S> {
+    // World point seen through the center of column `x` at view depth `depth`
+    fn world_point(&self, x: i32, depth: f32) -> (f32, f32) {
+        let (cos, sin) = self.forward;
+        let side = (((x as f32) + 0.5 - self.half_width) / self.x_scale) * depth;
+        (self.eye.0 + cos * depth - sin * side, self.eye.1 + sin * depth + cos * side)
+    }
+
+    // Fill rows `rows` of one column with a floor or ceiling at `height` relative to the eye
+    fn fill(
+        &self,
+        _pixels: &mut [[S::Pixel; RENDER_X as usize]; RENDER_Y as usize],
+        x: i32,
+        rows: (i32, i32),
+        color: [u8; 3],
+        height: f32
+    ) {
+        let y1 = rows.0.max(0);
+        let y2 = rows.1.min(RENDER_Y - 1);
+        for y in y1..=y2 {
+            let depth = ((height * self.y_scale) / (self.half_height - (y as f32) - 0.5)).abs();
+            let (world_x, world_y) = self.world_point(x, depth);
+            let light = self.lighting.floor_light(world_x, world_y);
+            _pixels[y as usize][x as usize] = self.shading.surface(color, light, depth);
+        }
+    }
+
+    // Fill rows `rows` of one column with the sky seen at `angle`
+    fn fill_sky(
+        &self,
+        _pixels: &mut [[S::Pixel; RENDER_X as usize]; RENDER_Y as usize],
+        x: i32,
+        rows: (i32, i32),
+        sky: &Sky,
+        angle: f32
+    ) {
+        let y1 = rows.0.max(0);
+        let y2 = rows.1.min(RENDER_Y - 1);
+        for y in y1..=y2 {
+            _pixels[y as usize][x as usize] = self.shading.unlit(sky.sample_row(angle, y));
+        }
+    }
+}
+
+// --- Fill Rows `rows` of One Column with a Color ---
+fn fill_column<P: Copy>(
+    _pixels: &mut [[P; RENDER_X as usize]; RENDER_Y as usize],
+    x: i32,
+    rows: (i32, i32),
+    color: P
+) {
+    let y1 = rows.0.max(0);
+    let y2 = rows.1.min(RENDER_Y - 1);
+    for y in y1..=y2 {
+        _pixels[y as usize][x as usize] = color;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw_gl::FrameRows;
+    use crate::light::LightCache;
+    use crate::lightmap::Lightmap;
+    use crate::segment::SegmentIndex;
+
+    // Surfaces keep their base color so tests can tell them apart
+    struct Flat;
+
+    impl Shading for Flat {
+        type Pixel = [u8; 3];
+
+        fn surface(&self, color: [u8; 3], _light: [f32; 3], _dist: f32) -> [u8; 3] {
+            color
+        }
+
+        fn unlit(&self, color: [u8; 3]) -> [u8; 3] {
+            color
+        }
+    }
+
+    const COLORS: WallColors = WallColors {
+        horizontal: [200, 0, 0],
+        vertical: [0, 200, 0],
+        segment: [0, 0, 200],
+        background: [50, 50, 50],
+        floor: [100, 60, 20],
+    };
+
+    static ROOM_A: [(f32, f32); 4] = [(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)];
+    static ROOM_B: [(f32, f32); 4] = [(100.0, 0.0), (200.0, 0.0), (200.0, 100.0), (100.0, 100.0)];
+
+    // Two rooms side by side, joined by a portal at x = 100
+    fn two_rooms(b_floor: f32, b_ceiling: f32) -> [Sector; 2] {
+        [
+            Sector { floor: 0.0, ceiling: 128.0, sky: false, vertices: &ROOM_A, neighbors: &[None, Some(1), None, None] },
+            Sector { floor: b_floor, ceiling: b_ceiling, sky: false, vertices: &ROOM_B, neighbors: &[None, None, None, Some(0)] },
+        ]
+    }
+
+    // Render from the back of room A looking through the portal into room B
+    fn render(sectors: &[Sector]) -> (Box<FrameRows<[u8; 3]>>, Vec<usize>) {
+        let segments = SegmentIndex::new(&[]);
+        let lightmap = Lightmap::bake(&[], [1.0; 3], &segments);
+        let dynamic = LightCache::new([0.0; 3]);
+        let lighting = Lighting { baked: &lightmap, dynamic: &dynamic };
+        let player = player::Player::new(2.0, 46.0);
+        let mut pixels: Box<FrameRows<[u8; 3]>> = Box::new([[[0; 3]; RENDER_X as usize]; RENDER_Y as usize]);
+        let sky = Sky::gradient([0, 0, 255], [255, 255, 255]);
+        let visits = draw_sectors(&mut pixels, &player, sectors, &COLORS, &Flat, &lighting, &sky);
+        (pixels, visits)
+    }
+
+    // Colors down one column with repeats collapsed
+    fn column_runs(pixels: &FrameRows<[u8; 3]>, x: usize) -> Vec<[u8; 3]> {
+        let mut runs: Vec<[u8; 3]> = Vec::new();
+        for row in pixels.iter() {
+            if runs.last() != Some(&row[x]) {
+                runs.push(row[x]);
+            }
+        }
+        runs
+    }
+
+    #[test]
+    fn portal_window_narrows_the_clip_range() {
+        let spans = column_spans(0, 359, 100.0, 260.0, Some((120.0, 240.0)));
+        assert_eq!(spans.ceiling, (0, 99));
+        assert_eq!(spans.upper, (100, 119));
+        assert_eq!(spans.lower, (241, 260));
+        assert_eq!(spans.floor, (261, 359));
+        assert_eq!(spans.open, (120, 240));
+
+        // A taller sector behind is still seen only through this sector's opening
+        let spans = column_spans(0, 359, 100.0, 260.0, Some((80.0, 280.0)));
+        assert_eq!(spans.open, (100, 260));
+        assert!(spans.upper.0 > spans.upper.1 && spans.lower.0 > spans.lower.1);
+
+        // An earlier portal's clip range bounds everything
+        let spans = column_spans(150, 200, 100.0, 260.0, Some((120.0, 240.0)));
+        assert_eq!(spans.open, (150, 200));
+        assert!(spans.ceiling.0 > spans.ceiling.1 && spans.floor.0 > spans.floor.1);
+    }
+
+    #[test]
+    fn solid_wall_closes_the_column() {
+        let spans = column_spans(10, 300, 100.0, 260.0, None);
+        assert_eq!(spans.upper, (100, 260));
+        assert!(spans.lower.0 > spans.lower.1);
+        assert!(spans.open.0 > spans.open.1);
+    }
+
+    #[test]
+    fn closed_portal_stops_the_recursion() {
+        // Room B's floor is above room A's ceiling, so its window is shut in every column
+        let (_, visits) = render(&two_rooms(200.0, 300.0));
+        assert_eq!(visits, vec![1, 0]);
+
+        let (_, visits) = render(&two_rooms(16.0, 96.0));
+        assert_eq!(visits, vec![1, 1]);
+    }
+
+    #[test]
+    fn floor_and_ceiling_spans_between_two_sectors() {
+        // Room B is lower and raised: upper and lower walls frame its own ceiling, far wall and floor
+        let (pixels, _) = render(&two_rooms(16.0, 96.0));
+        let runs = column_runs(&pixels, (RENDER_X / 2) as usize);
+        assert_eq!(runs, vec![
+            COLORS.background, // Room A ceiling
+            COLORS.vertical, // Upper wall over the portal
+            COLORS.background, // Room B ceiling
+            COLORS.vertical, // Room B far wall
+            COLORS.floor, // Room B floor
+            COLORS.vertical, // Lower wall under the portal
+            COLORS.floor, // Room A floor
+        ]);
+    }
+}
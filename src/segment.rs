@@ -0,0 +1,240 @@
+// Import map constants
+use crate::window_gl::{ MAP_S, MAP_X, MAP_Y };
+
+// A wall segment with arbitrary endpoints in world space (not tied to the tile grid)
+#[derive(Debug, Clone, Copy)]
+pub struct WallSegment {
+    pub x1: f32, // Start point x-coordinate
+    pub y1: f32, // Start point y-coordinate
+    pub x2: f32, // End point x-coordinate
+    pub y2: f32, // End point y-coordinate
+}
+
+impl WallSegment {
+    // Constructor to create a new WallSegment
+    pub const fn new(x1: f32, y1: f32, x2: f32, y2: f32) -> WallSegment {
+        WallSegment { x1, y1, x2, y2 }
+    }
+
+    // Distance along the ray (ox, oy) + t * (dx, dy) to this segment, if it is hit
+    pub fn ray_intersection(&self, ox: f32, oy: f32, dx: f32, dy: f32) -> Option<f32> {
+        let ex = self.x2 - self.x1;
+        let ey = self.y2 - self.y1;
+        let denom = dx * ey - dy * ex;
+        if denom.abs() < 1e-6 {
+            return None; // Parallel to the segment
+        }
+        let wx = self.x1 - ox;
+        let wy = self.y1 - oy;
+        let t = (wx * ey - wy * ex) / denom; // Position along the ray
+        let u = (wx * dy - wy * dx) / denom; // Position along the segment (0..1)
+        if t > 0.0 && (0.0..=1.0).contains(&u) {
+            Some(t)
+        } else {
+            None
+        }
+    }
+
+    // Check whether this segment passes through an axis-aligned rectangle (Liang-Barsky clipping)
+    pub fn intersects_rect(&self, min_x: f32, min_y: f32, max_x: f32, max_y: f32) -> bool {
+        let dx = self.x2 - self.x1;
+        let dy = self.y2 - self.y1;
+        let mut t0: f32 = 0.0;
+        let mut t1: f32 = 1.0;
+        let edges = [
+            (-dx, self.x1 - min_x),
+            (dx, max_x - self.x1),
+            (-dy, self.y1 - min_y),
+            (dy, max_y - self.y1),
+        ];
+        for (p, q) in edges {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return false; // Parallel to and outside this edge
+                }
+            } else {
+                let r = q / p;
+                if p < 0.0 {
+                    t0 = t0.max(r);
+                } else {
+                    t1 = t1.min(r);
+                }
+            }
+        }
+        t0 <= t1
+    }
+}
+
+// Nearest segment struck by a ray
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentHit {
+    pub index: usize, // Index of the segment in the indexed slice
+    pub dist: f32, // Distance from the ray origin
+    pub x: f32, // World x-coordinate of the hit
+    pub y: f32, // World y-coordinate of the hit
+}
+
+// Spatial index bucketing segments by the map cells they pass through
+pub struct SegmentIndex {
+    segments: Vec<WallSegment>, // All indexed segments
+    cells: Vec<Vec<usize>>, // Segment indices per map cell, row-major (MAP_X * MAP_Y buckets)
+}
+
+impl SegmentIndex {
+    // Constructor to build the index from a list of segments
+    pub fn new(segments: &[WallSegment]) -> SegmentIndex {
+        let map_s = MAP_S as f32;
+        let mut cells = vec![Vec::new(); (MAP_X * MAP_Y) as usize];
+        for (index, segment) in segments.iter().enumerate() {
+            for cell_y in 0..MAP_Y {
+                for cell_x in 0..MAP_X {
+                    let min_x = (cell_x as f32) * map_s;
+                    let min_y = (cell_y as f32) * map_s;
+                    if segment.intersects_rect(min_x, min_y, min_x + map_s, min_y + map_s) {
+                        cells[(cell_y * MAP_X + cell_x) as usize].push(index);
+                    }
+                }
+            }
+        }
+        SegmentIndex {
+            segments: segments.to_vec(),
+            cells,
+        }
+    }
+
+    // Get all indexed segments
+    pub fn segments(&self) -> &[WallSegment] {
+        &self.segments
+    }
+
+    // Find the nearest segment hit by a ray at angle `ra`, no further than `max_dist`
+    pub fn cast(&self, ox: f32, oy: f32, ra: f32, max_dist: f32) -> Option<SegmentHit> {
+        let map_s = MAP_S as f32;
+        let (dy, dx) = ra.sin_cos();
+
+        // Walk the cells along the ray (Amanatides & Woo grid traversal)
+        let mut cell_x = (ox / map_s).floor() as i32;
+        let mut cell_y = (oy / map_s).floor() as i32;
+        let step_x = if dx > 0.0 { 1 } else { -1 };
+        let step_y = if dy > 0.0 { 1 } else { -1 };
+        let delta_x = if dx != 0.0 { (map_s / dx).abs() } else { f32::INFINITY };
+        let delta_y = if dy != 0.0 { (map_s / dy).abs() } else { f32::INFINITY };
+        let mut next_x = if dx > 0.0 {
+            (((cell_x + 1) as f32) * map_s - ox) / dx
+        } else if dx < 0.0 {
+            ((cell_x as f32) * map_s - ox) / dx
+        } else {
+            f32::INFINITY
+        };
+        let mut next_y = if dy > 0.0 {
+            (((cell_y + 1) as f32) * map_s - oy) / dy
+        } else if dy < 0.0 {
+            ((cell_y as f32) * map_s - oy) / dy
+        } else {
+            f32::INFINITY
+        };
+
+        let mut best: Option<SegmentHit> = None;
+        loop {
+            if !(0..MAP_X).contains(&cell_x) || !(0..MAP_Y).contains(&cell_y) {
+                break;
+            }
+
+            // Test every segment passing through this cell
+            for &index in &self.cells[(cell_y * MAP_X + cell_x) as usize] {
+                if let Some(dist) = self.segments[index].ray_intersection(ox, oy, dx, dy) {
+                    if dist <= max_dist && best.is_none_or(|hit| dist < hit.dist) {
+                        best = Some(SegmentHit {
+                            index,
+                            dist,
+                            x: ox + dx * dist,
+                            y: oy + dy * dist,
+                        });
+                    }
+                }
+            }
+
+            // A hit inside the cells walked so far can't be beaten by later cells
+            let cell_exit = next_x.min(next_y);
+            if best.is_some_and(|hit| hit.dist <= cell_exit) || cell_exit > max_dist {
+                break;
+            }
+
+            // Step to the next cell
+            if next_x < next_y {
+                cell_x += step_x;
+                next_x += delta_x;
+            } else {
+                cell_y += step_y;
+                next_y += delta_y;
+            }
+        }
+        best
+    }
+
+    // Check whether any segment passes through an axis-aligned rectangle
+    pub fn intersects_rect(&self, min_x: f32, min_y: f32, max_x: f32, max_y: f32) -> bool {
+        let map_s = MAP_S as f32;
+        let first_x = ((min_x / map_s).floor() as i32).max(0);
+        let first_y = ((min_y / map_s).floor() as i32).max(0);
+        let last_x = ((max_x / map_s).floor() as i32).min(MAP_X - 1);
+        let last_y = ((max_y / map_s).floor() as i32).min(MAP_Y - 1);
+        for cell_y in first_y..=last_y {
+            for cell_x in first_x..=last_x {
+                for &index in &self.cells[(cell_y * MAP_X + cell_x) as usize] {
+                    if self.segments[index].intersects_rect(min_x, min_y, max_x, max_y) {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ray_hits_a_crossing_segment() {
+        let segment = WallSegment::new(100.0, 50.0, 100.0, 150.0);
+        assert_eq!(segment.ray_intersection(40.0, 100.0, 1.0, 0.0), Some(60.0));
+        // Pointing away, past the end, or parallel: no hit
+        assert_eq!(segment.ray_intersection(40.0, 100.0, -1.0, 0.0), None);
+        assert_eq!(segment.ray_intersection(40.0, 200.0, 1.0, 0.0), None);
+        assert_eq!(segment.ray_intersection(40.0, 100.0, 0.0, 1.0), None);
+    }
+
+    #[test]
+    fn rect_test_clips_the_segment() {
+        let segment = WallSegment::new(10.0, 10.0, 90.0, 90.0);
+        assert!(segment.intersects_rect(40.0, 40.0, 60.0, 60.0));
+        assert!(segment.intersects_rect(0.0, 0.0, 20.0, 20.0));
+        assert!(!segment.intersects_rect(60.0, 0.0, 100.0, 30.0));
+        assert!(!segment.intersects_rect(100.0, 100.0, 120.0, 120.0));
+    }
+
+    #[test]
+    fn index_cast_finds_the_nearest_segment() {
+        let map_s = MAP_S as f32;
+        let near = WallSegment::new(2.5 * map_s, 0.5 * map_s, 2.5 * map_s, 1.5 * map_s);
+        let far = WallSegment::new(4.5 * map_s, 0.5 * map_s, 4.5 * map_s, 1.5 * map_s);
+        let index = SegmentIndex::new(&[far, near]);
+        let hit = index.cast(1.5 * map_s, map_s, 0.0, 1000.0).unwrap();
+        assert_eq!(hit.index, 1);
+        assert!((hit.dist - map_s).abs() < 1e-3);
+        assert!((hit.x - 2.5 * map_s).abs() < 1e-3 && (hit.y - map_s).abs() < 1e-3);
+        // Nothing within reach, or behind the origin
+        assert!(index.cast(1.5 * map_s, map_s, 0.0, map_s * 0.5).is_none());
+        assert!(index.cast(1.5 * map_s, map_s, std::f32::consts::PI, 1000.0).is_none());
+    }
+
+    #[test]
+    fn index_rect_query_matches_the_segments() {
+        let map_s = MAP_S as f32;
+        let index = SegmentIndex::new(&[WallSegment::new(2.5 * map_s, 0.5 * map_s, 2.5 * map_s, 1.5 * map_s)]);
+        assert!(index.intersects_rect(2.4 * map_s, 0.9 * map_s, 2.6 * map_s, 1.1 * map_s));
+        assert!(!index.intersects_rect(3.0 * map_s, 0.0, 4.0 * map_s, 2.0 * map_s));
+    }
+}
@@ -0,0 +1,205 @@
+// Import the shader types, the logging utilities and file utilities
+extern crate gl;
+use crate::log::Level;
+use crate::log_event;
+use crate::render_gl::{ Program, Shader };
+use std::ffi::CString;
+use std::fs;
+use std::ops::Deref;
+use std::path::PathBuf;
+#[cfg(test)]
+use std::path::Path;
+use std::time::SystemTime;
+
+// Directory dev builds read and watch the shaders in (the same files `include_str!` embeds)
+const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+
+// One stage of a program: its file name, kind and the copy of the source compiled into the binary
+pub struct ShaderSource {
+    name: &'static str, // File name inside `src/shaders`
+    kind: gl::types::GLenum, // VERTEX_SHADER or FRAGMENT_SHADER
+    embedded: &'static str, // Source from `include_str!`, used in release builds
+    dir: PathBuf, // Directory the file is read from and watched in
+    modified: Option<SystemTime>, // Modification time of the file when it was last read
+}
+
+impl ShaderSource {
+    // A vertex shader stage
+    pub fn vertex(name: &'static str, embedded: &'static str) -> ShaderSource {
+        ShaderSource { name, kind: gl::VERTEX_SHADER, embedded, dir: PathBuf::from(SHADER_DIR), modified: None }
+    }
+
+    // A fragment shader stage
+    pub fn fragment(name: &'static str, embedded: &'static str) -> ShaderSource {
+        ShaderSource { name, kind: gl::FRAGMENT_SHADER, embedded, dir: PathBuf::from(SHADER_DIR), modified: None }
+    }
+
+    // The same stage, read from and watched in another directory
+    #[cfg(test)]
+    fn in_dir(self, dir: &Path) -> ShaderSource {
+        ShaderSource { dir: dir.to_path_buf(), ..self }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join(self.name)
+    }
+
+    fn modified_on_disk(&self) -> Option<SystemTime> {
+        fs::metadata(self.path()).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    // Current source: the file on disk in dev builds (if readable), otherwise the embedded copy
+    fn read(&mut self) -> String {
+        if cfg!(debug_assertions) {
+            self.modified = self.modified_on_disk();
+            if let Ok(source) = fs::read_to_string(self.path()) {
+                return source;
+            }
+        }
+        self.embedded.to_string()
+    }
+
+    #[track_caller]
+    fn compile(&mut self) -> Result<Shader, String> {
+        let source = CString::new(self.read()).map_err(|_| format!("{} contains a NUL byte", self.name))?;
+        Shader::from_source(self.name, &source, self.kind)
+    }
+}
+
+// A shader program that dev builds rebuild whenever one of its files changes on disk. If the new
+// sources fail to compile or link, the last program that built keeps running and the error is kept
+// for display until the files are fixed.
+pub struct ShaderProgram {
+    sources: Vec<ShaderSource>, // Stages linked into the program
+    program: Program, // Last program that built
+    error: Option<String>, // Compile or link error of the newest sources, if they failed
+}
+
+impl ShaderProgram {
+    // Build the program from its stages (the first build has no fallback, so errors are returned)
+    #[track_caller]
+    pub fn new(mut sources: Vec<ShaderSource>) -> Result<ShaderProgram, String> {
+        let program = link(&mut sources)?;
+        Ok(ShaderProgram { sources, program, error: None })
+    }
+
+    // In dev builds, rebuild the program if any of its files changed since they were read. Returns
+    // true if a new program was swapped in.
+    #[track_caller]
+    pub fn reload_if_changed(&mut self) -> bool {
+        if !cfg!(debug_assertions) {
+            return false;
+        }
+        let changed = self.sources.iter().any(|source| source.modified_on_disk() != source.modified);
+        if !changed {
+            return false;
+        }
+        // Note every file as seen now, so a failed build is only retried after the next save
+        for source in self.sources.iter_mut() {
+            source.modified = source.modified_on_disk();
+        }
+
+        let names: Vec<&str> = self.sources.iter().map(|source| source.name).collect();
+        let names = names.join(" + ");
+        match link(&mut self.sources) {
+            Ok(program) => {
+                self.program = program;
+                self.error = None;
+                log_event!(Level::Info, "shader", "reloaded"; program = names);
+                true
+            }
+            Err(error) => {
+                log_event!(Level::Error, "shader", "keeping the previous program"; program = names, error = error);
+                self.error = Some(error);
+                false
+            }
+        }
+    }
+
+    // Error from the newest sources, while the last good program stays in use
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+impl Deref for ShaderProgram {
+    type Target = Program;
+    fn deref(&self) -> &Program {
+        &self.program
+    }
+}
+
+// Compile every stage and link them
+#[track_caller]
+fn link(sources: &mut [ShaderSource]) -> Result<Program, String> {
+    let mut shaders = Vec::with_capacity(sources.len());
+    for source in sources.iter_mut() {
+        shaders.push(source.compile()?);
+    }
+    Program::from_shaders(&shaders)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_gl;
+    use std::time::Duration;
+
+    const VERTEX: &str = "#version 410 core\nvoid main() { gl_Position = vec4(0.0, 0.0, 0.0, 1.0); }\n";
+    const FIRST: &str = "#version 410 core\nuniform vec4 first_color;\nout vec4 color;\nvoid main() { color = first_color; }\n";
+    const SECOND: &str = "#version 410 core\nuniform vec4 second_color;\nout vec4 color;\nvoid main() { color = second_color; }\n";
+    const BROKEN: &str = "#version 410 core\nout vec4 color;\nvoid main() { color = undeclared; }\n";
+
+    // Save a shader file and move its modification time forward, so the change is seen even when
+    // two saves land within the file system's timestamp resolution
+    fn save(dir: &Path, name: &str, source: &str, saves: &mut u64) {
+        let path = dir.join(name);
+        fs::write(&path, source).unwrap();
+        *saves += 1;
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(*saves)).unwrap();
+    }
+
+    // Needs an OpenGL 4.1 context; run with `cargo test -- --ignored` on a machine with a display.
+    // Reloading is a dev build feature, so the test only exists there.
+    #[cfg(debug_assertions)]
+    #[test]
+    #[ignore]
+    fn reload_picks_up_changes_and_keeps_the_last_good_program() {
+        let _context = render_gl::test_context().expect("no OpenGL 4.1 context available");
+        let dir = std::env::temp_dir().join(format!("yggdrasil-shaders-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut saves = 0;
+        save(&dir, "test.vert", VERTEX, &mut saves);
+        save(&dir, "test.frag", FIRST, &mut saves);
+
+        let mut program = ShaderProgram::new(vec![
+            ShaderSource::vertex("test.vert", VERTEX).in_dir(&dir),
+            ShaderSource::fragment("test.frag", FIRST).in_dir(&dir),
+        ]).unwrap();
+        assert!(program.uniform_location("first_color") >= 0);
+        assert!(!program.reload_if_changed(), "nothing changed on disk yet");
+
+        save(&dir, "test.frag", SECOND, &mut saves);
+        assert!(program.reload_if_changed());
+        assert!(program.uniform_location("second_color") >= 0);
+        assert_eq!(program.uniform_location("first_color"), -1);
+        assert!(program.error().is_none());
+
+        // A change that doesn't compile keeps the program from the last good sources
+        let good = program.id();
+        save(&dir, "test.frag", BROKEN, &mut saves);
+        assert!(!program.reload_if_changed());
+        assert_eq!(program.id(), good);
+        assert!(program.uniform_location("second_color") >= 0);
+        assert!(program.error().is_some());
+        assert!(!program.reload_if_changed(), "a failed build is only retried after the next save");
+
+        save(&dir, "test.frag", FIRST, &mut saves);
+        assert!(program.reload_if_changed());
+        assert!(program.uniform_location("first_color") >= 0);
+        assert!(program.error().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,25 @@
+#version 330 core
+
+layout (location = 0) in vec2 Cell; // per instance: map column and row
+layout (location = 1) in vec3 Color; // per instance
+
+uniform mat3 view; // world units to normalized device coordinates (camera.rs)
+uniform float cell_size; // world units per map cell
+
+out VS_OUTPUT {
+    vec3 Color;
+} OUT;
+
+// Corners of the two triangles of a tile, indexed by gl_VertexID
+const vec2 CORNERS[6] = vec2[6](
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0),
+    vec2(1.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)
+);
+
+void main()
+{
+    // Leave a one unit gap around each tile so the grid lines show
+    vec2 point = Cell * cell_size + 1.0 + CORNERS[gl_VertexID] * (cell_size - 2.0);
+    gl_Position = vec4((view * vec3(point, 1.0)).xy, 0.0, 1.0);
+    OUT.Color = Color;
+}
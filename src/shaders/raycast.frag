@@ -0,0 +1,289 @@
+// raycast.frag
+#version 410 core
+in vec3 v_color;
+in vec2 v_texcoord;
+out vec4 FragColor;
+
+uniform usampler2D map; // per cell: tile value (r) and open-to-sky flag (g)
+uniform sampler2D floor_light; // light per floor texel, `light_res` texels per cell edge
+uniform sampler2D wall_light; // light per wall face texel: column cell_x * light_res + t, row cell_y * 4 + face
+uniform sampler2D sky; // panoramic sky, one full turn wide
+uniform sampler2D segments; // one texel per wall segment: x1, y1, x2, y2
+uniform sampler2D portals; // three texels per portal link, see `portal_link`
+uniform int segment_count;
+uniform int portal_count; // portal links (two per portal, one for each direction)
+uniform int portal_depth; // most portals one ray may pass through (at most MAX_PORTAL_LEGS)
+uniform vec2 eye; // player eye position (world units)
+uniform float view_angle; // player direction (radians)
+uniform float fov; // horizontal field of view (radians)
+uniform vec2 resolution; // internal render resolution
+uniform float cell_size; // world units per map cell
+uniform int light_res; // light texels per cell edge
+uniform vec3 horizontal_color; // walls on horizontal grid lines (0 - 255)
+uniform vec3 vertical_color; // walls on vertical grid lines (0 - 255)
+uniform vec3 segment_color; // free-standing wall segments (0 - 255)
+uniform vec3 ceiling_color; // (0 - 255)
+uniform vec3 floor_color; // (0 - 255)
+uniform int fog_mode; // 0 = off, 1 = linear, 2 = exponential
+uniform vec3 fog_color; // (0 - 255)
+uniform float fog_start;
+uniform float fog_end;
+uniform bool indexed; // write palette indices (red = index / 255) instead of RGB
+uniform usampler2D inverse; // RGB-to-index lookup: column green * steps + blue, row red
+uniform usampler2D light_table; // per light level (row), the index each color (column) turns into
+uniform usampler2D fog_table; // per fog level (row), the index each color (column) fades to
+uniform float max_light; // light of the brightest light level
+
+const int MAX_STEPS = 64;
+const int MAX_PORTAL_LEGS = 8;
+const float PI = 3.14159265;
+
+// Amount of fog at a distance, as in fog.rs
+float fog_factor(float dist) {
+        float range = max(fog_end - fog_start, 0.0001);
+        float depth = max(dist - fog_start, 0.0);
+        if (fog_mode == 1) {
+                return min(depth / range, 1.0);
+        }
+        if (fog_mode == 2) {
+                return 1.0 - exp(-3.0 * (depth / range));
+        }
+        return 0.0;
+}
+
+// Palette index of a color (0 - 255), as palette.rs looks it up
+uint palette_index(vec3 color) {
+        int steps = textureSize(inverse, 0).y;
+        int shift = 8 - findMSB(steps);
+        ivec3 c = ivec3(color + 0.5) >> shift;
+        return texelFetch(inverse, ivec2(c.g * steps + c.b, c.r), 0).r;
+}
+
+// Output of a color shown as-is: RGB, or its palette index
+vec4 unlit(vec3 color) {
+        if (indexed) {
+                return vec4(float(palette_index(color)) / 255.0, 0.0, 0.0, 1.0);
+        }
+        return vec4(color / 255.0, 1.0);
+}
+
+// Light, then fog, rounded to whole 0 - 255 steps like the CPU renderer (or looked up in the
+// colormap tables like palette.rs in indexed mode)
+vec4 surface(vec3 color, vec3 light, float dist) {
+        float amount = fog_factor(dist);
+        if (indexed) {
+                int light_levels = textureSize(light_table, 0).y;
+                int fog_levels = textureSize(fog_table, 0).y;
+                float luminance = light.r * 0.299 + light.g * 0.587 + light.b * 0.114;
+                int level = clamp(int(floor((luminance / max_light) * float(light_levels - 1) + 0.5)), 0, light_levels - 1);
+                uint index = texelFetch(light_table, ivec2(palette_index(color), level), 0).r;
+                int fog_level = min(int(floor(amount * float(fog_levels - 1) + 0.5)), fog_levels - 1);
+                index = texelFetch(fog_table, ivec2(index, fog_level), 0).r;
+                return vec4(float(index) / 255.0, 0.0, 0.0, 1.0);
+        }
+        vec3 lit = min(floor(color * light + 0.5), 255.0);
+        if (amount > 0.0) {
+                lit = floor(lit + (fog_color - lit) * amount + 0.5);
+        }
+        return vec4(lit / 255.0, 1.0);
+}
+
+bool in_map(ivec2 cell) {
+        ivec2 size = textureSize(map, 0);
+        return all(greaterThanEqual(cell, ivec2(0))) && all(lessThan(cell, size));
+}
+
+vec3 floor_light_at(vec2 point) {
+        ivec2 texel = ivec2(floor(point / (cell_size / float(light_res))));
+        ivec2 size = textureSize(floor_light, 0);
+        if (any(lessThan(texel, ivec2(0))) || any(greaterThanEqual(texel, size))) {
+                return vec3(0.0);
+        }
+        return texelFetch(floor_light, texel, 0).rgb;
+}
+
+// Walk the grid from `origin` along `dir` cell by cell until a wall (Amanatides & Woo). Returns
+// whether a wall was hit; `cell`, `face` (North, South, East, West), `vertical_line` and `dist`
+// describe the last grid line crossed.
+bool trace_grid(vec2 origin, vec2 dir, out ivec2 cell, out int face, out bool vertical_line, out float dist) {
+        cell = ivec2(floor(origin / cell_size));
+        ivec2 cell_step = ivec2(dir.x > 0.0 ? 1 : -1, dir.y > 0.0 ? 1 : -1);
+        vec2 delta = vec2(
+                dir.x != 0.0 ? abs(cell_size / dir.x) : 1e30,
+                dir.y != 0.0 ? abs(cell_size / dir.y) : 1e30);
+        vec2 next = vec2(
+                dir.x > 0.0 ? (float(cell.x + 1) * cell_size - origin.x) / dir.x :
+                        dir.x < 0.0 ? (float(cell.x) * cell_size - origin.x) / dir.x : 1e30,
+                dir.y > 0.0 ? (float(cell.y + 1) * cell_size - origin.y) / dir.y :
+                        dir.y < 0.0 ? (float(cell.y) * cell_size - origin.y) / dir.y : 1e30);
+        dist = 0.0;
+        vertical_line = false;
+        face = 0;
+        for (int i = 0; i < MAX_STEPS; i++) {
+                if (next.x < next.y) {
+                        cell.x += cell_step.x;
+                        dist = next.x;
+                        next.x += delta.x;
+                        vertical_line = true;
+                        face = cell_step.x > 0 ? 3 : 2;
+                } else {
+                        cell.y += cell_step.y;
+                        dist = next.y;
+                        next.y += delta.y;
+                        vertical_line = false;
+                        face = cell_step.y > 0 ? 0 : 1;
+                }
+                if (!in_map(cell)) {
+                        return false;
+                }
+                if (texelFetch(map, cell, 0).r != 0u) {
+                        return true;
+                }
+        }
+        return false;
+}
+
+// Nearest wall segment along `origin` + t * `dir` no further than `max_dist`, as segment.rs finds it
+bool cast_segments(vec2 origin, vec2 dir, float max_dist, out float nearest) {
+        nearest = max_dist;
+        bool found = false;
+        for (int i = 0; i < segment_count; i++) {
+                vec4 segment = texelFetch(segments, ivec2(i, 0), 0);
+                vec2 edge = segment.zw - segment.xy;
+                float denom = dir.x * edge.y - dir.y * edge.x;
+                if (abs(denom) < 1e-6) {
+                        continue; // Parallel to the segment
+                }
+                vec2 w = segment.xy - origin;
+                float t = (w.x * edge.y - w.y * edge.x) / denom;
+                float u = (w.x * dir.y - w.y * dir.x) / denom;
+                if (t > 0.0 && u >= 0.0 && u <= 1.0 && t <= nearest) {
+                        nearest = t;
+                        found = true;
+                }
+        }
+        return found;
+}
+
+// The portal link entered through a face, or -1. Each link is three texels: entry cell and face
+// with the rotation, entry and exit face centers, and the cosine and sine of the rotation with
+// the exit face's normal.
+int portal_link(ivec2 cell, int face) {
+        for (int i = 0; i < portal_count; i++) {
+                vec4 entry = texelFetch(portals, ivec2(i * 3, 0), 0);
+                if (ivec3(entry.xyz) == ivec3(cell, face)) {
+                        return i;
+                }
+        }
+        return -1;
+}
+
+void main() {
+        // Work on the internal resolution's pixel grid so the output lines up with the CPU renderer
+        vec2 pixel = floor(v_texcoord * resolution);
+        float half_width = resolution.x / 2.0;
+        float screen_angle = ((pixel.x - half_width) / half_width) * (fov / 2.0);
+        float ray_angle = view_angle + screen_angle;
+        float proj_plane_dist = half_width / tan(fov / 2.0);
+
+        // Follow the ray leg by leg through portals, like render_gl.rs: each leg starts where the
+        // previous one entered a portal, on the far side of the linked face
+        vec2 origin = eye;
+        float angle = ray_angle;
+        float travelled = 0.0; // Distance covered by earlier legs
+        vec4 legs[MAX_PORTAL_LEGS]; // Legs after each portal: start distance, origin, angle
+        int leg_count = 0;
+        ivec2 cell;
+        int face;
+        bool vertical_line;
+        float dist;
+        bool hit = false;
+        bool segment_hit = false;
+        vec2 hit_point;
+        for (int depth = 0; depth <= portal_depth; depth++) {
+                vec2 dir = vec2(cos(angle), sin(angle));
+                hit = trace_grid(origin, dir, cell, face, vertical_line, dist);
+
+                // A wall segment in front of the grid wall blocks the ray (and any portal behind it)
+                float segment_dist;
+                if (cast_segments(origin, dir, dist, segment_dist)) {
+                        segment_hit = true;
+                        dist = segment_dist;
+                        hit_point = origin + dir * segment_dist;
+                        break;
+                }
+                hit_point = origin + dir * dist;
+
+                int link = hit && depth < portal_depth ? portal_link(cell, face) : -1;
+                if (link < 0) {
+                        break;
+                }
+
+                // Continue from the exit face, nudged off it so the exit wall isn't hit again
+                vec4 entry = texelFetch(portals, ivec2(link * 3, 0), 0);
+                vec4 centers = texelFetch(portals, ivec2(link * 3 + 1, 0), 0);
+                vec4 exit = texelFetch(portals, ivec2(link * 3 + 2, 0), 0);
+                vec2 offset = hit_point - centers.xy;
+                vec2 point = centers.zw + vec2(
+                        offset.x * exit.x - offset.y * exit.y,
+                        offset.x * exit.y + offset.y * exit.x);
+                origin = point + exit.zw * 0.01;
+                angle = mod(angle + entry.w, 2.0 * PI);
+                travelled += dist;
+                legs[leg_count] = vec4(travelled, origin, angle);
+                leg_count++;
+        }
+        float total = travelled + dist;
+
+        // Wall slice, with the same fisheye correction and rounding as the CPU renderer
+        float corrected = max(total, 0.0001) * cos(screen_angle);
+        float wall_height = min(20.0 * proj_plane_dist / corrected, resolution.y);
+        float wall_top = floor((resolution.y - wall_height) / 2.0 + 0.5);
+        float wall_bottom = floor((resolution.y + wall_height) / 2.0 + 0.5);
+
+        if (pixel.y >= wall_top && pixel.y < wall_bottom) {
+                if (segment_hit) {
+                        // Segments have no face of their own; they take the light on the floor there
+                        FragColor = surface(segment_color, floor_light_at(hit_point), corrected);
+                        return;
+                }
+                vec3 light = vec3(0.0);
+                if (hit) {
+                        float texel = cell_size / float(light_res);
+                        float along = face < 2 ?
+                                hit_point.x - float(cell.x) * cell_size :
+                                hit_point.y - float(cell.y) * cell_size;
+                        int t = clamp(int(along / texel), 0, light_res - 1);
+                        light = texelFetch(wall_light, ivec2(cell.x * light_res + t, cell.y * 4 + face), 0).rgb;
+                }
+                FragColor = surface(vertical_line ? vertical_color : horizontal_color, light, corrected);
+                return;
+        }
+
+        // Floor and ceiling: find where this row's view ray meets the plane, on the far side of
+        // any portal the ray has passed by then
+        float row_dist = 10.0 * proj_plane_dist / abs(pixel.y + 0.5 - resolution.y / 2.0);
+        float along = row_dist / cos(screen_angle);
+        vec2 point = eye + vec2(cos(ray_angle), sin(ray_angle)) * along;
+        for (int i = leg_count - 1; i >= 0; i--) {
+                if (legs[i].x <= along) {
+                        point = legs[i].yz + vec2(cos(legs[i].w), sin(legs[i].w)) * (along - legs[i].x);
+                        break;
+                }
+        }
+        if (pixel.y < wall_top) {
+                ivec2 point_cell = ivec2(floor(point / cell_size));
+                if (in_map(point_cell) && texelFetch(map, point_cell, 0).g != 0u) {
+                        // Open ceiling: sample the sky like sky.rs does
+                        ivec2 sky_size = textureSize(sky, 0);
+                        float turn = mod(ray_angle, 2.0 * PI) / (2.0 * PI);
+                        float v = clamp(pixel.y / (resolution.y / 2.0), 0.0, 1.0);
+                        ivec2 texel = min(ivec2(int(turn * float(sky_size.x)), int(v * float(sky_size.y))), sky_size - 1);
+                        FragColor = unlit(floor(texelFetch(sky, texel, 0).rgb * 255.0 + 0.5));
+                        return;
+                }
+                FragColor = surface(ceiling_color, floor_light_at(point), row_dist);
+        } else {
+                FragColor = surface(floor_color, floor_light_at(point), row_dist);
+        }
+}
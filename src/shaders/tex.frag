@@ -3,8 +3,75 @@
 in vec3 v_color;
 in vec2 v_texcoord;
 out vec4 FragColor;
-uniform sampler2D tex;
+uniform sampler2D tex; // raycast frame: RGB, or palette indices when `indexed` is set
+uniform sampler2D palette; // 256 x 1 RGB palette for indexed frames
+uniform bool indexed;
+uniform sampler3D lut; // color grading lookup table
+uniform float lut_size;
+
+// Post-processing chain (each effect can be switched off on its own)
+uniform bool crt;
+uniform float curvature;
+uniform bool aberration;
+uniform float aberration_amount;
+uniform bool grading;
+uniform float brightness;
+uniform float gamma;
+uniform bool scanlines;
+uniform float scanline_strength;
+uniform bool vignette;
+uniform float vignette_strength;
+
+// Frame color at a texture coordinate, expanding palette indices if needed
+vec3 fetch(vec2 uv) {
+        if (indexed) {
+                int index = int(texture(tex, uv).r * 255.0 + 0.5);
+                return texelFetch(palette, ivec2(index, 0), 0).rgb;
+        }
+        return texture(tex, uv).rgb;
+}
 
 void main() {
-        FragColor = texture(tex, v_texcoord);
+        vec2 uv = v_texcoord;
+
+        // CRT curvature: bulge the image outwards, black outside the tube
+        if (crt) {
+                vec2 centered = uv * 2.0 - 1.0;
+                centered *= 1.0 + curvature * centered.yx * centered.yx;
+                uv = centered * 0.5 + 0.5;
+                if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+                        FragColor = vec4(0.0, 0.0, 0.0, 1.0);
+                        return;
+                }
+        }
+
+        // Chromatic aberration: red and blue drift apart towards the edges
+        vec3 color;
+        if (aberration) {
+                vec2 offset = (uv - 0.5) * aberration_amount;
+                color = vec3(fetch(uv + offset).r, fetch(uv).g, fetch(uv - offset).b);
+        } else {
+                color = fetch(uv);
+        }
+
+        // LUT color grading (sampled at texel centers)
+        if (grading) {
+                color = texture(lut, color * ((lut_size - 1.0) / lut_size) + 0.5 / lut_size).rgb;
+        }
+
+        // Brightness and gamma
+        color = pow(clamp(color * brightness, 0.0, 1.0), vec3(1.0 / gamma));
+
+        // Scanlines: darken every other row of the raycast frame
+        if (scanlines && mod(floor(uv.y * float(textureSize(tex, 0).y)), 2.0) == 1.0) {
+                color *= 1.0 - scanline_strength;
+        }
+
+        // Vignette: darken towards the corners
+        if (vignette) {
+                float dist = length(uv - 0.5) / 0.7071;
+                color *= 1.0 - vignette_strength * dist * dist;
+        }
+
+        FragColor = vec4(color, 1.0);
 }
\ No newline at end of file
@@ -0,0 +1,11 @@
+// text.frag
+#version 410 core
+in vec3 v_color;
+in vec2 v_texcoord;
+out vec4 FragColor;
+
+uniform sampler2D atlas; // glyph coverage in the red channel
+
+void main() {
+    FragColor = vec4(v_color, texture(atlas, v_texcoord).r);
+}
@@ -0,0 +1,17 @@
+#version 410 core
+layout(location = 0) in vec3 position; // window pixels, from the top-left corner
+layout(location = 1) in vec3 color;
+layout(location = 2) in vec2 texcoord; // glyph atlas
+
+uniform vec2 window_size; // window size in pixels
+
+out vec3 v_color;
+out vec2 v_texcoord;
+
+void main() {
+    vec2 half_size = window_size / 2.0;
+    vec2 ndc = (position.xy - half_size) / half_size;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+    v_color = color;
+    v_texcoord = texcoord;
+}
@@ -3,12 +3,14 @@
 layout (location = 0) in vec3 Position;
 layout (location = 1) in vec3 Color;
 
+uniform mat3 view; // world units to normalized device coordinates (camera.rs)
+
 out VS_OUTPUT {
     vec3 Color;
 } OUT;
 
 void main()
 {
-    gl_Position = vec4(Position, 1.0);
+    gl_Position = vec4((view * vec3(Position.xy, 1.0)).xy, Position.z, 1.0);
     OUT.Color = Color;
 }
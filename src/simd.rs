@@ -0,0 +1,257 @@
+// Import the SSE2 intrinsics (always available on x86_64) and the operator traits
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::ops::{ Add, Div, Mul, Sub };
+use std::sync::atomic::{ AtomicBool, Ordering };
+
+// Number of values processed together
+pub const LANES: usize = 4;
+
+// Whether the renderers use the packed code paths (switched off to compare against the scalar ones)
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+// Check whether the packed code paths are in use
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Switch between the packed code paths and the plain scalar ones
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// Four f32 values operated on together. Each operation rounds exactly like the scalar one, so
+// packed code gives bit-identical results to the same expressions written per value.
+#[derive(Debug, Clone, Copy)]
+pub struct F32x4 {
+    #[cfg(target_arch = "x86_64")]
+    value: __m128,
+    #[cfg(not(target_arch = "x86_64"))]
+    value: [f32; LANES],
+}
+
+// Per-lane result of a comparison, used to pick between two values
+#[derive(Debug, Clone, Copy)]
+pub struct Mask4 {
+    #[cfg(target_arch = "x86_64")]
+    value: __m128,
+    #[cfg(not(target_arch = "x86_64"))]
+    value: [bool; LANES],
+}
+
+#[cfg(target_arch = "x86_64")]
+impl F32x4 {
+    // The same value in every lane
+    pub fn splat(value: f32) -> F32x4 {
+        F32x4 { value: unsafe { _mm_set1_ps(value) } }
+    }
+
+    // Load four values
+    pub fn from_array(values: [f32; LANES]) -> F32x4 {
+        F32x4 { value: unsafe { _mm_loadu_ps(values.as_ptr()) } }
+    }
+
+    // Store the four values
+    pub fn to_array(self) -> [f32; LANES] {
+        let mut values = [0.0; LANES];
+        unsafe { _mm_storeu_ps(values.as_mut_ptr(), self.value) };
+        values
+    }
+
+    // Lanes where self < other
+    pub fn lt(self, other: F32x4) -> Mask4 {
+        Mask4 { value: unsafe { _mm_cmplt_ps(self.value, other.value) } }
+    }
+
+    // Lanes where self > other
+    pub fn gt(self, other: F32x4) -> Mask4 {
+        Mask4 { value: unsafe { _mm_cmpgt_ps(self.value, other.value) } }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Mask4 {
+    // Lanes set in both masks
+    pub fn and(self, other: Mask4) -> Mask4 {
+        Mask4 { value: unsafe { _mm_and_ps(self.value, other.value) } }
+    }
+
+    // Lanes set in either mask
+    pub fn or(self, other: Mask4) -> Mask4 {
+        Mask4 { value: unsafe { _mm_or_ps(self.value, other.value) } }
+    }
+
+    // Per lane, `if_set` where the mask is set and `if_clear` elsewhere
+    pub fn select(self, if_set: F32x4, if_clear: F32x4) -> F32x4 {
+        F32x4 {
+            value: unsafe {
+                _mm_or_ps(_mm_and_ps(self.value, if_set.value), _mm_andnot_ps(self.value, if_clear.value))
+            },
+        }
+    }
+
+    // Get the lanes as booleans
+    pub fn to_array(self) -> [bool; LANES] {
+        let bits = unsafe { _mm_movemask_ps(self.value) };
+        [bits & 1 != 0, bits & 2 != 0, bits & 4 != 0, bits & 8 != 0]
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+macro_rules! packed_op {
+    ($trait:ident, $method:ident, $intrinsic:ident) => {
+        impl $trait for F32x4 {
+            type Output = F32x4;
+            fn $method(self, other: F32x4) -> F32x4 {
+                F32x4 { value: unsafe { $intrinsic(self.value, other.value) } }
+            }
+        }
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+packed_op!(Add, add, _mm_add_ps);
+#[cfg(target_arch = "x86_64")]
+packed_op!(Sub, sub, _mm_sub_ps);
+#[cfg(target_arch = "x86_64")]
+packed_op!(Mul, mul, _mm_mul_ps);
+#[cfg(target_arch = "x86_64")]
+packed_op!(Div, div, _mm_div_ps);
+
+// Portable fallback: plain arrays, one lane at a time
+#[cfg(not(target_arch = "x86_64"))]
+impl F32x4 {
+    // The same value in every lane
+    pub fn splat(value: f32) -> F32x4 {
+        F32x4 { value: [value; LANES] }
+    }
+
+    // Load four values
+    pub fn from_array(values: [f32; LANES]) -> F32x4 {
+        F32x4 { value: values }
+    }
+
+    // Store the four values
+    pub fn to_array(self) -> [f32; LANES] {
+        self.value
+    }
+
+    // Lanes where self < other
+    pub fn lt(self, other: F32x4) -> Mask4 {
+        Mask4 { value: std::array::from_fn(|i| self.value[i] < other.value[i]) }
+    }
+
+    // Lanes where self > other
+    pub fn gt(self, other: F32x4) -> Mask4 {
+        Mask4 { value: std::array::from_fn(|i| self.value[i] > other.value[i]) }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+impl Mask4 {
+    // Lanes set in both masks
+    pub fn and(self, other: Mask4) -> Mask4 {
+        Mask4 { value: std::array::from_fn(|i| self.value[i] && other.value[i]) }
+    }
+
+    // Lanes set in either mask
+    pub fn or(self, other: Mask4) -> Mask4 {
+        Mask4 { value: std::array::from_fn(|i| self.value[i] || other.value[i]) }
+    }
+
+    // Per lane, `if_set` where the mask is set and `if_clear` elsewhere
+    pub fn select(self, if_set: F32x4, if_clear: F32x4) -> F32x4 {
+        F32x4 {
+            value: std::array::from_fn(|i| {
+                if self.value[i] { if_set.value[i] } else { if_clear.value[i] }
+            }),
+        }
+    }
+
+    // Get the lanes as booleans
+    pub fn to_array(self) -> [bool; LANES] {
+        self.value
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+macro_rules! packed_op {
+    ($trait:ident, $method:ident, $op:tt) => {
+        impl $trait for F32x4 {
+            type Output = F32x4;
+            fn $method(self, other: F32x4) -> F32x4 {
+                F32x4 { value: std::array::from_fn(|i| self.value[i] $op other.value[i]) }
+            }
+        }
+    };
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+packed_op!(Add, add, +);
+#[cfg(not(target_arch = "x86_64"))]
+packed_op!(Sub, sub, -);
+#[cfg(not(target_arch = "x86_64"))]
+packed_op!(Mul, mul, *);
+#[cfg(not(target_arch = "x86_64"))]
+packed_op!(Div, div, /);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Values with awkward rounding, signs and magnitudes, one test case per lane group
+    const VALUES: [[f32; LANES]; 4] = [
+        [1.0, -2.5, 0.1, 3.0e-8],
+        [1.0e20, -7.0, 0.3333333, 64.0],
+        [-0.0, 123.456, -1.0e-3, 2.0],
+        [f32::INFINITY, 5.0, -64.0, 0.7],
+    ];
+
+    // The scalar version of a packed operation
+    type ScalarOp = fn(f32, f32) -> f32;
+
+    #[test]
+    fn arithmetic_rounds_like_scalar_code() {
+        for a in VALUES {
+            for b in VALUES {
+                let (pa, pb) = (F32x4::from_array(a), F32x4::from_array(b));
+                let ops: [(F32x4, ScalarOp); 4] = [
+                    (pa + pb, |x, y| x + y),
+                    (pa - pb, |x, y| x - y),
+                    (pa * pb, |x, y| x * y),
+                    (pa / pb, |x, y| x / y),
+                ];
+                for (packed, scalar) in ops {
+                    let lanes = packed.to_array();
+                    for lane in 0..LANES {
+                        let expected = scalar(a[lane], b[lane]);
+                        assert_eq!(lanes[lane].to_bits(), expected.to_bits(), "{:?} {:?} lane {}", a, b, lane);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn splat_fills_every_lane() {
+        assert_eq!(F32x4::splat(2.5).to_array(), [2.5; LANES]);
+        assert_eq!(F32x4::from_array([1.0, 2.0, 3.0, 4.0]).to_array(), [1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn comparisons_and_masks_match_scalar_code() {
+        let a = [1.0, f32::NAN, -3.0, 4.0];
+        let b = [2.0, 0.0, -3.0, 1.0];
+        let (pa, pb) = (F32x4::from_array(a), F32x4::from_array(b));
+        let lt = pa.lt(pb);
+        let gt = pa.gt(pb);
+        for lane in 0..LANES {
+            assert_eq!(lt.to_array()[lane], a[lane] < b[lane], "lane {}", lane);
+            assert_eq!(gt.to_array()[lane], a[lane] > b[lane], "lane {}", lane);
+        }
+        assert_eq!(lt.and(gt).to_array(), [false; LANES]);
+        assert_eq!(lt.or(gt).to_array(), [true, false, false, true]); // NaN is neither
+        let picked = lt.select(F32x4::splat(1.0), F32x4::splat(-1.0)).to_array();
+        assert_eq!(picked, [1.0, -1.0, -1.0, -1.0]);
+    }
+}
@@ -0,0 +1,183 @@
+// Import the constant for PI, file utilities and logging
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+use crate::log::Level;
+use crate::log_event;
+use crate::window_gl::RENDER_Y;
+
+// Panoramic sky texture covering the full 360 degrees around the player
+pub struct Sky {
+    width: usize, // Texels around the horizon (one full turn)
+    height: usize, // Texels from the top of the sky down to the horizon
+    pixels: Vec<[u8; 3]>, // Row-major RGB texels
+}
+
+impl Sky {
+    // Load a sky from a binary PPM (P6) image; the left and right edges must meet seamlessly
+    pub fn load(path: &Path) -> Result<Sky, String> {
+        let (width, height, pixels) = load_ppm(path)?;
+        Ok(Sky { width, height, pixels })
+    }
+
+    // Load a sky image, falling back to a plain gradient if it can't be read
+    pub fn load_or_default(path: &Path) -> Sky {
+        match Sky::load(path) {
+            Ok(sky) => sky,
+            Err(error) => {
+                log_event!(Level::Warn, "assets", "could not load sky"; path = path.display(), error = error);
+                Sky::gradient([20, 24, 60], [140, 110, 130])
+            }
+        }
+    }
+
+    // A one-texel-wide vertical gradient from `top` down to `horizon`
+    pub fn gradient(top: [u8; 3], horizon: [u8; 3]) -> Sky {
+        let height = 32;
+        let pixels = (0..height)
+            .map(|y| {
+                let t = (y as f32) / ((height - 1) as f32);
+                let mut color = top;
+                for (channel, &target) in color.iter_mut().zip(horizon.iter()) {
+                    let from = *channel as f32;
+                    *channel = (from + ((target as f32) - from) * t).round() as u8;
+                }
+                color
+            })
+            .collect();
+        Sky { width: 1, height, pixels }
+    }
+
+    // Get the width and height in texels
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    // Get the row-major RGB texels
+    pub fn pixels(&self) -> &[[u8; 3]] {
+        &self.pixels
+    }
+
+    // Sky color looking at `angle` (radians, any range), `v` from 0.0 (top of screen) to 1.0 (horizon)
+    pub fn sample(&self, angle: f32, v: f32) -> [u8; 3] {
+        let turn = angle.rem_euclid(2.0 * PI) / (2.0 * PI);
+        let x = ((turn * (self.width as f32)) as usize).min(self.width - 1);
+        let y = ((v.clamp(0.0, 1.0) * (self.height as f32)) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+
+    // Sky color for frame row `y` looking at `angle`, with the horizon in the middle of the frame
+    pub fn sample_row(&self, angle: f32, y: i32) -> [u8; 3] {
+        self.sample(angle, (y as f32) / ((RENDER_Y as f32) / 2.0))
+    }
+}
+
+// Read a binary PPM (P6) image as its width, height and row-major RGB pixels
+pub fn load_ppm(path: &Path) -> Result<(usize, usize, Vec<[u8; 3]>), String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+
+    // Header: magic, width, height and max value, separated by whitespace (with # comments)
+    let mut fields = Vec::new();
+    let mut at = 0;
+    while fields.len() < 4 {
+        while at < bytes.len() && bytes[at].is_ascii_whitespace() {
+            at += 1;
+        }
+        if at < bytes.len() && bytes[at] == b'#' {
+            while at < bytes.len() && bytes[at] != b'\n' {
+                at += 1;
+            }
+            continue;
+        }
+        let start = at;
+        while at < bytes.len() && !bytes[at].is_ascii_whitespace() {
+            at += 1;
+        }
+        if start == at {
+            return Err(String::from("truncated PPM header"));
+        }
+        fields.push(String::from_utf8_lossy(&bytes[start..at]).into_owned());
+    }
+    at += 1; // Single whitespace byte before the pixel data
+
+    if fields[0] != "P6" {
+        return Err(format!("{} is not a binary PPM (P6) image", path.display()));
+    }
+    let width: usize = fields[1].parse().map_err(|_| String::from("bad PPM width"))?;
+    let height: usize = fields[2].parse().map_err(|_| String::from("bad PPM height"))?;
+    if fields[3] != "255" {
+        return Err(String::from("only 8-bit PPM images are supported"));
+    }
+    if width == 0 || height == 0 || bytes.len() < at + width * height * 3 {
+        return Err(String::from("PPM pixel data is truncated"));
+    }
+
+    let pixels = bytes[at..at + width * height * 3]
+        .chunks_exact(3)
+        .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+        .collect();
+    Ok((width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Write `bytes` to a temporary file, load it as a PPM and remove the file again
+    fn load_bytes(name: &str, bytes: &[u8]) -> Result<(usize, usize, Vec<[u8; 3]>), String> {
+        let path = std::env::temp_dir().join(format!("yggdrasil-{}-{}.ppm", name, std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        let result = load_ppm(&path);
+        let _ = fs::remove_file(&path);
+        result
+    }
+
+    #[test]
+    fn ppm_header_with_comments() {
+        let mut bytes = b"P6\n# made by hand\n2 1\n255\n".to_vec();
+        bytes.extend_from_slice(&[255, 0, 0, 0, 0, 255]);
+        let (width, height, pixels) = load_bytes("comments", &bytes).unwrap();
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(pixels, vec![[255, 0, 0], [0, 0, 255]]);
+    }
+
+    #[test]
+    fn ppm_rejects_bad_images() {
+        assert!(load_bytes("ascii", b"P3\n1 1\n255\n0 0 0\n").is_err());
+        assert!(load_bytes("deep", b"P6\n1 1\n65535\n\0\0\0\0\0\0").is_err());
+        assert!(load_bytes("short", b"P6\n2 2\n255\n\0\0\0").is_err());
+        assert!(load_bytes("header", b"P6\n2").is_err());
+    }
+
+    #[test]
+    fn shipped_sky_loads() {
+        let sky = Sky::load(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/skies/dusk.ppm"))).unwrap();
+        let (width, height) = sky.size();
+        assert!(width > 0 && height > 0);
+        assert_eq!(sky.pixels().len(), width * height);
+    }
+
+    #[test]
+    fn sample_wraps_around_the_horizon() {
+        let sky = Sky { width: 4, height: 1, pixels: vec![[0; 3], [1; 3], [2; 3], [3; 3]] };
+        assert_eq!(sky.sample(0.0, 0.0), [0; 3]);
+        assert_eq!(sky.sample(PI, 0.5), [2; 3]);
+        assert_eq!(sky.sample(-PI / 2.0, 1.0), [3; 3]);
+        assert_eq!(sky.sample(2.0 * PI + PI / 2.0, 0.0), [1; 3]);
+    }
+
+    #[test]
+    fn gradient_runs_from_top_to_horizon() {
+        let sky = Sky::gradient([0, 0, 0], [200, 100, 50]);
+        assert_eq!(sky.sample(1.0, 0.0), [0, 0, 0]);
+        assert_eq!(sky.sample(1.0, 1.0), [200, 100, 50]);
+    }
+
+    #[test]
+    fn rows_reach_the_horizon_mid_frame() {
+        let sky = Sky::gradient([0, 0, 0], [200, 100, 50]);
+        assert_eq!(sky.sample_row(1.0, 0), [0, 0, 0]);
+        assert_eq!(sky.sample_row(1.0, RENDER_Y / 2), [200, 100, 50]);
+        assert_eq!(sky.sample_row(1.0, RENDER_Y - 1), [200, 100, 50]);
+    }
+}
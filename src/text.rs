@@ -0,0 +1,417 @@
+// Import OpenGL bindings, the buffer and texture helpers and the image loader
+extern crate gl;
+use crate::draw_gl::{ BufferArrayBinder, FrameRows, TexturedVertex, TextureManager };
+use crate::log::Level;
+use crate::log_event;
+use crate::render_gl::Program;
+use crate::sky::load_ppm;
+use crate::window_gl::{ Layout, RENDER_X, RENDER_Y };
+use std::path::Path;
+
+// Glyph atlases hold the printable ASCII characters (space to DEL) in 16 columns and 6 rows
+const FIRST_GLYPH: u8 = b' ';
+const ATLAS_COLUMNS: usize = 16;
+const ATLAS_ROWS: usize = 6;
+
+// Built-in 8x8 font (font8x8_basic, public domain): one byte per row, least significant bit on the
+// left, for the characters from space to `~`
+const BUILTIN_GLYPHS: [[u8; 8]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x18, 0x3c, 0x3c, 0x18, 0x18, 0x00, 0x18, 0x00], // '!'
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x36, 0x36, 0x7f, 0x36, 0x7f, 0x36, 0x36, 0x00], // '#'
+    [0x0c, 0x3e, 0x03, 0x1e, 0x30, 0x1f, 0x0c, 0x00], // '$'
+    [0x00, 0x63, 0x33, 0x18, 0x0c, 0x66, 0x63, 0x00], // '%'
+    [0x1c, 0x36, 0x1c, 0x6e, 0x3b, 0x33, 0x6e, 0x00], // '&'
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], // '''
+    [0x18, 0x0c, 0x06, 0x06, 0x06, 0x0c, 0x18, 0x00], // '('
+    [0x06, 0x0c, 0x18, 0x18, 0x18, 0x0c, 0x06, 0x00], // ')'
+    [0x00, 0x66, 0x3c, 0xff, 0x3c, 0x66, 0x00, 0x00], // '*'
+    [0x00, 0x0c, 0x0c, 0x3f, 0x0c, 0x0c, 0x00, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c, 0x06], // ','
+    [0x00, 0x00, 0x00, 0x3f, 0x00, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c, 0x00], // '.'
+    [0x60, 0x30, 0x18, 0x0c, 0x06, 0x03, 0x01, 0x00], // '/'
+    [0x3e, 0x63, 0x73, 0x7b, 0x6f, 0x67, 0x3e, 0x00], // '0'
+    [0x0c, 0x0e, 0x0c, 0x0c, 0x0c, 0x0c, 0x3f, 0x00], // '1'
+    [0x1e, 0x33, 0x30, 0x1c, 0x06, 0x33, 0x3f, 0x00], // '2'
+    [0x1e, 0x33, 0x30, 0x1c, 0x30, 0x33, 0x1e, 0x00], // '3'
+    [0x38, 0x3c, 0x36, 0x33, 0x7f, 0x30, 0x78, 0x00], // '4'
+    [0x3f, 0x03, 0x1f, 0x30, 0x30, 0x33, 0x1e, 0x00], // '5'
+    [0x1c, 0x06, 0x03, 0x1f, 0x33, 0x33, 0x1e, 0x00], // '6'
+    [0x3f, 0x33, 0x30, 0x18, 0x0c, 0x0c, 0x0c, 0x00], // '7'
+    [0x1e, 0x33, 0x33, 0x1e, 0x33, 0x33, 0x1e, 0x00], // '8'
+    [0x1e, 0x33, 0x33, 0x3e, 0x30, 0x18, 0x0e, 0x00], // '9'
+    [0x00, 0x0c, 0x0c, 0x00, 0x00, 0x0c, 0x0c, 0x00], // ':'
+    [0x00, 0x0c, 0x0c, 0x00, 0x00, 0x0c, 0x0c, 0x06], // ';'
+    [0x18, 0x0c, 0x06, 0x03, 0x06, 0x0c, 0x18, 0x00], // '<'
+    [0x00, 0x00, 0x3f, 0x00, 0x00, 0x3f, 0x00, 0x00], // '='
+    [0x06, 0x0c, 0x18, 0x30, 0x18, 0x0c, 0x06, 0x00], // '>'
+    [0x1e, 0x33, 0x30, 0x18, 0x0c, 0x00, 0x0c, 0x00], // '?'
+    [0x3e, 0x63, 0x7b, 0x7b, 0x7b, 0x03, 0x1e, 0x00], // '@'
+    [0x0c, 0x1e, 0x33, 0x33, 0x3f, 0x33, 0x33, 0x00], // 'A'
+    [0x3f, 0x66, 0x66, 0x3e, 0x66, 0x66, 0x3f, 0x00], // 'B'
+    [0x3c, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3c, 0x00], // 'C'
+    [0x1f, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1f, 0x00], // 'D'
+    [0x7f, 0x46, 0x16, 0x1e, 0x16, 0x46, 0x7f, 0x00], // 'E'
+    [0x7f, 0x46, 0x16, 0x1e, 0x16, 0x06, 0x0f, 0x00], // 'F'
+    [0x3c, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7c, 0x00], // 'G'
+    [0x33, 0x33, 0x33, 0x3f, 0x33, 0x33, 0x33, 0x00], // 'H'
+    [0x1e, 0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // 'I'
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1e, 0x00], // 'J'
+    [0x67, 0x66, 0x36, 0x1e, 0x36, 0x66, 0x67, 0x00], // 'K'
+    [0x0f, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7f, 0x00], // 'L'
+    [0x63, 0x77, 0x7f, 0x7f, 0x6b, 0x63, 0x63, 0x00], // 'M'
+    [0x63, 0x67, 0x6f, 0x7b, 0x73, 0x63, 0x63, 0x00], // 'N'
+    [0x1c, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1c, 0x00], // 'O'
+    [0x3f, 0x66, 0x66, 0x3e, 0x06, 0x06, 0x0f, 0x00], // 'P'
+    [0x1e, 0x33, 0x33, 0x33, 0x3b, 0x1e, 0x38, 0x00], // 'Q'
+    [0x3f, 0x66, 0x66, 0x3e, 0x36, 0x66, 0x67, 0x00], // 'R'
+    [0x1e, 0x33, 0x07, 0x0e, 0x38, 0x33, 0x1e, 0x00], // 'S'
+    [0x3f, 0x2d, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // 'T'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3f, 0x00], // 'U'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1e, 0x0c, 0x00], // 'V'
+    [0x63, 0x63, 0x63, 0x6b, 0x7f, 0x77, 0x63, 0x00], // 'W'
+    [0x63, 0x63, 0x36, 0x1c, 0x1c, 0x36, 0x63, 0x00], // 'X'
+    [0x33, 0x33, 0x33, 0x1e, 0x0c, 0x0c, 0x1e, 0x00], // 'Y'
+    [0x7f, 0x63, 0x31, 0x18, 0x4c, 0x66, 0x7f, 0x00], // 'Z'
+    [0x1e, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1e, 0x00], // '['
+    [0x03, 0x06, 0x0c, 0x18, 0x30, 0x60, 0x40, 0x00], // '\'
+    [0x1e, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1e, 0x00], // ']'
+    [0x08, 0x1c, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff], // '_'
+    [0x0c, 0x0c, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x1e, 0x30, 0x3e, 0x33, 0x6e, 0x00], // 'a'
+    [0x07, 0x06, 0x06, 0x3e, 0x66, 0x66, 0x3b, 0x00], // 'b'
+    [0x00, 0x00, 0x1e, 0x33, 0x03, 0x33, 0x1e, 0x00], // 'c'
+    [0x38, 0x30, 0x30, 0x3e, 0x33, 0x33, 0x6e, 0x00], // 'd'
+    [0x00, 0x00, 0x1e, 0x33, 0x3f, 0x03, 0x1e, 0x00], // 'e'
+    [0x1c, 0x36, 0x06, 0x0f, 0x06, 0x06, 0x0f, 0x00], // 'f'
+    [0x00, 0x00, 0x6e, 0x33, 0x33, 0x3e, 0x30, 0x1f], // 'g'
+    [0x07, 0x06, 0x36, 0x6e, 0x66, 0x66, 0x67, 0x00], // 'h'
+    [0x0c, 0x00, 0x0e, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // 'i'
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1e], // 'j'
+    [0x07, 0x06, 0x66, 0x36, 0x1e, 0x36, 0x67, 0x00], // 'k'
+    [0x0e, 0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // 'l'
+    [0x00, 0x00, 0x33, 0x7f, 0x7f, 0x6b, 0x63, 0x00], // 'm'
+    [0x00, 0x00, 0x1f, 0x33, 0x33, 0x33, 0x33, 0x00], // 'n'
+    [0x00, 0x00, 0x1e, 0x33, 0x33, 0x33, 0x1e, 0x00], // 'o'
+    [0x00, 0x00, 0x3b, 0x66, 0x66, 0x3e, 0x06, 0x0f], // 'p'
+    [0x00, 0x00, 0x6e, 0x33, 0x33, 0x3e, 0x30, 0x78], // 'q'
+    [0x00, 0x00, 0x3b, 0x6e, 0x66, 0x06, 0x0f, 0x00], // 'r'
+    [0x00, 0x00, 0x3e, 0x03, 0x1e, 0x30, 0x1f, 0x00], // 's'
+    [0x08, 0x0c, 0x3e, 0x0c, 0x0c, 0x2c, 0x18, 0x00], // 't'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6e, 0x00], // 'u'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1e, 0x0c, 0x00], // 'v'
+    [0x00, 0x00, 0x63, 0x6b, 0x7f, 0x7f, 0x36, 0x00], // 'w'
+    [0x00, 0x00, 0x63, 0x36, 0x1c, 0x36, 0x63, 0x00], // 'x'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3e, 0x30, 0x1f], // 'y'
+    [0x00, 0x00, 0x3f, 0x19, 0x0c, 0x26, 0x3f, 0x00], // 'z'
+    [0x38, 0x0c, 0x0c, 0x07, 0x0c, 0x0c, 0x38, 0x00], // '{'
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00], // '|'
+    [0x07, 0x0c, 0x0c, 0x38, 0x0c, 0x0c, 0x07, 0x00], // '}'
+    [0x6e, 0x3b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '~'
+];
+
+// Horizontal placement of a line relative to the x-coordinate it is drawn at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left, // The line starts at x
+    Center, // The line is centered on x
+    Right, // The line ends at x
+}
+
+// How a string is drawn
+#[derive(Debug, Clone, Copy)]
+pub struct TextStyle {
+    pub color: [u8; 3], // RGB (0 - 255)
+    pub scale: usize, // Pixels per glyph texel
+    pub align: Align,
+}
+
+impl TextStyle {
+    // Constructor to create a new TextStyle
+    pub fn new(color: [u8; 3], scale: usize, align: Align) -> TextStyle {
+        TextStyle { color, scale: scale.max(1), align }
+    }
+}
+
+// Monospace bitmap font: a glyph atlas of 16 x 6 equally sized cells, stored as coverage (0 - 255)
+pub struct Font {
+    glyph_width: usize, // Texels per glyph cell, across
+    glyph_height: usize, // Texels per glyph cell, down
+    coverage: Vec<u8>, // Row-major atlas texels (0 = transparent, 255 = solid)
+}
+
+impl Font {
+    // The font compiled into the binary
+    pub fn builtin() -> Font {
+        let (glyph_width, glyph_height) = (8, 8);
+        let atlas_width = glyph_width * ATLAS_COLUMNS;
+        let mut coverage = vec![0u8; atlas_width * glyph_height * ATLAS_ROWS];
+        for (index, rows) in BUILTIN_GLYPHS.iter().enumerate() {
+            let origin_x = (index % ATLAS_COLUMNS) * glyph_width;
+            let origin_y = (index / ATLAS_COLUMNS) * glyph_height;
+            for (y, &bits) in rows.iter().enumerate() {
+                for x in 0..glyph_width {
+                    if bits & (1 << x) != 0 {
+                        coverage[(origin_y + y) * atlas_width + origin_x + x] = 255;
+                    }
+                }
+            }
+        }
+        Font { glyph_width, glyph_height, coverage }
+    }
+
+    // Load a glyph atlas from a binary PPM (P6) image: white glyphs on black, 16 columns of
+    // characters from space onwards in 6 rows, every cell the same size
+    pub fn load(path: &Path) -> Result<Font, String> {
+        let (width, height, pixels) = load_ppm(path)?;
+        if width % ATLAS_COLUMNS != 0 || height % ATLAS_ROWS != 0 {
+            return Err(format!("a {}x{} atlas can't be split into 16 x 6 glyphs", width, height));
+        }
+        let coverage = pixels
+            .iter()
+            .map(|rgb| *rgb.iter().max().unwrap())
+            .collect();
+        Ok(Font { glyph_width: width / ATLAS_COLUMNS, glyph_height: height / ATLAS_ROWS, coverage })
+    }
+
+    // Load a glyph atlas, falling back to the built-in font if it can't be read
+    pub fn load_or_default(path: &Path) -> Font {
+        match Font::load(path) {
+            Ok(font) => font,
+            Err(error) => {
+                log_event!(Level::Warn, "assets", "could not load font"; path = path.display(), error = error);
+                Font::builtin()
+            }
+        }
+    }
+
+    // Get the size of one glyph in texels
+    pub fn glyph_size(&self) -> (usize, usize) {
+        (self.glyph_width, self.glyph_height)
+    }
+
+    // Get the atlas size in texels
+    pub fn atlas_size(&self) -> (usize, usize) {
+        (self.glyph_width * ATLAS_COLUMNS, self.glyph_height * ATLAS_ROWS)
+    }
+
+    // Get the row-major atlas coverage
+    pub fn coverage(&self) -> &[u8] {
+        &self.coverage
+    }
+
+    // Top-left atlas texel of a character's cell (characters outside the atlas show as '?')
+    fn glyph_origin(&self, c: char) -> (usize, usize) {
+        let last = FIRST_GLYPH as usize + ATLAS_COLUMNS * ATLAS_ROWS;
+        let code = c as usize;
+        let index = if (FIRST_GLYPH as usize..last).contains(&code) {
+            code - FIRST_GLYPH as usize
+        } else {
+            (b'?' - FIRST_GLYPH) as usize
+        };
+        ((index % ATLAS_COLUMNS) * self.glyph_width, (index / ATLAS_COLUMNS) * self.glyph_height)
+    }
+
+    // Size in pixels of a (possibly multi-line) string drawn at `scale`
+    pub fn measure(&self, text: &str, scale: usize) -> (usize, usize) {
+        let width = text.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+        let lines = text.lines().count();
+        (width * self.glyph_width * scale, lines * self.glyph_height * scale)
+    }
+
+    // Call `f` with each character and the top-left pixel of its cell, laying the lines out down
+    // from `y` and aligning each one on `x`
+    fn layout<F>(&self, text: &str, x: i32, y: i32, style: &TextStyle, mut f: F) where F: FnMut(char, i32, i32) {
+        let advance = (self.glyph_width * style.scale) as i32;
+        let line_height = (self.glyph_height * style.scale) as i32;
+        for (row, line) in text.lines().enumerate() {
+            let width = (line.chars().count() as i32) * advance;
+            let start = match style.align {
+                Align::Left => x,
+                Align::Center => x - width / 2,
+                Align::Right => x - width,
+            };
+            let top = y + (row as i32) * line_height;
+            for (column, c) in line.chars().enumerate() {
+                if c != ' ' {
+                    f(c, start + (column as i32) * advance, top);
+                }
+            }
+        }
+    }
+}
+
+// --- Draw a String into the Software Pixel Buffer ---
+// Glyph edges blend with what is already there; anything outside the buffer is clipped
+pub fn draw_text_to_pixels(
+    pixels: &mut FrameRows<[u8; 3]>,
+    font: &Font,
+    text: &str,
+    x: i32,
+    y: i32,
+    style: &TextStyle
+) {
+    let (atlas_width, _) = font.atlas_size();
+    let scale = style.scale as i32;
+    font.layout(text, x, y, style, |c, left, top| {
+        let (origin_x, origin_y) = font.glyph_origin(c);
+        for gy in 0..font.glyph_height {
+            for gx in 0..font.glyph_width {
+                let coverage = font.coverage[(origin_y + gy) * atlas_width + origin_x + gx];
+                if coverage == 0 {
+                    continue;
+                }
+                let alpha = (coverage as f32) / 255.0;
+                for py in top + (gy as i32) * scale..top + ((gy as i32) + 1) * scale {
+                    for px in left + (gx as i32) * scale..left + ((gx as i32) + 1) * scale {
+                        if !(0..RENDER_X).contains(&px) || !(0..RENDER_Y).contains(&py) {
+                            continue;
+                        }
+                        let pixel = &mut pixels[py as usize][px as usize];
+                        for (channel, &target) in pixel.iter_mut().zip(style.color.iter()) {
+                            let from = *channel as f32;
+                            *channel = (from + ((target as f32) - from) * alpha).round() as u8;
+                        }
+                    }
+                }
+            }
+        }
+    });
+}
+
+// Text drawn over everything else in window pixels: strings are queued during the frame and drawn
+// in one call with text.vert/text.frag, blending the atlas coverage over the frame
+pub struct TextRenderer {
+    font: Font,
+    atlas: TextureManager, // Coverage of the font's glyph atlas
+    buffers: BufferArrayBinder<TexturedVertex>, // VAO/VBO the queued glyph quads are streamed into
+    vertices: Vec<TexturedVertex>, // Glyph quads queued since the last draw
+}
+
+impl TextRenderer {
+    // Constructor to upload a font's atlas
+    pub fn new(font: Font) -> TextRenderer {
+        let atlas = TextureManager::new();
+        let (width, height) = font.atlas_size();
+        atlas.load_coverage_texture(width as i32, height as i32, font.coverage()).unwrap();
+        TextRenderer {
+            font,
+            atlas,
+            buffers: BufferArrayBinder::new(gl::STREAM_DRAW),
+            vertices: Vec::new(),
+        }
+    }
+
+    // Get the font strings are drawn with
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+
+    // Queue a string at window pixel (x, y) (the top of its first line)
+    pub fn queue(&mut self, text: &str, x: i32, y: i32, style: &TextStyle) {
+        let (atlas_width, atlas_height) = self.font.atlas_size();
+        let (glyph_width, glyph_height) = self.font.glyph_size();
+        let width = (glyph_width * style.scale) as f32;
+        let height = (glyph_height * style.scale) as f32;
+        let color = style.color.map(|channel| (channel as f32) / 255.0);
+        let font = &self.font;
+        let vertices = &mut self.vertices;
+        font.layout(text, x, y, style, |c, left, top| {
+            let (origin_x, origin_y) = font.glyph_origin(c);
+            let u0 = (origin_x as f32) / (atlas_width as f32);
+            let v0 = (origin_y as f32) / (atlas_height as f32);
+            let u1 = ((origin_x + glyph_width) as f32) / (atlas_width as f32);
+            let v1 = ((origin_y + glyph_height) as f32) / (atlas_height as f32);
+            let (left, top) = (left as f32, top as f32);
+            let corner = |x: f32, y: f32, texcoord: [f32; 2]| TexturedVertex { position: [x, y, 0.0], color, texcoord };
+            let top_left = corner(left, top, [u0, v0]);
+            let top_right = corner(left + width, top, [u1, v0]);
+            let bottom_left = corner(left, top + height, [u0, v1]);
+            let bottom_right = corner(left + width, top + height, [u1, v1]);
+            vertices.extend_from_slice(&[top_left, top_right, bottom_left, top_right, bottom_right, bottom_left]);
+        });
+    }
+
+    // Draw everything queued this frame and clear the queue
+    pub fn draw(&mut self, program: &Program, layout: &Layout) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        self.buffers.set_buffers(&self.vertices);
+        program.set_used();
+        program.set_vec2("window_size", [layout.width as f32, layout.height as f32]);
+        program.set_sampler("atlas", 0);
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, self.atlas.id);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+        self.buffers.draw_arrays(gl::TRIANGLES, 0..self.vertices.len());
+        unsafe {
+            gl::Disable(gl::BLEND);
+        }
+        self.vertices.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Write a font's atlas as a PPM with every texel blown up to `scale` x `scale` pixels, load it
+    // back with `Font::load` and remove the file again
+    fn load_scaled_atlas(font: &Font, scale: usize) -> Font {
+        let (width, height) = font.atlas_size();
+        let mut bytes = format!("P6\n{} {}\n255\n", width * scale, height * scale).into_bytes();
+        for y in 0..height * scale {
+            for x in 0..width * scale {
+                let value = font.coverage()[(y / scale) * width + x / scale];
+                bytes.extend_from_slice(&[value, value, value]);
+            }
+        }
+        let path = std::env::temp_dir().join(format!("yggdrasil-font-{}-{}.ppm", scale, std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        let font = Font::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        font
+    }
+
+    #[test]
+    fn atlas_loads_with_the_builtin_glyphs() {
+        let builtin = Font::builtin();
+        let loaded = load_scaled_atlas(&builtin, 1);
+        assert_eq!(loaded.glyph_size(), (8, 8));
+        assert_eq!(loaded.coverage(), builtin.coverage());
+
+        // Bigger cells are picked up from the image size
+        let large = load_scaled_atlas(&builtin, 2);
+        assert_eq!(large.glyph_size(), (16, 16));
+        assert_eq!(large.atlas_size(), (256, 96));
+        let (x, y) = large.glyph_origin('A');
+        assert_eq!(builtin.glyph_origin('A'), (x / 2, y / 2));
+    }
+
+    #[test]
+    fn atlas_that_does_not_split_into_cells_falls_back() {
+        let path = std::env::temp_dir().join(format!("yggdrasil-font-odd-{}.ppm", std::process::id()));
+        let mut bytes = b"P6\n17 6\n255\n".to_vec();
+        bytes.resize(bytes.len() + 17 * 6 * 3, 0);
+        fs::write(&path, bytes).unwrap();
+        assert!(Font::load(&path).is_err());
+        assert_eq!(Font::load_or_default(&path).coverage(), Font::builtin().coverage());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn characters_outside_the_atlas_show_as_question_marks() {
+        let font = Font::builtin();
+        assert_eq!(font.glyph_origin('é'), font.glyph_origin('?'));
+        assert_eq!(font.glyph_origin(' '), (0, 0));
+        assert_eq!(font.measure("ab\ncde", 2), (48, 32));
+    }
+}
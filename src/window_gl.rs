@@ -1,3 +1,12 @@
+use crate::fog::{ Fog, FogMode };
+use crate::light::PointLight;
+use crate::log::{ Level, LogConfig };
+use crate::postfx::PostFx;
+use crate::render_gl::WallColors;
+use crate::portal::{ Face, FaceRef, Portal };
+use crate::sector::Sector;
+use crate::segment::WallSegment;
+
 pub const WIDTH: u32 = 1280;
 pub const HEIGHT: u32 = 700;
 
@@ -11,6 +20,110 @@ pub const RAYS_COUNT: i32 = 360;
 pub const CURRENT_FOV: FieldOfView = FieldOfView::Normal;
 pub const FOV: f32 = CURRENT_FOV.to_radians();
 
+// Renderer used at startup - Tab switches between them at runtime
+pub const CURRENT_RENDER_MODE: RenderMode = RenderMode::Grid;
+
+// Whether the software renderers start in 8-bit palette mode - P toggles it at runtime
+pub const CURRENT_PALETTE_MODE: bool = false;
+
+// Worker threads for the software raycaster's columns at startup (0 = one per available CPU core;
+// `--threads` and the T key change it at runtime)
+pub const RENDER_THREADS: usize = 0;
+
+// Window area of the 2D debug view in a WIDTH x HEIGHT window (x, y, width, height in pixels, from
+// the top-left corner); the minimap, player and rays are clipped to it however the camera is zoomed
+// or panned
+pub const DEBUG_VIEW: (i32, i32, i32, i32) = (0, 0, 512, HEIGHT as i32);
+
+// Window area of the canvas quad the 3D view is drawn on in a WIDTH x HEIGHT window
+pub const CANVAS_VIEW: (i32, i32, i32, i32) = (513, 0, 1024, 512);
+
+// Where the views sit in the window at its current size. Resizing the window scales DEBUG_VIEW and
+// CANVAS_VIEW with it, so drawing, clipping and mouse picking all follow the new size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub width: i32, // Window size in pixels
+    pub height: i32,
+    pub debug_view: (i32, i32, i32, i32), // Area of the 2D debug view
+    pub canvas_view: (i32, i32, i32, i32), // Area of the canvas quad
+}
+
+impl Layout {
+    // Layout for a window of the given size in pixels
+    pub fn new(width: i32, height: i32) -> Layout {
+        let (width, height) = (width.max(1), height.max(1));
+        // Edges are scaled rather than sizes, so views that touch still touch after rounding
+        let scale_x = |x: i32| (x * width) / (WIDTH as i32);
+        let scale_y = |y: i32| (y * height) / (HEIGHT as i32);
+        let scale = |(x, y, w, h): (i32, i32, i32, i32)| {
+            (scale_x(x), scale_y(y), scale_x(x + w) - scale_x(x), scale_y(y + h) - scale_y(y))
+        };
+        Layout { width, height, debug_view: scale(DEBUG_VIEW), canvas_view: scale(CANVAS_VIEW) }
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::new(WIDTH as i32, HEIGHT as i32)
+    }
+}
+
+// Glyph atlas for on-screen text (a PPM of 16 x 6 white glyphs on black) - None uses the built-in
+// 8x8 font
+pub const FONT_PATH: Option<&str> = None;
+
+// Frames an in-game message stays on screen
+pub const MESSAGE_FRAMES: u32 = 120;
+
+// Whether the performance overlay starts visible - F3 toggles it at runtime
+pub const CURRENT_PERF_OVERLAY: bool = false;
+
+// Chrome trace written when a recording (F4) is stopped
+pub const PROFILE_TRACE_PATH: &str = "traces/profile.json";
+
+// File F5 writes every ray of the current frame to (CSV if it ends in `.csv`, otherwise JSON)
+pub const RAY_DUMP_PATH: &str = "dumps/rays.json";
+
+// Logging at startup - L toggles it at runtime, and the YGGDRASIL_LOG environment variable
+// replaces the filter (e.g. YGGDRASIL_LOG=info,raycast=trace)
+pub const LOG: LogConfig = LogConfig {
+    enabled: true,
+    filter: "info",
+    stderr: true,
+    file: None, // e.g. Some("logs/yggdrasil.log")
+    max_file_bytes: 1 << 20,
+    max_files: 3,
+};
+
+// Noisiest level compiled in; records above it are removed at compile time (debug and trace records
+// only exist in debug builds)
+pub const LOG_MAX_LEVEL: Level = if cfg!(debug_assertions) { Level::Trace } else { Level::Info };
+
+// Whether the minimap shows every ray as a line, not just the FOV cone - R toggles it at runtime
+pub const CURRENT_DEBUG_RAYS: bool = false;
+
+// Whether software frames are streamed to the canvas texture through pixel buffer objects
+pub const FRAME_UPLOAD_PBOS: bool = true;
+
+// Post-processing applied to the canvas at startup - number keys toggle the effects at runtime
+pub const POST_FX: PostFx = PostFx {
+    crt: false,
+    curvature: 0.08,
+    aberration: false,
+    aberration_amount: 0.012,
+    grading: false,
+    brightness: 1.0,
+    gamma: 1.0,
+    scanlines: false,
+    scanline_strength: 0.3,
+    vignette: false,
+    vignette_strength: 0.45,
+    software: false,
+};
+
+// Color grading LUT (.cube) used by the post-processing chain
+pub const POST_LUT_PATH: &str = "luts/warm.cube";
+
 // Field of View options
 #[derive(Debug, Clone, Copy)]
 pub enum FieldOfView {
@@ -37,17 +150,229 @@ impl FieldOfView {
     }
 }
 
+// Flat colors of the software renderers' walls, floor and ceiling
+pub const WALL_COLORS: WallColors = WallColors {
+    horizontal: [120, 120, 120], // horizontal wall color (light gray)
+    vertical: [80, 80, 80], // vertical wall color (dark gray)
+    segment: [100, 100, 100], // wall segment color (mid gray)
+    background: [30, 30, 60], // background color (dark blue)
+    floor: [45, 40, 35], // floor color (dark brown)
+};
+
+// Map tile values
+pub const TILE_EMPTY: u8 = 0; // Open floor
+pub const TILE_WALL: u8 = 1; // Solid wall
+pub const TILE_PORTAL: u8 = 2; // Solid wall with one or more portal faces (see `PORTALS`)
+
+// Renderer options
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    Grid, // Raycast the tile grid (`MAP`, `PORTALS`, `SEGMENTS`)
+    Sector, // Render the polygonal sector level (`SECTORS`)
+    Gpu, // Raycast the tile grid in a fragment shader (`MAP` walls only, see raycast.frag)
+}
+
+impl RenderMode {
+    // The next renderer in the cycle
+    pub fn next(self) -> RenderMode {
+        match self {
+            RenderMode::Grid => RenderMode::Sector,
+            RenderMode::Sector => RenderMode::Gpu,
+            RenderMode::Gpu => RenderMode::Grid,
+        }
+    }
+
+    // Name shown when switching to the renderer
+    pub fn name(self) -> &'static str {
+        match self {
+            RenderMode::Grid => "Grid raycaster",
+            RenderMode::Sector => "Sector renderer",
+            RenderMode::Gpu => "GPU raycaster",
+        }
+    }
+}
+
 pub static MAP: [[u8; MAP_X as usize]; MAP_Y as usize] = [
-    [1, 1, 1, 1, 1, 1, 1, 1],
+    [1, 1, 1, 1, 1, 2, 1, 1],
     [1, 0, 1, 0, 0, 0, 0, 1],
     [1, 0, 1, 0, 1, 1, 0, 1],
     [1, 0, 1, 0, 1, 0, 0, 1],
     [1, 0, 1, 0, 1, 0, 1, 1],
     [1, 0, 1, 0, 1, 0, 0, 1],
-    [1, 0, 0, 0, 1, 0, 0, 1],
+    [2, 0, 0, 0, 1, 0, 0, 1],
     [1, 1, 1, 1, 1, 1, 1, 1],
 ];
 
+// Cells of the grid map with no ceiling, where the sky shows instead (1 = open to the sky)
+pub static SKY_CELLS: [[u8; MAP_X as usize]; MAP_Y as usize] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 1, 1, 0],
+    [0, 0, 0, 0, 0, 1, 1, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+];
+
+// Panoramic sky image (binary PPM) for the grid map
+pub const MAP_SKY_PATH: &str = "skies/dusk.ppm";
+
+// Distance fog for the grid map
+pub const MAP_FOG: Fog = Fog {
+    mode: FogMode::Linear,
+    color: [20, 20, 35],
+    start: 64.0,
+    end: 640.0,
+};
+
+// Light every surface of the grid map gets, even in full shadow (RGB multiplier)
+pub const MAP_AMBIENT_LIGHT: [f32; 3] = [0.35, 0.35, 0.4];
+
+// Point lights placed in the grid map (steady lights are baked into the lightmap, flickering ones
+// are lit dynamically every frame)
+pub static LIGHTS: [PointLight; 2] = [
+    // Warm, flickering light in the bottom-right room
+    PointLight {
+        x: 352.0,
+        y: 352.0,
+        color: [1.0, 0.75, 0.45],
+        radius: 220.0,
+        intensity: 1.4,
+        flicker: 0.35,
+    },
+    // Cool, steady light in the top hall
+    PointLight {
+        x: 320.0,
+        y: 96.0,
+        color: [0.5, 0.6, 1.0],
+        radius: 200.0,
+        intensity: 1.2,
+        flicker: 0.0,
+    },
+];
+
+// Where the baked lightmap for the grid map is stored, relative to the executable unless absolute
+// (rebaked at startup when out of date)
+pub const LIGHTMAP_PATH: &str = "lightmaps/map.lightmap";
+
+// Light carried by the player when the lantern is on (position follows the player)
+pub const PLAYER_LANTERN: PointLight = PointLight {
+    x: 0.0,
+    y: 0.0,
+    color: [1.0, 0.9, 0.7],
+    radius: 160.0,
+    intensity: 1.0,
+    flicker: 0.1,
+};
+
+// Portal links between wall faces; each face must belong to a `TILE_PORTAL` cell
+pub static PORTALS: [Portal; 1] = [
+    Portal {
+        a: FaceRef::new(0, 6, Face::East), // left wall of the bottom-left corridor
+        b: FaceRef::new(5, 0, Face::South), // top wall of the right-hand room
+    },
+];
+
+// Free-standing wall segments in world coordinates, independent of the tile grid
+pub static SEGMENTS: [WallSegment; 2] = [
+    WallSegment::new(64.0, 96.0, 96.0, 64.0), // cuts the top-left corner of the left corridor
+    WallSegment::new(384.0, 448.0, 448.0, 384.0), // cuts the bottom-right corner of the right room
+];
+
+// Sector level for the sector renderer, following the open space of `MAP` with varying heights
+pub static SECTORS: [Sector; 8] = [
+    // 0: left corridor
+    Sector {
+        floor: 0.0,
+        ceiling: 64.0,
+        sky: false,
+        vertices: &[(64.0, 64.0), (128.0, 64.0), (128.0, 384.0), (64.0, 384.0)],
+        neighbors: &[None, None, Some(1), None],
+    },
+    // 1: bottom corridor, raised floor and low ceiling
+    Sector {
+        floor: 8.0,
+        ceiling: 56.0,
+        sky: false,
+        vertices: &[
+            (64.0, 384.0),
+            (128.0, 384.0),
+            (192.0, 384.0),
+            (256.0, 384.0),
+            (256.0, 448.0),
+            (64.0, 448.0),
+        ],
+        neighbors: &[Some(0), None, Some(2), None, None, None],
+    },
+    // 2: middle corridor (player start)
+    Sector {
+        floor: 0.0,
+        ceiling: 80.0,
+        sky: false,
+        vertices: &[(192.0, 64.0), (256.0, 64.0), (256.0, 128.0), (256.0, 384.0), (192.0, 384.0)],
+        neighbors: &[None, Some(3), None, Some(1), None],
+    },
+    // 3: top hall, one step up
+    Sector {
+        floor: 16.0,
+        ceiling: 96.0,
+        sky: false,
+        vertices: &[(256.0, 64.0), (448.0, 64.0), (448.0, 128.0), (384.0, 128.0), (256.0, 128.0)],
+        neighbors: &[None, None, Some(4), None, Some(2)],
+    },
+    // 4: right-hand passage
+    Sector {
+        floor: 24.0,
+        ceiling: 96.0,
+        sky: false,
+        vertices: &[(384.0, 128.0), (448.0, 128.0), (448.0, 192.0), (384.0, 192.0)],
+        neighbors: &[Some(3), None, Some(5), None],
+    },
+    // 5: landing
+    Sector {
+        floor: 32.0,
+        ceiling: 112.0,
+        sky: false,
+        vertices: &[
+            (320.0, 192.0),
+            (384.0, 192.0),
+            (448.0, 192.0),
+            (448.0, 256.0),
+            (384.0, 256.0),
+            (320.0, 256.0),
+        ],
+        neighbors: &[None, Some(4), None, None, Some(6), None],
+    },
+    // 6: stairs down
+    Sector {
+        floor: 24.0,
+        ceiling: 112.0,
+        sky: false,
+        vertices: &[(320.0, 256.0), (384.0, 256.0), (384.0, 320.0), (320.0, 320.0)],
+        neighbors: &[Some(5), None, Some(7), None],
+    },
+    // 7: bottom-right courtyard, open to the sky
+    Sector {
+        floor: 16.0,
+        ceiling: 128.0,
+        sky: true,
+        vertices: &[(320.0, 320.0), (384.0, 320.0), (448.0, 320.0), (448.0, 448.0), (320.0, 448.0)],
+        neighbors: &[Some(6), None, None, None, None],
+    },
+];
+
+// Panoramic sky image (binary PPM) for the sector level
+pub const SECTOR_SKY_PATH: &str = "skies/dusk.ppm";
+
+// Distance fog for the sector level
+pub const SECTOR_FOG: Fog = Fog {
+    mode: FogMode::Exponential,
+    color: [25, 20, 20],
+    start: 32.0,
+    end: 512.0,
+};
+
 pub fn single_index_map() -> [u8; MAP_S as usize] {
     let mut new_map = [0; MAP_S as usize];
 
@@ -59,3 +384,33 @@ pub fn single_index_map() -> [u8; MAP_S as usize] {
 
     return new_map;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_scales_the_views_with_the_window() {
+        let default = Layout::default();
+        assert_eq!((default.debug_view, default.canvas_view), (DEBUG_VIEW, CANVAS_VIEW));
+
+        let doubled = Layout::new(2 * (WIDTH as i32), 2 * (HEIGHT as i32));
+        assert_eq!(doubled.debug_view, (0, 0, 1024, 2 * (HEIGHT as i32)));
+        assert_eq!(doubled.canvas_view, (1026, 0, 2048, 1024));
+
+        // Odd sizes round the edges, not the sizes: the debug view still reaches the bottom edge
+        // and the canvas still starts just right of the debug view, its one-pixel gap scaled
+        for &(width, height) in &[(641, 351), (1000, 999), (1919, 1033)] {
+            let layout = Layout::new(width, height);
+            let (debug_x, debug_y, debug_width, debug_height) = layout.debug_view;
+            let (canvas_x, _, _, _) = layout.canvas_view;
+            assert_eq!((debug_x, debug_y, debug_y + debug_height), (0, 0, height));
+            let gap = canvas_x - (debug_x + debug_width);
+            assert!((0..=width / (WIDTH as i32) + 1).contains(&gap), "gap of {} at {}x{}", gap, width, height);
+        }
+
+        // A minimized window reports a zero size
+        let tiny = Layout::new(0, 0);
+        assert_eq!((tiny.width, tiny.height), (1, 1));
+    }
+}